@@ -6,7 +6,7 @@ use std::collections::HashMap;
 
 use reach::{ReachError, AUTHOR, VERSION};
 use reachapi::{arxive_search, gemini_query, google_search, ApiConfig, ApiKeys, ArxivConfig, ReachConfig, ReachConfigKeys};
-use rsearch::Rsearch;
+use rsearch::{Kg, Rsearch};
 
 use reachtui::tui::{App, run_app, setup_terminal, restore_terminal};
 
@@ -22,7 +22,13 @@ pub enum Commands {
     ArxivConfig(ArxivConfig),
 
     /// Configure RSearch config
-    Rsearch(Rsearch)
+    Rsearch(Rsearch),
+
+    /// Work with the stored knowledge graphs
+    Kg(Kg),
+
+    /// Start the Terminal User Interface
+    Tui,
 }
 
 #[derive(Parser, Debug)]
@@ -67,29 +73,6 @@ async fn main() -> Result<(), ReachError> {
     let args = Cli::parse();
     // println!("{args:?}");
 
-    // Example of adding a TUI subcommand to your CLI
-    // You would integrate this with your existing command structure
-    
-    let matches = clap::Command::new("reach")
-        // ...existing command setup...
-        .subcommand(clap::Command::new("tui").about("Start the Terminal User Interface"))
-        .get_matches();
-        
-    if matches.subcommand_matches("tui").is_some() {
-        // Start the TUI mode
-        let mut terminal = setup_terminal()?;
-        let app = App::new();
-        let res = run_app(&mut terminal, app);
-        
-        // restore terminal
-        restore_terminal(&mut terminal)?;
-        
-        if let Err(err) = res {
-            println!("Error: {:?}", err);
-        }
-        
-        return Ok(());
-    }
 
     match args.command {
         Some(Commands::ApiConfig(config)) => { // Change Api Config!
@@ -132,6 +115,25 @@ async fn main() -> Result<(), ReachError> {
         Some(Commands::Rsearch(cmd)) => {
             Ok(())
         }
+        Some(Commands::Kg(cmd)) => {
+            cmd.run()?;
+            Ok(())
+        }
+        Some(Commands::Tui) => {
+            // Start the TUI mode
+            let mut terminal = setup_terminal()?;
+            let app = App::new();
+            let res = run_app(&mut terminal, app);
+
+            // restore terminal
+            restore_terminal(&mut terminal)?;
+
+            if let Err(err) = res {
+                println!("Error: {:?}", err);
+            }
+
+            Ok(())
+        }
         None => { // Apply Proper Search
 
             let api_config: HashMap<String, String> = ApiConfig::read_config()?.into_iter().collect();
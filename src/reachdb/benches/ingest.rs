@@ -0,0 +1,75 @@
+//! Ingestion benchmark over triples shaped like the `tempdata/c.json` dumps of rsearch
+//!
+//! Run with `cargo bench -p reachdb --bench ingest`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use reachdb::{BuiltinRelationType as TypeId, Reachdb};
+use serde_json::{json, Value};
+
+/// `{ url: [{ source, relationship, target }, ..] }`, with concepts shared between the pages
+fn sample_data(urls: usize, edges_per_url: usize) -> Value {
+    let relations = ["IS-A", "RELATES-TO", "INFLUENCES"];
+    let mut data = serde_json::Map::new();
+    for u in 0..urls {
+        let edges = (0..edges_per_url)
+            .map(|e| {
+                let i = u * edges_per_url + e;
+                json!({
+                    "source": format!("concept {}", i % 97),
+                    "relationship": relations[i % relations.len()],
+                    "target": format!("concept {}", (i * 7 + 3) % 211),
+                })
+            })
+            .collect();
+        data.insert(format!("https://example.org/page/{}", u), Value::Array(edges));
+    }
+    Value::Object(data)
+}
+
+fn ingest(db: &mut Reachdb<TypeId>, data: &Value) {
+    for edges in data.as_object().unwrap().values() {
+        db.transaction(|db| {
+            for edge in edges.as_array().unwrap() {
+                db.add_edge(
+                    edge["source"].as_str().unwrap(),
+                    edge["target"].as_str().unwrap(),
+                    edge["relationship"].as_str().unwrap(),
+                )?;
+            }
+            Ok(())
+        }).unwrap();
+    }
+}
+
+fn bench_ingest(c: &mut Criterion) {
+    let data = sample_data(20, 25);
+
+    c.bench_function("ingest 500 triples", |b| {
+        b.iter_batched(
+            || tempfile::tempdir().unwrap(),
+            |dir| {
+                let mut db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+                ingest(&mut db, &data);
+                db.close().unwrap();
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+    ingest(&mut db, &data);
+    let relation_ids = db.get_all_relation_ids().unwrap();
+
+    c.bench_function("get_edge_from_rel_id over all edges", |b| {
+        b.iter(|| {
+            for rel_id in &relation_ids {
+                db.get_edge_from_rel_id(*rel_id).unwrap();
+            }
+        })
+    });
+    db.close().unwrap();
+}
+
+criterion_group!(benches, bench_ingest);
+criterion_main!(benches);
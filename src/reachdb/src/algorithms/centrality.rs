@@ -0,0 +1,205 @@
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, records::property::PropertyValue};
+use super::Direction;
+
+/// Damping factor commonly used for PageRank
+pub const DEFAULT_DAMPING: f64 = 0.85;
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// PageRank of every node along the outgoing relations
+    ///
+    /// Stops after `max_iterations` or once the scores move less than `tolerance` in total.
+    /// Nodes without outgoing relations spread their score over all nodes, so the scores sum to 1.
+    pub fn pagerank(&self, damping: f64, max_iterations: usize, tolerance: f64) -> Result<HashMap<u64, f64>, ReachdbError> {
+        let adjacency = self.adjacency(Direction::Outgoing, false)?;
+        let n = adjacency.len() as f64;
+        let mut ranks: HashMap<u64, f64> = adjacency.keys().map(|id| (*id, 1.0 / n)).collect();
+
+        for _ in 0..max_iterations {
+            let dangling: f64 = adjacency.iter()
+                .filter(|(_, out)| out.is_empty())
+                .map(|(id, _)| ranks[id])
+                .sum();
+            let base = (1.0 - damping) / n + damping * dangling / n;
+            let mut next: HashMap<u64, f64> = adjacency.keys().map(|id| (*id, base)).collect();
+            for (id, out) in &adjacency {
+                let share = damping * ranks[id] / out.len() as f64;
+                for target in out {
+                    *next.get_mut(target).expect("relations point to live nodes") += share;
+                }
+            }
+            let change: f64 = next.iter().map(|(id, rank)| (rank - ranks[id]).abs()).sum();
+            ranks = next;
+            if change < tolerance {
+                break;
+            }
+        }
+        Ok(ranks)
+    }
+
+    /// Relations of each node in `direction`, divided by the number of other nodes
+    pub fn degree_centrality(&self, direction: Direction) -> Result<HashMap<u64, f64>, ReachdbError> {
+        let adjacency = self.adjacency(direction, false)?;
+        let others = (adjacency.len().max(2) - 1) as f64;
+        Ok(adjacency.into_iter().map(|(id, neighbors)| (id, neighbors.len() as f64 / others)).collect())
+    }
+
+    /// Share of the shortest paths between other nodes that pass through each node
+    ///
+    /// Brandes' algorithm over unweighted paths, normalized by `(n - 1)(n - 2)`.
+    pub fn betweenness_centrality(&self, direction: Direction) -> Result<HashMap<u64, f64>, ReachdbError> {
+        let adjacency = self.adjacency(direction, true)?;
+        let mut scores: HashMap<u64, f64> = adjacency.keys().map(|id| (*id, 0.0)).collect();
+
+        for &source in adjacency.keys() {
+            let mut order = Vec::new();
+            let mut predecessors: HashMap<u64, Vec<u64>> = HashMap::new();
+            let mut paths: HashMap<u64, f64> = HashMap::from([(source, 1.0)]);
+            let mut distance: HashMap<u64, usize> = HashMap::from([(source, 0)]);
+            let mut queue = VecDeque::from([source]);
+            while let Some(node) = queue.pop_front() {
+                order.push(node);
+                let depth = distance[&node];
+                for &next in &adjacency[&node] {
+                    if let Entry::Vacant(entry) = distance.entry(next) {
+                        entry.insert(depth + 1);
+                        queue.push_back(next);
+                    }
+                    if distance[&next] == depth + 1 {
+                        *paths.entry(next).or_default() += paths[&node];
+                        predecessors.entry(next).or_default().push(node);
+                    }
+                }
+            }
+
+            let mut dependency: HashMap<u64, f64> = HashMap::new();
+            for &node in order.iter().rev() {
+                let through = dependency.get(&node).copied().unwrap_or_default();
+                for &previous in predecessors.get(&node).into_iter().flatten() {
+                    *dependency.entry(previous).or_default() += paths[&previous] / paths[&node] * (1.0 + through);
+                }
+                if node != source {
+                    *scores.get_mut(&node).expect("every node has a score") += through;
+                }
+            }
+        }
+
+        let n = scores.len() as f64;
+        if n > 2.0 {
+            let scale = 1.0 / ((n - 1.0) * (n - 2.0));
+            scores.values_mut().for_each(|score| *score *= scale);
+        }
+        Ok(scores)
+    }
+
+    /// Inverse of the mean distance from each node to the nodes it reaches in `direction`
+    ///
+    /// Scaled by the share of nodes reached (Wasserman and Faust), so nodes of a small component rank lower.
+    pub fn closeness_centrality(&self, direction: Direction) -> Result<HashMap<u64, f64>, ReachdbError> {
+        let adjacency = self.adjacency(direction, true)?;
+        let others = adjacency.len().saturating_sub(1) as f64;
+        let mut scores = HashMap::with_capacity(adjacency.len());
+
+        for &source in adjacency.keys() {
+            let mut distance: HashMap<u64, usize> = HashMap::from([(source, 0)]);
+            let mut queue = VecDeque::from([source]);
+            while let Some(node) = queue.pop_front() {
+                let depth = distance[&node];
+                for &next in &adjacency[&node] {
+                    if let Entry::Vacant(entry) = distance.entry(next) {
+                        entry.insert(depth + 1);
+                        queue.push_back(next);
+                    }
+                }
+            }
+            let reached = (distance.len() - 1) as f64;
+            let total: usize = distance.values().sum();
+            let score = if total == 0 { 0.0 } else { (reached / total as f64) * (reached / others) };
+            scores.insert(source, score);
+        }
+        Ok(scores)
+    }
+
+    /// Stores each score as the float node property `key`, in one transaction
+    pub fn write_scores(&mut self, key: &str, scores: &HashMap<u64, f64>) -> Result<(), ReachdbError> {
+        self.transaction(|db| {
+            for (node_id, score) in scores {
+                db.set_node_property(*node_id, key, PropertyValue::Float(*score))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The `k` nodes with the highest scores, best first
+pub fn top_scores(scores: &HashMap<u64, f64>, k: usize) -> Vec<(u64, f64)> {
+    let mut ranked: Vec<(u64, f64)> = scores.iter().map(|(id, score)| (*id, *score)).collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked.truncate(k);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::{PropertyValue, Reachdb};
+    use super::{top_scores, Direction, DEFAULT_DAMPING};
+
+    /// A star around "Transformer", with a tail behind "Attention"
+    fn transformer_star() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = temp_db(None, None);
+        for leaf in ["BERT", "GPT", "T5"] {
+            db.add_edge(leaf, "Transformer", "IS-A").unwrap();
+        }
+        db.add_edge("Attention", "Transformer", "INFLUENCES").unwrap();
+        db.add_edge("Memory Networks", "Attention", "INFLUENCES").unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn pagerank_sums_to_one_and_ranks_the_hub_first() {
+        let (_dir, db) = transformer_star();
+        let ranks = db.pagerank(DEFAULT_DAMPING, 100, 1e-9).unwrap();
+        assert!((ranks.values().sum::<f64>() - 1.0).abs() < 1e-6);
+        assert_eq!(top_scores(&ranks, 1)[0].0, node_id(&db, "Transformer"));
+        assert!(ranks[&node_id(&db, "Attention")] > ranks[&node_id(&db, "BERT")]);
+    }
+
+    #[test]
+    fn degree_counts_incoming_relations() {
+        let (_dir, db) = transformer_star();
+        let degree = db.degree_centrality(Direction::Incoming).unwrap();
+        assert_eq!(degree[&node_id(&db, "Transformer")], 4.0 / 5.0);
+        assert_eq!(degree[&node_id(&db, "BERT")], 0.0);
+    }
+
+    #[test]
+    fn betweenness_counts_the_pairs_a_node_sits_between() {
+        let (_dir, db) = transformer_star();
+        // Undirected, "Transformer" sits between every pair of the 4 other branches and
+        // the tail behind "Attention", "Attention" only between "Memory Networks" and the rest
+        let betweenness = db.betweenness_centrality(Direction::Both).unwrap();
+        assert!((betweenness[&node_id(&db, "Transformer")] - 18.0 / 20.0).abs() < 1e-9);
+        assert!((betweenness[&node_id(&db, "Attention")] - 8.0 / 20.0).abs() < 1e-9);
+        assert_eq!(betweenness[&node_id(&db, "GPT")], 0.0);
+    }
+
+    #[test]
+    fn closeness_favours_the_hub() {
+        let (_dir, db) = transformer_star();
+        let hub = node_id(&db, "Transformer");
+        let closeness = db.closeness_centrality(Direction::Both).unwrap();
+        assert!((closeness[&hub] - 5.0 / 6.0).abs() < 1e-9);
+        assert!(closeness[&hub] > closeness[&node_id(&db, "Memory Networks")]);
+    }
+
+    #[test]
+    fn scores_are_written_as_node_properties() {
+        let (_dir, mut db) = transformer_star();
+        let hub = node_id(&db, "Transformer");
+        let ranks = db.pagerank(DEFAULT_DAMPING, 100, 1e-9).unwrap();
+        db.write_scores("pagerank", &ranks).unwrap();
+        assert_eq!(db.get_node_properties(hub).unwrap().get("pagerank"), Some(&PropertyValue::Float(ranks[&hub])));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+use super::Direction;
+
+/// Resolution of the classic modularity, larger values give smaller communities
+pub const DEFAULT_RESOLUTION: f64 = 1.0;
+
+/// Renumbers the clusters by size, the largest becomes 0 and ties go to the cluster with the smallest node id
+fn renumber(membership: HashMap<u64, usize>) -> HashMap<u64, usize> {
+    let mut clusters: BTreeMap<usize, Vec<u64>> = BTreeMap::new();
+    for (node_id, cluster) in &membership {
+        clusters.entry(*cluster).or_default().push(*node_id);
+    }
+    let mut clusters: Vec<Vec<u64>> = clusters.into_values().collect();
+    clusters.iter_mut().for_each(|nodes| nodes.sort_unstable());
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    clusters.into_iter()
+        .enumerate()
+        .flat_map(|(cluster, nodes)| nodes.into_iter().map(move |node_id| (node_id, cluster)))
+        .collect()
+}
+
+/// Node ids of each cluster, indexed by cluster and sorted
+pub fn clusters(membership: &HashMap<u64, usize>) -> Vec<Vec<u64>> {
+    let count = membership.values().max().map_or(0, |max| max + 1);
+    let mut clusters = vec![Vec::new(); count];
+    for (node_id, cluster) in membership {
+        clusters[*cluster].push(*node_id);
+    }
+    clusters.iter_mut().for_each(|nodes| nodes.sort_unstable());
+    clusters
+}
+
+/// Undirected graph with weighted edges, nodes are indices
+///
+/// `edges[i][j]` is the total weight between `i` and `j`, a self-loop is stored once under `edges[i][i]`.
+struct WeightedGraph {
+    edges: Vec<HashMap<usize, f64>>,
+}
+
+impl WeightedGraph {
+    fn degree(&self, node: usize) -> f64 {
+        self.edges[node].iter().map(|(j, w)| if *j == node { 2.0 * w } else { *w }).sum()
+    }
+
+    /// Moves single nodes to the neighboring community with the best modularity gain until none moves
+    ///
+    /// Returns the community of each node, and whether any node moved.
+    fn local_moves(&self, resolution: f64, two_m: f64) -> (Vec<usize>, bool) {
+        let n = self.edges.len();
+        let degrees: Vec<f64> = (0..n).map(|i| self.degree(i)).collect();
+        let mut community: Vec<usize> = (0..n).collect();
+        let mut totals = degrees.clone();
+        let mut moved_any = false;
+
+        loop {
+            let mut moved = false;
+            for i in 0..n {
+                let current = community[i];
+                totals[current] -= degrees[i];
+
+                // Weight from i to each neighboring community, in a stable order
+                let mut links: BTreeMap<usize, f64> = BTreeMap::new();
+                for (j, w) in &self.edges[i] {
+                    if *j != i {
+                        *links.entry(community[*j]).or_default() += w;
+                    }
+                }
+                let gain = |c: usize, weight: f64| weight - resolution * totals[c] * degrees[i] / two_m;
+                let mut best = (current, gain(current, links.get(&current).copied().unwrap_or_default()));
+                for (c, weight) in &links {
+                    let g = gain(*c, *weight);
+                    if g > best.1 + 1e-12 {
+                        best = (*c, g);
+                    }
+                }
+
+                community[i] = best.0;
+                totals[best.0] += degrees[i];
+                if best.0 != current {
+                    moved = true;
+                    moved_any = true;
+                }
+            }
+            if !moved {
+                return (community, moved_any);
+            }
+        }
+    }
+
+    /// Graph with one node per community, `community` must be numbered from 0 without gaps
+    fn aggregate(&self, community: &[usize], count: usize) -> WeightedGraph {
+        let mut edges = vec![HashMap::new(); count];
+        for (i, neighbors) in self.edges.iter().enumerate() {
+            for (j, w) in neighbors {
+                let (a, b) = (community[i], community[*j]);
+                if *j == i {
+                    *edges[a].entry(a).or_default() += w;
+                } else if i < *j {
+                    if a == b {
+                        *edges[a].entry(a).or_default() += w;
+                    } else {
+                        *edges[a].entry(b).or_default() += w;
+                        *edges[b].entry(a).or_default() += w;
+                    }
+                }
+            }
+        }
+        WeightedGraph { edges }
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Nodes linked by relations in either direction share a component
+    pub fn weakly_connected_components(&self) -> Result<HashMap<u64, usize>, ReachdbError> {
+        let adjacency = self.adjacency(Direction::Both, true)?;
+        let mut membership = HashMap::with_capacity(adjacency.len());
+        let mut ids: Vec<u64> = adjacency.keys().copied().collect();
+        ids.sort_unstable();
+
+        for start in ids {
+            if membership.contains_key(&start) {
+                continue;
+            }
+            let component = start as usize;
+            membership.insert(start, component);
+            let mut queue = VecDeque::from([start]);
+            while let Some(node) = queue.pop_front() {
+                for next in &adjacency[&node] {
+                    if let Entry::Vacant(entry) = membership.entry(*next) {
+                        entry.insert(component);
+                        queue.push_back(*next);
+                    }
+                }
+            }
+        }
+        Ok(renumber(membership))
+    }
+
+    /// Nodes that reach each other along outgoing relations share a component
+    ///
+    /// Tarjan's algorithm, with an explicit stack so long chains do not overflow the call stack.
+    pub fn strongly_connected_components(&self) -> Result<HashMap<u64, usize>, ReachdbError> {
+        let adjacency = self.adjacency(Direction::Outgoing, true)?;
+        let mut ids: Vec<u64> = adjacency.keys().copied().collect();
+        ids.sort_unstable();
+
+        let mut index: HashMap<u64, usize> = HashMap::new();
+        let mut low: HashMap<u64, usize> = HashMap::new();
+        let mut on_stack: HashSet<u64> = HashSet::new();
+        let mut stack: Vec<u64> = Vec::new();
+        let mut membership: HashMap<u64, usize> = HashMap::new();
+        let mut next_index = 0;
+
+        for root in ids {
+            if index.contains_key(&root) {
+                continue;
+            }
+            // Each frame is a node and the position of the next neighbor to look at
+            let mut frames = vec![(root, 0)];
+            index.insert(root, next_index);
+            low.insert(root, next_index);
+            next_index += 1;
+            stack.push(root);
+            on_stack.insert(root);
+
+            while let Some(&mut (node, ref mut position)) = frames.last_mut() {
+                if let Some(&next) = adjacency[&node].get(*position) {
+                    *position += 1;
+                    match index.entry(next) {
+                        Entry::Vacant(entry) => {
+                            entry.insert(next_index);
+                            low.insert(next, next_index);
+                            next_index += 1;
+                            stack.push(next);
+                            on_stack.insert(next);
+                            frames.push((next, 0));
+                        }
+                        Entry::Occupied(entry) if on_stack.contains(&next) => {
+                            let lowest = low[&node].min(*entry.get());
+                            low.insert(node, lowest);
+                        }
+                        Entry::Occupied(_) => {}
+                    }
+                    continue;
+                }
+
+                frames.pop();
+                if let Some(&(parent, _)) = frames.last() {
+                    let lowest = low[&parent].min(low[&node]);
+                    low.insert(parent, lowest);
+                }
+                if low[&node] == index[&node] {
+                    let component = node as usize;
+                    while let Some(member) = stack.pop() {
+                        on_stack.remove(&member);
+                        membership.insert(member, component);
+                        if member == node {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(renumber(membership))
+    }
+
+    /// Undirected graph of the live nodes, each relation weighing 1, with the node id of each index
+    fn weighted_graph(&self) -> Result<(Vec<u64>, WeightedGraph), ReachdbError> {
+        let mut ids = self.get_all_node_ids()?;
+        ids.sort_unstable();
+        let position: HashMap<u64, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+        let mut edges = vec![HashMap::new(); ids.len()];
+        for rel_id in self.get_all_relation_ids()? {
+            let rel = self.get_relation(rel_id)?;
+            let (a, b) = (position[&rel.source_id], position[&rel.target_id]);
+            *edges[a].entry(b).or_default() += 1.0;
+            if a != b {
+                *edges[b].entry(a).or_default() += 1.0;
+            }
+        }
+        Ok((ids, WeightedGraph { edges }))
+    }
+
+    /// Communities found by the Louvain method, ignoring the direction of relations
+    ///
+    /// Nodes are moved between communities while the modularity grows, then each community
+    /// becomes a single node and the moves start over, until nothing moves anymore.
+    pub fn louvain(&self, resolution: f64) -> Result<HashMap<u64, usize>, ReachdbError> {
+        let (ids, mut graph) = self.weighted_graph()?;
+        let two_m: f64 = (0..graph.edges.len()).map(|i| graph.degree(i)).sum();
+        // Community of every original node, updated after each level
+        let mut membership: Vec<usize> = (0..ids.len()).collect();
+
+        if two_m > 0.0 {
+            loop {
+                let (community, moved) = graph.local_moves(resolution, two_m);
+                if !moved {
+                    break;
+                }
+                // Number the communities from 0 so they can become the nodes of the next level
+                let mut numbering: BTreeMap<usize, usize> = BTreeMap::new();
+                for c in &community {
+                    let next = numbering.len();
+                    numbering.entry(*c).or_insert(next);
+                }
+                let community: Vec<usize> = community.iter().map(|c| numbering[c]).collect();
+                membership.iter_mut().for_each(|m| *m = community[*m]);
+                graph = graph.aggregate(&community, numbering.len());
+            }
+        }
+        Ok(renumber(ids.into_iter().zip(membership).collect()))
+    }
+
+    /// Modularity of a clustering, ignoring the direction of relations
+    ///
+    /// Nodes missing from `membership` count as clusters of their own.
+    pub fn modularity(&self, membership: &HashMap<u64, usize>, resolution: f64) -> Result<f64, ReachdbError> {
+        let (ids, graph) = self.weighted_graph()?;
+        let two_m: f64 = (0..graph.edges.len()).map(|i| graph.degree(i)).sum();
+        if two_m == 0.0 {
+            return Ok(0.0);
+        }
+        // Unclustered nodes get labels past every cluster
+        let offset = membership.values().max().map_or(0, |max| max + 1);
+        let cluster: Vec<usize> = ids.iter()
+            .enumerate()
+            .map(|(i, id)| membership.get(id).copied().unwrap_or(offset + i))
+            .collect();
+
+        let mut internal: HashMap<usize, f64> = HashMap::new();
+        let mut totals: HashMap<usize, f64> = HashMap::new();
+        for (i, neighbors) in graph.edges.iter().enumerate() {
+            *totals.entry(cluster[i]).or_default() += graph.degree(i);
+            for (j, w) in neighbors {
+                if cluster[i] == cluster[*j] && i <= *j {
+                    *internal.entry(cluster[i]).or_default() += w;
+                }
+            }
+        }
+        let m = two_m / 2.0;
+        Ok(totals.iter()
+            .map(|(c, total)| internal.get(c).copied().unwrap_or_default() / m - resolution * (total / two_m).powi(2))
+            .sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::Reachdb;
+    use super::{clusters, DEFAULT_RESOLUTION};
+
+    /// Two triangles joined by one relation, and a separate pair
+    fn joined_triangles() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = temp_db(None, None);
+        for (a, b) in [("A1", "A2"), ("A2", "A3"), ("A3", "A1"), ("B1", "B2"), ("B2", "B3"), ("B3", "B1"), ("A1", "B1")] {
+            db.add_edge(a, b, "RELATES-TO").unwrap();
+        }
+        db.add_edge("X", "Y", "IS-A").unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn weak_components_ignore_directions() {
+        let (_dir, db) = joined_triangles();
+        let weak = db.weakly_connected_components().unwrap();
+        assert_eq!(clusters(&weak).iter().map(Vec::len).collect::<Vec<_>>(), vec![6, 2]);
+        assert_eq!(weak[&node_id(&db, "A1")], 0);
+        assert_eq!(weak[&node_id(&db, "X")], 1);
+    }
+
+    #[test]
+    fn strong_components_follow_cycles() {
+        let (_dir, db) = joined_triangles();
+        let strong = db.strongly_connected_components().unwrap();
+        assert_eq!(clusters(&strong).iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 3, 1, 1]);
+        assert_eq!(strong[&node_id(&db, "A1")], strong[&node_id(&db, "A3")]);
+        assert_ne!(strong[&node_id(&db, "A1")], strong[&node_id(&db, "B1")]);
+        assert_ne!(strong[&node_id(&db, "X")], strong[&node_id(&db, "Y")]);
+    }
+
+    #[test]
+    fn louvain_splits_the_joined_triangles() {
+        let (_dir, db) = joined_triangles();
+        let communities = db.louvain(DEFAULT_RESOLUTION).unwrap();
+        assert_eq!(clusters(&communities).len(), 3);
+        assert_eq!(communities[&node_id(&db, "A1")], communities[&node_id(&db, "A2")]);
+        assert_eq!(communities[&node_id(&db, "B1")], communities[&node_id(&db, "B3")]);
+        assert_ne!(communities[&node_id(&db, "A1")], communities[&node_id(&db, "B1")]);
+
+        let weak = db.weakly_connected_components().unwrap();
+        assert!(db.modularity(&communities, DEFAULT_RESOLUTION).unwrap() > db.modularity(&weak, DEFAULT_RESOLUTION).unwrap());
+    }
+}
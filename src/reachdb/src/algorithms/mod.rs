@@ -1,7 +1,31 @@
+mod traversal;
+mod paths;
+mod centrality;
+mod community;
+mod walk;
+
+pub use traversal::{Bfs, Dfs, Visit};
+pub use centrality::{top_scores, DEFAULT_DAMPING};
+pub use community::{clusters, DEFAULT_RESOLUTION};
+pub use walk::{EdgeWeight, WalkConfig};
+
+use std::collections::HashMap;
+
 use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
 use log::{debug, info};
 use rand::Rng;
 
+/// Which relations of a node are followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// From source to target
+    #[default]
+    Outgoing,
+    /// From target back to source
+    Incoming,
+    /// Either way
+    Both,
+}
 
 impl<E: UserDefinedRelationType + std::fmt::Debug> Reachdb<E> {
     /// Returns the relation ids
@@ -23,4 +47,61 @@ impl<E: UserDefinedRelationType + std::fmt::Debug> Reachdb<E> {
         }
         Ok(path)
     }
-}
\ No newline at end of file
+
+    /// Relations of the node followed in `direction`, with the node each one leads to
+    ///
+    /// A self-loop leads back to the node itself.
+    pub fn neighbors(&self, node_id: u64, direction: Direction) -> Result<Vec<(u64, u64)>, ReachdbError> {
+        let mut neighbors = Vec::new();
+        for rel_id in self.get_all_node_relations(node_id)? {
+            let rel = self.get_relation(rel_id)?;
+            let outgoing = rel.source_id == node_id;
+            let incoming = rel.target_id == node_id;
+            let next = match direction {
+                Direction::Outgoing if outgoing => rel.target_id,
+                Direction::Incoming if incoming => rel.source_id,
+                Direction::Both if outgoing => rel.target_id,
+                Direction::Both => rel.source_id,
+                _ => continue,
+            };
+            neighbors.push((rel_id, next));
+        }
+        Ok(neighbors)
+    }
+
+    /// Neighbors of every live node in `direction`, read once from the chains
+    ///
+    /// Parallel relations give repeated neighbors, `distinct` keeps one of each and drops self-loops.
+    pub(crate) fn adjacency(&self, direction: Direction, distinct: bool) -> Result<HashMap<u64, Vec<u64>>, ReachdbError> {
+        let mut adjacency = HashMap::new();
+        for node_id in self.get_all_node_ids()? {
+            let mut neighbors: Vec<u64> = self.neighbors(node_id, direction)?.into_iter().map(|(_, next)| next).collect();
+            if distinct {
+                neighbors.retain(|next| *next != node_id);
+                neighbors.sort_unstable();
+                neighbors.dedup();
+            }
+            adjacency.insert(node_id, neighbors);
+        }
+        Ok(adjacency)
+    }
+
+    /// Renders a relation path that starts at `start_node_id`, like `A -[IS-A]-> B <-[INFLUENCES]- C`
+    pub fn path_to_string(&self, start_node_id: u64, path: &[u64]) -> Result<String, ReachdbError> {
+        let mut current = start_node_id;
+        let mut out = self.get_node_name(current)?;
+        for rel_id in path {
+            let rel = self.get_relation(*rel_id)?;
+            let relation = self.get_relation_type_str(rel.type_id);
+            if rel.source_id == current {
+                current = rel.target_id;
+                out.push_str(&format!(" -[{}]-> ", relation));
+            } else {
+                current = rel.source_id;
+                out.push_str(&format!(" <-[{}]- ", relation));
+            }
+            out.push_str(&self.get_node_name(current)?);
+        }
+        Ok(out)
+    }
+}
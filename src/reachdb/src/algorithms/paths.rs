@@ -0,0 +1,130 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+use super::Direction;
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    fn require_node_id(&self, node: &str) -> Result<u64, ReachdbError> {
+        self.get_node_id(node)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node \"{}\" not found", node)))
+    }
+
+    /// Fewest relations leading from `source` to `target`, as relation ids like `random_walk`
+    ///
+    /// Returns `None` when `target` cannot be reached, and an empty path when both are the same node.
+    pub fn shortest_path(&self, source: &str, target: &str, direction: Direction) -> Result<Option<Vec<u64>>, ReachdbError> {
+        let source_id = self.require_node_id(source)?;
+        let target_id = self.require_node_id(target)?;
+        if source_id == target_id {
+            return Ok(Some(Vec::new()));
+        }
+
+        // Relation and node each reached node came from
+        let mut parent: HashMap<u64, (u64, u64)> = HashMap::new();
+        let mut queue = VecDeque::from([source_id]);
+        while let Some(node) = queue.pop_front() {
+            for (rel_id, next) in self.neighbors(node, direction)? {
+                if next == source_id || parent.contains_key(&next) {
+                    continue;
+                }
+                parent.insert(next, (rel_id, node));
+                if next == target_id {
+                    let mut path = vec![rel_id];
+                    let mut current = node;
+                    while current != source_id {
+                        let (rel_id, previous) = parent[&current];
+                        path.push(rel_id);
+                        current = previous;
+                    }
+                    path.reverse();
+                    return Ok(Some(path));
+                }
+                queue.push_back(next);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Every path from `source` to `target` with the fewest relations
+    ///
+    /// Parallel relations between two nodes give separate paths. Empty when `target` cannot be reached.
+    pub fn all_shortest_paths(&self, source: &str, target: &str, direction: Direction) -> Result<Vec<Vec<u64>>, ReachdbError> {
+        let source_id = self.require_node_id(source)?;
+        let target_id = self.require_node_id(target)?;
+        if source_id == target_id {
+            return Ok(vec![Vec::new()]);
+        }
+
+        // Distance of each reached node and every (relation, node) it is reached from at that distance
+        let mut distance: HashMap<u64, usize> = HashMap::from([(source_id, 0)]);
+        let mut parents: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+        let mut queue = VecDeque::from([source_id]);
+        while let Some(node) = queue.pop_front() {
+            let depth = distance[&node];
+            // The whole level of the target is explored, nothing deeper is needed
+            if distance.get(&target_id).is_some_and(|d| depth >= *d) {
+                break;
+            }
+            for (rel_id, next) in self.neighbors(node, direction)? {
+                match distance.get(&next) {
+                    Some(d) if *d == depth + 1 => parents.entry(next).or_default().push((rel_id, node)),
+                    Some(_) => {}
+                    None => {
+                        distance.insert(next, depth + 1);
+                        parents.entry(next).or_default().push((rel_id, node));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        if !parents.contains_key(&target_id) {
+            return Ok(Vec::new());
+        }
+
+        // Unwind the parents from the target, building the paths back to front
+        let mut paths = Vec::new();
+        let mut stack = vec![(target_id, Vec::new())];
+        while let Some((node, suffix)) = stack.pop() {
+            if node == source_id {
+                let mut path: Vec<u64> = suffix;
+                path.reverse();
+                paths.push(path);
+                continue;
+            }
+            for (rel_id, previous) in &parents[&node] {
+                let mut longer = suffix.clone();
+                longer.push(*rel_id);
+                stack.push((*previous, longer));
+            }
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, two_routes_to_generative_model};
+    use super::Direction;
+
+    #[test]
+    fn shortest_path_follows_the_direction() {
+        let (_dir, db, relations) = two_routes_to_generative_model();
+        let path = db.shortest_path("Diffusion", "Score Matching", Direction::Both).unwrap().unwrap();
+        assert_eq!(path, vec![relations[0]]);
+        assert_eq!(
+            db.path_to_string(node_id(&db, "Diffusion"), &path).unwrap(),
+            "Diffusion <-[INFLUENCES]- Score Matching"
+        );
+        assert_eq!(db.shortest_path("Diffusion", "Score Matching", Direction::Outgoing).unwrap(), None);
+        assert_eq!(db.shortest_path("GAN", "GAN", Direction::Both).unwrap(), Some(vec![]));
+        assert!(db.shortest_path("GAN", "VAE", Direction::Both).is_err());
+    }
+
+    #[test]
+    fn all_shortest_paths_returns_every_route() {
+        let (_dir, db, relations) = two_routes_to_generative_model();
+        let mut paths = db.all_shortest_paths("Score Matching", "Generative Model", Direction::Outgoing).unwrap();
+        paths.sort();
+        assert_eq!(paths, vec![vec![relations[0], relations[1]], vec![relations[2], relations[3]]]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+use super::Direction;
+
+/// A node reached by a traversal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visit {
+    pub node_id: u64,
+    /// Number of relations from the start node
+    pub depth: usize,
+    /// Relation the node was reached through, `None` for the start node
+    pub relation_id: Option<u64>,
+}
+
+/// Breadth-first traversal, created by `Reachdb::bfs`
+///
+/// Every node is visited once, at its smallest depth.
+pub struct Bfs<'a, E: UserDefinedRelationType> {
+    db: &'a Reachdb<E>,
+    direction: Direction,
+    max_depth: Option<usize>,
+    queue: VecDeque<Visit>,
+    visited: HashSet<u64>,
+}
+
+impl<E: UserDefinedRelationType> Iterator for Bfs<'_, E> {
+    type Item = Result<Visit, ReachdbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let visit = self.queue.pop_front()?;
+        if self.max_depth.is_none_or(|max| visit.depth < max) {
+            let neighbors = match self.db.neighbors(visit.node_id, self.direction) {
+                Ok(neighbors) => neighbors,
+                Err(e) => {
+                    self.queue.clear();
+                    return Some(Err(e));
+                }
+            };
+            for (rel_id, next) in neighbors {
+                if self.visited.insert(next) {
+                    self.queue.push_back(Visit { node_id: next, depth: visit.depth + 1, relation_id: Some(rel_id) });
+                }
+            }
+        }
+        Some(Ok(visit))
+    }
+}
+
+/// Depth-first traversal in pre-order, created by `Reachdb::dfs`
+///
+/// Every node is visited once, along the first path that reaches it. With a `max_depth`, a node
+/// first reached deep is explored again when a shallower path reaches it, so no node within
+/// `max_depth` is missed.
+pub struct Dfs<'a, E: UserDefinedRelationType> {
+    db: &'a Reachdb<E>,
+    direction: Direction,
+    max_depth: Option<usize>,
+    stack: Vec<Visit>,
+    /// Smallest depth each node was explored at
+    depths: HashMap<u64, usize>,
+}
+
+impl<E: UserDefinedRelationType> Iterator for Dfs<'_, E> {
+    type Item = Result<Visit, ReachdbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Nodes are marked when popped, a node pushed twice is visited through the entry popped first
+            let visit = self.stack.pop()?;
+            let first = match self.depths.get(&visit.node_id) {
+                None => true,
+                Some(&depth) if self.reexplores(visit.depth, depth) => false,
+                Some(_) => continue,
+            };
+            self.depths.insert(visit.node_id, visit.depth);
+
+            if self.max_depth.is_none_or(|max| visit.depth < max) {
+                let neighbors = match self.db.neighbors(visit.node_id, self.direction) {
+                    Ok(neighbors) => neighbors,
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                };
+                // Pushed in reverse so the newest relation is explored first, as the chain lists it
+                for (rel_id, next) in neighbors.into_iter().rev() {
+                    if self.depths.get(&next).is_none_or(|&depth| self.reexplores(visit.depth + 1, depth)) {
+                        self.stack.push(Visit { node_id: next, depth: visit.depth + 1, relation_id: Some(rel_id) });
+                    }
+                }
+            }
+            // A node explored again was already visited
+            if first {
+                return Some(Ok(visit));
+            }
+        }
+    }
+}
+
+impl<E: UserDefinedRelationType> Dfs<'_, E> {
+    /// Whether a node explored at `explored` is explored again when reached at `depth`
+    ///
+    /// Without a limit every node reachable from it is visited anyway.
+    fn reexplores(&self, depth: usize, explored: usize) -> bool {
+        self.max_depth.is_some() && depth < explored
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Breadth-first traversal from `start_node_id`, up to `max_depth` relations away when given
+    pub fn bfs(&self, start_node_id: u64, direction: Direction, max_depth: Option<usize>) -> Bfs<'_, E> {
+        Bfs {
+            db: self,
+            direction,
+            max_depth,
+            queue: VecDeque::from([Visit { node_id: start_node_id, depth: 0, relation_id: None }]),
+            visited: HashSet::from([start_node_id]),
+        }
+    }
+
+    /// Depth-first traversal from `start_node_id`, up to `max_depth` relations away when given
+    pub fn dfs(&self, start_node_id: u64, direction: Direction, max_depth: Option<usize>) -> Dfs<'_, E> {
+        Dfs {
+            db: self,
+            direction,
+            max_depth,
+            stack: vec![Visit { node_id: start_node_id, depth: 0, relation_id: None }],
+            depths: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, two_routes_to_generative_model};
+    use super::Direction;
+
+    #[test]
+    fn limited_dfs_explores_nodes_again_from_a_shallower_path() {
+        let (_dir, mut db) = temp_db(None, None);
+        // The newest relation is explored first, so B is first reached through X at depth 2
+        db.add_edge("A", "B", "RELATES-TO").unwrap();
+        db.add_edge("B", "Y", "RELATES-TO").unwrap();
+        db.add_edge("X", "B", "RELATES-TO").unwrap();
+        db.add_edge("A", "X", "RELATES-TO").unwrap();
+
+        let visits: Vec<(u64, usize)> = db.dfs(node_id(&db, "A"), Direction::Outgoing, Some(2))
+            .map(|visit| visit.map(|v| (v.node_id, v.depth)).unwrap())
+            .collect();
+        assert_eq!(visits, vec![(node_id(&db, "A"), 0), (node_id(&db, "X"), 1), (node_id(&db, "B"), 2), (node_id(&db, "Y"), 2)]);
+
+        // Each node is visited once, even when reached again
+        let visits: Vec<u64> = db.dfs(node_id(&db, "A"), Direction::Both, Some(3)).map(|visit| visit.unwrap().node_id).collect();
+        assert_eq!(visits.len(), 4);
+        let visits: Vec<u64> = db.dfs(node_id(&db, "A"), Direction::Outgoing, Some(1)).map(|visit| visit.unwrap().node_id).collect();
+        assert_eq!(visits, vec![node_id(&db, "A"), node_id(&db, "X"), node_id(&db, "B")]);
+    }
+
+    #[test]
+    fn bfs_visits_nodes_at_their_smallest_depth() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "RELATES-TO").unwrap();
+        db.add_edge("B", "C", "RELATES-TO").unwrap();
+        db.add_edge("A", "C", "RELATES-TO").unwrap();
+
+        let visits: Vec<(u64, usize)> = db.bfs(node_id(&db, "A"), Direction::Outgoing, None)
+            .map(|visit| visit.map(|v| (v.node_id, v.depth)).unwrap())
+            .collect();
+        assert_eq!(visits.len(), 3);
+        assert!(visits.contains(&(node_id(&db, "C"), 1)));
+    }
+
+    #[test]
+    fn bfs_reports_depths_and_stops_at_the_limit() {
+        let (_dir, db, _) = two_routes_to_generative_model();
+        let depths: Vec<(u64, usize)> = db.bfs(node_id(&db, "Score Matching"), Direction::Outgoing, None)
+            .map(|visit| visit.map(|v| (v.node_id, v.depth)).unwrap())
+            .collect();
+        assert_eq!(depths.len(), 4);
+        assert_eq!(depths[0], (node_id(&db, "Score Matching"), 0));
+        assert!(depths.contains(&(node_id(&db, "Generative Model"), 2)));
+
+        let limited: Vec<u64> = db.bfs(node_id(&db, "Generative Model"), Direction::Incoming, Some(1))
+            .map(|visit| visit.unwrap().node_id)
+            .collect();
+        assert_eq!(limited.len(), 4);
+        assert!(!limited.contains(&node_id(&db, "Score Matching")));
+    }
+
+    #[test]
+    fn dfs_reaches_every_node_both_ways() {
+        let (_dir, db, _) = two_routes_to_generative_model();
+        let gan = node_id(&db, "GAN");
+        let dfs: Vec<u64> = db.dfs(gan, Direction::Both, None).map(|visit| visit.unwrap().node_id).collect();
+        assert_eq!(dfs.len(), 5);
+        assert_eq!(dfs[0], gan);
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{HashMap, HashSet};
+
+use log::debug;
+use rand::Rng;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+use super::Direction;
+
+/// What a relation weighs when the walker picks the next step
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum EdgeWeight {
+    /// Every neighbor is as likely, however many relations lead to it
+    #[default]
+    Uniform,
+    /// Neighbors linked by more relations are more likely
+    EdgeCount,
+    /// The numeric edge property `key`, like a confidence; relations without it, or with an
+    /// infinite or NaN value, weigh 1
+    Property(String),
+}
+
+/// Settings of `Reachdb::biased_walk`
+///
+/// The defaults walk outgoing relations with uniform weights, like `random_walk`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalkConfig {
+    pub direction: Direction,
+    /// Factor of each relation name, names left out weigh 1
+    pub type_weights: HashMap<String, f64>,
+    pub edge_weight: EdgeWeight,
+    /// node2vec return parameter, a high `p` makes going straight back less likely, must be positive
+    pub p: f64,
+    /// node2vec in-out parameter, a low `q` moves away from the previous node, a high `q` stays around it,
+    /// must be positive
+    pub q: f64,
+    /// Probability of jumping back to the start node before each step
+    pub restart: f64,
+}
+
+impl Default for WalkConfig {
+    fn default() -> Self {
+        Self {
+            direction: Direction::Outgoing,
+            type_weights: HashMap::new(),
+            edge_weight: EdgeWeight::Uniform,
+            p: 1.0,
+            q: 1.0,
+            restart: 0.0,
+        }
+    }
+}
+
+impl WalkConfig {
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+    pub fn type_weight(mut self, relation: &str, weight: f64) -> Self {
+        self.type_weights.insert(relation.to_string(), weight);
+        self
+    }
+    pub fn edge_weight(mut self, edge_weight: EdgeWeight) -> Self {
+        self.edge_weight = edge_weight;
+        self
+    }
+    pub fn p(mut self, p: f64) -> Self {
+        self.p = p;
+        self
+    }
+    pub fn q(mut self, q: f64) -> Self {
+        self.q = q;
+        self
+    }
+    pub fn restart(mut self, restart: f64) -> Self {
+        self.restart = restart;
+        self
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Random walk of up to `steps` relations from `start_node_id`, biased by `config`
+    ///
+    /// Returns the relation ids like `random_walk`. After a restart or a dead end the walk
+    /// carries on from the start node, so consecutive relations do not always share a node.
+    /// It ends early only when the start node itself has nowhere to go.
+    pub fn biased_walk(&self, start_node_id: u64, steps: usize, config: &WalkConfig) -> Result<Vec<u64>, ReachdbError> {
+        self.biased_walk_with(start_node_id, steps, config, &mut rand::thread_rng())
+    }
+
+    /// `biased_walk` drawing from the given random number generator, e.g. a seeded one
+    pub fn biased_walk_with<R: Rng>(&self, start_node_id: u64, steps: usize, config: &WalkConfig, rng: &mut R) -> Result<Vec<u64>, ReachdbError> {
+        for (name, value) in [("p", config.p), ("q", config.q)] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(ReachdbError::OtherError(format!("Walk parameter {} must be positive and finite, got {}", name, value)));
+            }
+        }
+        let mut type_weights = HashMap::new();
+        for (relation, weight) in &config.type_weights {
+            if !(weight.is_finite() && *weight >= 0.0) {
+                return Err(ReachdbError::OtherError(format!("Weight of {} must be finite and not negative, got {}", relation, weight)));
+            }
+            match self.relation_type_id(relation) {
+                Some(type_id) => type_weights.insert(type_id, *weight),
+                None => return Err(ReachdbError::OtherError(format!("Unknown relation type {}", relation))),
+            };
+        }
+
+        let mut path = Vec::with_capacity(steps);
+        let mut current = start_node_id;
+        let mut previous: Option<u64> = None;
+        while path.len() < steps {
+            if previous.is_some() && config.restart > 0.0 && rng.gen_bool(config.restart.min(1.0)) {
+                current = start_node_id;
+                previous = None;
+            }
+
+            let candidates = self.walk_candidates(current, previous, config, &type_weights)?;
+            let total: f64 = candidates.iter().map(|(_, _, weight)| weight).sum();
+            if !total.is_finite() {
+                return Err(ReachdbError::OtherError(format!("Weights of the relations of node {} add up to {}", current, total)));
+            }
+            if total <= 0.0 {
+                if previous.is_none() {
+                    break;
+                }
+                debug!("Dead end at node {}, back to {}", current, start_node_id);
+                current = start_node_id;
+                previous = None;
+                continue;
+            }
+
+            let mut pick = rng.gen_range(0.0..total);
+            let &(rel_id, next, _) = candidates.iter()
+                .find(|(_, _, weight)| {
+                    pick -= weight;
+                    pick < 0.0
+                })
+                .unwrap_or_else(|| candidates.iter().rev().find(|(_, _, w)| *w > 0.0).expect("total is positive"));
+            path.push(rel_id);
+            previous = Some(current);
+            current = next;
+        }
+        Ok(path)
+    }
+
+    /// Relations the walker may take from `current`, with the node they lead to and their weight
+    fn walk_candidates(&self, current: u64, previous: Option<u64>, config: &WalkConfig, type_weights: &HashMap<u8, f64>) -> Result<Vec<(u64, u64, f64)>, ReachdbError> {
+        let neighbors = self.neighbors(current, config.direction)?;
+        let mut parallel: HashMap<u64, usize> = HashMap::new();
+        for (_, next) in &neighbors {
+            *parallel.entry(*next).or_default() += 1;
+        }
+        // Nodes one step from the previous node, for the in-out bias
+        let around_previous: HashSet<u64> = match previous {
+            Some(previous) if config.q != 1.0 => self.neighbors(previous, Direction::Both)?.into_iter().map(|(_, n)| n).collect(),
+            _ => HashSet::new(),
+        };
+
+        let mut candidates = Vec::with_capacity(neighbors.len());
+        for (rel_id, next) in neighbors {
+            let rel = self.get_relation(rel_id)?;
+            let mut weight = type_weights.get(&rel.type_id).copied().unwrap_or(1.0);
+            weight *= match &config.edge_weight {
+                EdgeWeight::Uniform => 1.0 / parallel[&next] as f64,
+                EdgeWeight::EdgeCount => 1.0,
+                EdgeWeight::Property(key) => self.get_edge_properties(rel_id)?
+                    .get(key)
+                    .and_then(|value| value.as_f64())
+                    .filter(|weight| weight.is_finite())
+                    .unwrap_or(1.0),
+            };
+            if let Some(previous) = previous {
+                weight *= if next == previous {
+                    1.0 / config.p
+                } else if config.q == 1.0 || around_previous.contains(&next) {
+                    1.0
+                } else {
+                    1.0 / config.q
+                };
+            }
+            candidates.push((rel_id, next, weight.max(0.0)));
+        }
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::{PropertyValue, Reachdb};
+    use super::{EdgeWeight, WalkConfig};
+    use crate::algorithms::Direction;
+
+    /// "Attention" influences "Transformer" and is a "Mechanism" of confidence 0
+    fn attention_two_ways() -> (tempfile::TempDir, Reachdb<TypeId>, u64, u64) {
+        let (dir, mut db) = temp_db(None, None);
+        let influences = db.add_edge("Attention", "Transformer", "INFLUENCES").unwrap().unwrap();
+        let is_a = db.add_edge("Attention", "Mechanism", "IS-A").unwrap().unwrap();
+        db.set_edge_property(is_a, "confidence", PropertyValue::Float(0.0)).unwrap();
+        let start = node_id(&db, "Attention");
+        (dir, db, start, influences)
+    }
+
+    #[test]
+    fn walks_skip_switched_off_relation_types() {
+        let (_dir, db, start, influences) = attention_two_ways();
+        let mut rng = StdRng::seed_from_u64(7);
+        // IS-A is switched off, so every outgoing step takes INFLUENCES and then restarts at the dead end
+        let config = WalkConfig::default().type_weight("IS-A", 0.0);
+        let path = db.biased_walk_with(start, 5, &config, &mut rng).unwrap();
+        assert_eq!(path, vec![influences; 5]);
+    }
+
+    #[test]
+    fn walks_follow_property_weights() {
+        let (_dir, mut db, start, influences) = attention_two_ways();
+        let mut rng = StdRng::seed_from_u64(7);
+        let config = WalkConfig::default().edge_weight(EdgeWeight::Property("confidence".into()));
+        assert!(db.biased_walk_with(start, 5, &config, &mut rng).unwrap().iter().all(|rel| *rel == influences));
+
+        // An infinite weight counts as a missing one
+        db.set_edge_property(influences, "confidence", PropertyValue::Float(f64::INFINITY)).unwrap();
+        assert!(db.biased_walk_with(start, 5, &config, &mut rng).unwrap().iter().all(|rel| *rel == influences));
+    }
+
+    #[test]
+    fn a_high_return_parameter_keeps_walks_from_turning_back() {
+        let (_dir, mut db) = temp_db(None, None);
+        let mut rng = StdRng::seed_from_u64(7);
+        // Undirected and all but unable to turn back, the walk only does so at the ends of the chain
+        let xy = db.add_edge("X", "Y", "IS-A").unwrap().unwrap();
+        let yz = db.add_edge("Z", "Y", "IS-A").unwrap().unwrap();
+        let x = node_id(&db, "X");
+        let config = WalkConfig::default().direction(Direction::Both).p(1e12);
+        assert_eq!(db.biased_walk_with(x, 5, &config, &mut rng).unwrap(), vec![xy, yz, yz, xy, xy]);
+    }
+
+    #[test]
+    fn bad_configs_are_rejected() {
+        let (_dir, db, start, _) = attention_two_ways();
+        for config in [WalkConfig::default().p(0.0), WalkConfig::default().q(f64::INFINITY), WalkConfig::default().type_weight("IS-A", f64::NAN)] {
+            assert!(db.biased_walk(start, 1, &config).is_err());
+        }
+
+        let config = WalkConfig::default().type_weight("CITES", 2.0);
+        assert!(db.biased_walk(start, 1, &config).is_err());
+    }
+}
@@ -1,48 +1,142 @@
 // All the metadata for a particular session is stored here
 
 use std::result::Result;
-use crate::{errors::ReachdbError, records::{node::NodeRecord, relationship::RelationshipRecord, Record, NULL_OFFSET}, utils::create_mmap};
+use crate::{errors::ReachdbError, graphs::GraphInfo, lock::{LockFile, LockMode}, registry::RelationRegistry, resolve::NameNormalizer, wal::{RecordFile, SledTree, WriteAheadLog}, records::{node::NodeRecord, property::PropertyValue, relationship::RelationshipRecord, FileHeader, Record, FORMAT_VERSION, NULL_OFFSET}, vector::{embedding_offset, EMBEDDING_MAGIC}, utils::{create_mmap, grown_mmap_size, open_mmap_read_only, unix_time, MappedFile}};
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use lru::LruCache;
 use log::{info, debug};
 use memmap2::MmapMut;
 use serde::{Deserialize, Serialize};
 
+/// Name of the tree inside `reachdb.property` holding the key/value properties
+pub(crate) const PROPERTY_TREE: &str = "reachdb.property.kv";
+
+/// Name of the tree inside `reachdb.property` holding when and from where each relation was seen
+pub(crate) const EDGE_PROVENANCE_TREE: &str = "reachdb.property.provenance";
+
+/// Name of the tree inside `reachdb.nodeid` mapping aliases and normalized names to node ids
+pub(crate) const NODE_ALIAS_TREE: &str = "reachdb.nodeid.alias";
+
+/// Name of the tree inside `reachdb.nodeid` holding the token index of the node names
+pub(crate) const NODE_TOKEN_TREE: &str = "reachdb.nodeid.token";
+
+/// Number of property strings kept by the lookup cache
+const PROPERTY_CACHE_SIZE: usize = 4096;
+
 pub trait UserDefinedRelationType: std::fmt::Debug {
     fn get_type_id(relation: &str) -> Option<Self> where Self: Sized;
     fn type_id(&self) -> u8;
     fn get_type_str(id: u8) -> Option<String>;
 }
 
+/// Sled databases, kept open for the whole lifetime of the `Reachdb`
+pub struct SledHandles {
+    pub node_id: sled::Db,
+    pub node_alias: sled::Tree,
+    pub node_token: sled::Tree,
+    pub property: sled::Db,
+    pub property_kv: sled::Tree,
+    pub edge_provenance: sled::Tree,
+}
+
+// The derived `Debug` of sled dumps every page of the databases
+impl std::fmt::Debug for SledHandles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SledHandles")
+            .field("node_id", &self.node_id.len())
+            .field("node_alias", &self.node_alias.len())
+            .field("node_token", &self.node_token.len())
+            .field("property", &self.property.len())
+            .field("property_kv", &self.property_kv.len())
+            .field("edge_provenance", &self.edge_provenance.len())
+            .finish()
+    }
+}
+
+/// LRU cache of the id -> string lookups done by `get_property`
+#[derive(Debug)]
+pub(crate) struct PropertyCache(Mutex<LruCache<u64, String>>);
+
+impl Default for PropertyCache {
+    fn default() -> Self {
+        Self(Mutex::new(LruCache::new(NonZeroUsize::new(PROPERTY_CACHE_SIZE).unwrap())))
+    }
+}
+
+impl PropertyCache {
+    fn get(&self, property_id: u64) -> Option<String> {
+        self.0.lock().unwrap().get(&property_id).cloned()
+    }
+    fn insert(&self, property_id: u64, property: String) {
+        self.0.lock().unwrap().put(property_id, property);
+    }
+    pub(crate) fn invalidate(&self, property_id: u64) {
+        self.0.lock().unwrap().pop(&property_id);
+    }
+}
+
 /// Wrapper for the memory maps
 #[derive(Debug)]
 pub struct MmapWrapper {
-    pub node_mmap: MmapMut,
-    pub relation_mmap: MmapMut,
+    pub node_mmap: MappedFile,
+    pub relation_mmap: MappedFile,
+    /// Only mapped once embeddings are used
+    pub embedding_mmap: Option<MappedFile>,
 }
 impl MmapWrapper {
     pub fn flush(&self) -> Result<(), ReachdbError> {
         self.node_mmap.flush()?;
         self.relation_mmap.flush()?;
+        if let Some(embedding_mmap) = self.embedding_mmap.as_ref() {
+            embedding_mmap.flush()?;
+        }
         Ok(())
     }
-    pub fn get_node_as_mut(&mut self) -> &mut MmapMut {
-        &mut self.node_mmap
+    pub fn get_node_as_mut(&mut self) -> Result<&mut MmapMut, ReachdbError> {
+        self.node_mmap.as_mut()
     }
-    pub fn get_relation_as_mut(&mut self) -> &mut MmapMut {
-        &mut self.relation_mmap
+    pub fn get_relation_as_mut(&mut self) -> Result<&mut MmapMut, ReachdbError> {
+        self.relation_mmap.as_mut()
     }
-    pub fn get_node_as_ref(&self) -> &MmapMut {
+    pub fn get_node_as_ref(&self) -> &[u8] {
         &self.node_mmap
     }
-    pub fn get_relation_as_ref(&self) -> &MmapMut {
+    pub fn get_relation_as_ref(&self) -> &[u8] {
         &self.relation_mmap
     }
-    pub fn take_as_mut(&mut self) -> (&mut MmapMut, &mut MmapMut) {
-        (&mut self.node_mmap, &mut self.relation_mmap)
+    pub fn get_embedding_as_mut(&mut self) -> Result<&mut MmapMut, ReachdbError> {
+        self.embedding_mmap.as_mut()
+            .ok_or_else(|| ReachdbError::OtherError("Embeddings not initialized".to_string()))?
+            .as_mut()
     }
-    pub fn take_as_ref(&self) -> (&MmapMut, &MmapMut) {
+    pub fn get_embedding_as_ref(&self) -> Result<&[u8], ReachdbError> {
+        self.embedding_mmap.as_deref().ok_or_else(|| ReachdbError::OtherError("Embeddings not initialized".to_string()))
+    }
+    pub fn take_as_ref(&self) -> (&[u8], &[u8]) {
         (&self.node_mmap, &self.relation_mmap)
     }
-    
+    /// Flushes the current node mmap and maps `file_path` again with the new `size`
+    pub fn remap_node(&mut self, file_path: &str, size: usize) -> Result<(), ReachdbError> {
+        self.node_mmap.flush()?;
+        self.node_mmap = MappedFile::ReadWrite(create_mmap(file_path, size)?);
+        Ok(())
+    }
+    /// Flushes the current relation mmap and maps `file_path` again with the new `size`
+    pub fn remap_relation(&mut self, file_path: &str, size: usize) -> Result<(), ReachdbError> {
+        self.relation_mmap.flush()?;
+        self.relation_mmap = MappedFile::ReadWrite(create_mmap(file_path, size)?);
+        Ok(())
+    }
+    /// Flushes the current embedding mmap, if any, and maps `file_path` again with the new `size`
+    pub fn remap_embedding(&mut self, file_path: &str, size: usize) -> Result<(), ReachdbError> {
+        if let Some(embedding_mmap) = self.embedding_mmap.as_ref() {
+            embedding_mmap.flush()?;
+        }
+        self.embedding_mmap = Some(MappedFile::ReadWrite(create_mmap(file_path, size)?));
+        Ok(())
+    }
 }
 
 /// Stores all the metadata for a particular session
@@ -53,16 +147,62 @@ pub struct Reachdb<E: UserDefinedRelationType> {
     _marker: std::marker::PhantomData<E>, // We don't store `E`, but we want to enforce the trait
 
     #[serde(skip_serializing, skip_deserializing)]
-    mmap: Option<MmapWrapper>,
+    pub(crate) mmap: Option<MmapWrapper>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) sled: Option<SledHandles>,
+
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) property_cache: PropertyCache,
+
+    // Log of the running transaction
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) wal: Option<WriteAheadLog>,
+
+    // Lock on the directory, held until `close`
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) lock: Option<LockFile>,
+
+    // Opened with `open_read_only`, every write is refused
+    #[serde(skip_serializing, skip_deserializing)]
+    pub(crate) read_only: bool,
     
     // Metadata
-    node_mmap_size: usize,
-    relation_mmap_size: usize,
-    node_count: u64,
-    relationship_count: u64,
-    property_count: u64,
+    pub(crate) node_mmap_size: usize,
+    pub(crate) relation_mmap_size: usize,
+    pub(crate) node_count: u64,
+    pub(crate) relationship_count: u64,
+    pub(crate) property_count: u64,
     pub path: String,
 
+    // Slots of removed records, reused before the files grow
+    #[serde(default)]
+    pub(crate) free_node_ids: Vec<u64>,
+    #[serde(default)]
+    pub(crate) free_relationship_ids: Vec<u64>,
+
+    // Relation types named at runtime
+    #[serde(default)]
+    pub(crate) relation_registry: RelationRegistry,
+
+    // Cleanup applied to node names on insert, `None` keeps names exact
+    #[serde(default)]
+    pub(crate) name_normalizer: Option<NameNormalizer>,
+
+    // Layout of the record files, 0 for directories written before the file headers
+    #[serde(default)]
+    pub(crate) format_version: u32,
+
+    // Length of the node embeddings, 0 until they are used
+    #[serde(default)]
+    pub(crate) embedding_dim: usize,
+    #[serde(default)]
+    pub(crate) embedding_mmap_size: usize,
+
+    // Named graphs under `reachdb.graphs`
+    #[serde(default)]
+    pub(crate) graphs: BTreeMap<String, GraphInfo>,
+
 }
 
 impl<E: UserDefinedRelationType> Reachdb<E> {
@@ -70,6 +210,11 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         Ok(Self {
             _marker: std::marker::PhantomData,
             mmap: None,
+            sled: None,
+            property_cache: PropertyCache::default(),
+            wal: None,
+            lock: None,
+            read_only: false,
             
             // Metadata
             node_mmap_size: 4096,
@@ -78,6 +223,14 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
             relationship_count: 0,
             property_count: 0,
             path: path.to_string(),
+            free_node_ids: Vec::new(),
+            free_relationship_ids: Vec::new(),
+            relation_registry: RelationRegistry::default(),
+            name_normalizer: None,
+            format_version: FORMAT_VERSION,
+            embedding_dim: 0,
+            embedding_mmap_size: 0,
+            graphs: BTreeMap::new(),
         })
     }
 
@@ -103,27 +256,60 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         relation_mmap_size: Option<usize>) -> Result<(), ReachdbError> {
 
         info!("Preparing databases...");
-        // If None then keep 4096(default value)
-        let node_mmap_size  = node_mmap_size.unwrap_or(self.node_mmap_size);
-        let relation_mmap_size  = relation_mmap_size.unwrap_or(self.relation_mmap_size);
+        // The requested sizes are only a lower bound, a smaller size would truncate the existing records
+        let node_mmap_size = Self::initial_mmap_size(
+            &Self::get_db_path(&self.path)[2],
+            node_mmap_size,
+            self.node_mmap_size
+        )?;
+        let relation_mmap_size = Self::initial_mmap_size(
+            &Self::get_db_path(&self.path)[3],
+            relation_mmap_size,
+            self.relation_mmap_size
+        )?;
 
-        // Open sled databases (this will create them if they don't exist)
+        // Open sled databases (this will create them if they don't exist), they stay open until `close`
         let node_db = sled::open(&Self::get_db_path(&self.path)[0])?;
-        let property_db = sled::open(&Self::get_db_path(&self.path)[1])?;  
+        let node_alias = node_db.open_tree(NODE_ALIAS_TREE)?;
+        let node_token = node_db.open_tree(NODE_TOKEN_TREE)?;
+        let property_db = sled::open(&Self::get_db_path(&self.path)[1])?;
+        let property_kv = property_db.open_tree(PROPERTY_TREE)?;
+        let edge_provenance = property_db.open_tree(EDGE_PROVENANCE_TREE)?;
 
         // Make sure the databases are properly initialized
         node_db.flush()?;
         property_db.flush()?;
+        self.sled = Some(SledHandles {
+            node_id: node_db,
+            node_alias,
+            node_token,
+            property: property_db,
+            property_kv,
+            edge_provenance,
+        });
 
-        // Creating the Mmap files
-        let node_mmap = create_mmap(
+        // Creating the Mmap files, a read-only handle maps them as they are
+        let read_only = self.read_only;
+        let map = |file_path: &str, size: usize| match read_only {
+            true => open_mmap_read_only(file_path),
+            false => create_mmap(file_path, size).map(MappedFile::ReadWrite),
+        };
+        let node_mmap = map(
             &Self::get_db_path(&self.path)[2],
             node_mmap_size
         )?;
-        let relation_mmap = create_mmap(
+        let relation_mmap = map(
             &Self::get_db_path(&self.path)[3],
             relation_mmap_size
         )?;
+        let embedding_path = &Self::get_db_path(&self.path)[6];
+        let embedding_exists = std::path::Path::new(embedding_path).exists();
+        let embedding_mmap = if embedding_exists || (self.embedding_dim > 0 && !read_only) {
+            self.embedding_mmap_size = Self::initial_mmap_size(embedding_path, Some(4096), self.embedding_mmap_size)?;
+            Some(map(embedding_path, self.embedding_mmap_size)?)
+        } else {
+            None
+        };
 
         // Update the metadata
         self.update(
@@ -133,10 +319,13 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
             Some(relation_mmap_size),
             Some(MmapWrapper {
                 node_mmap,
-                relation_mmap
+                relation_mmap,
+                embedding_mmap,
             })
         );
 
+        self.check_format()?;
+
         info!("Databases created successfully");
 
         Ok(())
@@ -150,89 +339,260 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         if !std::path::Path::new(path).exists() {
             // Create the directory since it doesn't exist
             std::fs::create_dir_all(path)?;
+        }
+        let lock = LockFile::acquire(&Self::get_db_path(path)[7], LockMode::ReadWrite)?;
+        // Put back the files of a restore that was interrupted while it swapped them
+        Self::roll_back_restore(path)?;
+        let metadata_path = &Self::get_db_path(path)[4];
+        if !std::path::Path::new(metadata_path).exists() {
             reachdb = Self::new(path)?;
         } else {
-            let metadata_path = &Self::get_db_path(path)[4];
-            if !std::path::Path::new(metadata_path).exists() {
-                reachdb = Self::new(path)?;
-            } else {
-                let metadata = std::fs::read_to_string(metadata_path)?;
-                reachdb = serde_json::from_str(&metadata)?;
-            }
+            let metadata = std::fs::read_to_string(metadata_path)?;
+            reachdb = serde_json::from_str(&metadata)?;
         }
+        reachdb.lock = Some(lock);
+        // The directory may have been moved or restored since the metadata was written
+        reachdb.path = path.to_string();
         
         // Prepare the databases
         reachdb.prepare(node_mmap_size, relation_mmap_size)?;
 
+        // Finish a transaction that committed but was interrupted before its changes were written
+        reachdb.recover()?;
+
+        // Databases written before the search index existed get it on their first open
+        if reachdb.sled_tree(SledTree::NodeToken).is_empty() && !reachdb.sled_tree(SledTree::NodeId).is_empty() {
+            reachdb.rebuild_search_index()?;
+        }
+
         info!("Opening databases... {:#?}", reachdb);
         info!("Databases opened successfully");
 
         Ok(reachdb)
     }
 
+    /// Opens an existing database without writing to it
+    ///
+    /// The record files are mapped read-only and every write, `transaction` included, fails.
+    /// A database with an unfinished transaction must be opened for writing first to recover it.
+    pub fn open_read_only(path: &str) -> Result<Self, ReachdbError> {
+        info!("Opening databases read-only...");
+        let paths = Self::get_db_path(path);
+        if std::path::Path::new(&format!("{}.old", path.trim_end_matches('/'))).exists() {
+            return Err(ReachdbError::OtherError(format!(
+                "{} holds an interrupted restore, open it for writing to roll it back", path
+            )));
+        }
+        if !std::path::Path::new(&paths[4]).exists() {
+            return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+        }
+        let lock = LockFile::acquire(&paths[7], LockMode::ReadOnly)?;
+        if std::path::Path::new(&paths[5]).exists() {
+            return Err(ReachdbError::OtherError(format!(
+                "{} holds an unfinished transaction, open it for writing to recover it", paths[5]
+            )));
+        }
+
+        let mut reachdb: Self = serde_json::from_str(&std::fs::read_to_string(&paths[4])?)?;
+        reachdb.lock = Some(lock);
+        reachdb.path = path.to_string();
+        reachdb.read_only = true;
+        reachdb.prepare(None, None)?;
+
+        info!("Databases opened read-only");
+        Ok(reachdb)
+    }
+
+    /// Whether the database was opened with `open_read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     // Close databases and save metadata
     pub fn close(&mut self) -> Result<(), ReachdbError> {
 
         // Serialize and save metadata to a file
-        let metadata_path = &Self::get_db_path(&self.path)[4];
-        let metadata = serde_json::to_string(&self)?;
-        std::fs::write(metadata_path, metadata)?;
+        if !self.read_only {
+            self.save_metadata()?;
+        }
         
         // Flush and drop memory maps
         if let Some(mmap) = self.mmap.take() {
             mmap.flush()?;
         }
+
+        // Flush and release the sled databases
+        if let Some(sled) = self.sled.take() {
+            sled.node_id.flush()?;
+            sled.property.flush()?;
+        }
+
+        // Let the next handle open the directory
+        self.lock.take();
         
         info!("Reachdb closed successfully");
         Ok(())
     }
         
-    fn get_db_path(path: &str) -> Vec<String> {
+    /// Serializes the metadata into `reachdb.metadata.json`
+    pub(crate) fn save_metadata(&self) -> Result<(), ReachdbError> {
+        self.check_writable()?;
+        let metadata_path = &Self::get_db_path(&self.path)[4];
+        let metadata = serde_json::to_string(&self)?;
+        std::fs::write(metadata_path, metadata)?;
+        Ok(())
+    }
+
+    /// Fails for a database opened with `open_read_only`
+    pub(crate) fn check_writable(&self) -> Result<(), ReachdbError> {
+        if self.read_only {
+            return Err(ReachdbError::OtherError(format!("{} is opened read-only", self.path)));
+        }
+        Ok(())
+    }
+
+    /// Size to map a file with on `prepare`: the largest of the requested size, the size in the metadata and the file on disk
+    fn initial_mmap_size(file_path: &str, requested: Option<usize>, stored: usize) -> Result<usize, ReachdbError> {
+        let on_disk = match std::fs::metadata(file_path) {
+            Ok(file) => file.len() as usize,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(requested.unwrap_or(0).max(stored).max(on_disk))
+    }
+
+    /// Grows the node mmap (by doubling) if the record `node_id` does not fit into it
+    pub(crate) fn ensure_node_capacity(&mut self, node_id: u64) -> Result<(), ReachdbError> {
+        let required = NodeRecord::id2offset(node_id) + NodeRecord::record_size();
+        if required <= self.node_mmap_size {
+            return Ok(());
+        }
+        let new_size = grown_mmap_size(self.node_mmap_size, required);
+        self.mmap
+            .as_mut()
+            .expect("Mmap not initialized")
+            .remap_node(&Self::get_db_path(&self.path)[2], new_size)?;
+        info!("Grew node mmap: {} -> {} bytes", self.node_mmap_size, new_size);
+
+        self.node_mmap_size = new_size;
+        self.save_metadata()
+    }
+
+    /// Grows the relation mmap (by doubling) if the record `relation_id` does not fit into it
+    pub(crate) fn ensure_relation_capacity(&mut self, relation_id: u64) -> Result<(), ReachdbError> {
+        let required = RelationshipRecord::id2offset(relation_id) + RelationshipRecord::record_size();
+        if required <= self.relation_mmap_size {
+            return Ok(());
+        }
+        let new_size = grown_mmap_size(self.relation_mmap_size, required);
+        self.mmap
+            .as_mut()
+            .expect("Mmap not initialized")
+            .remap_relation(&Self::get_db_path(&self.path)[3], new_size)?;
+        info!("Grew relation mmap: {} -> {} bytes", self.relation_mmap_size, new_size);
+
+        self.relation_mmap_size = new_size;
+        self.save_metadata()
+    }
+
+    /// Writes the headers of new record files and validates those of existing ones
+    fn check_format(&mut self) -> Result<(), ReachdbError> {
+        let empty = self.node_count == 0 && self.relationship_count == 0 && self.property_count == 0;
+        if self.format_version != FORMAT_VERSION {
+            if self.format_version > FORMAT_VERSION || !empty {
+                return Err(ReachdbError::OtherError(format!(
+                    "{} was written with record format {}, this build uses format {}{}",
+                    self.path,
+                    self.format_version,
+                    FORMAT_VERSION,
+                    if self.format_version < FORMAT_VERSION { ", migrate it first with `reachdb migrate`" } else { "" },
+                )));
+            }
+            // Nothing to convert in an empty directory
+            self.format_version = FORMAT_VERSION;
+        }
+
+        let paths = Self::get_db_path(&self.path);
+        let mmap = self.mmap.as_mut().expect("Mmap not initialized");
+        for (bytes, header, path) in [
+            (&mut mmap.node_mmap, NodeRecord::header(), &paths[2]),
+            (&mut mmap.relation_mmap, RelationshipRecord::header(), &paths[3]),
+        ] {
+            if FileHeader::is_blank(bytes) && !self.read_only {
+                header.write(bytes.as_mut()?)?;
+            } else {
+                FileHeader::validate(bytes, &header, path)?;
+            }
+        }
+        // The slot size of the embeddings is checked when they are written, it follows the dimension
+        if let Some(bytes) = mmap.embedding_mmap.as_ref()
+            && !FileHeader::is_blank(bytes)
+        {
+            let header = FileHeader::read(bytes)?;
+            let expected = FileHeader { record_size: header.record_size, ..FileHeader::new(EMBEDDING_MAGIC, 0) };
+            FileHeader::validate(bytes, &expected, &paths[6])?;
+        }
+        Ok(())
+    }
+
+    /// Grows the embedding mmap (by doubling), or creates it, if the slot of `node_id` does not fit into it
+    pub(crate) fn ensure_embedding_capacity(&mut self, node_id: u64, slot_size: usize) -> Result<(), ReachdbError> {
+        let required = embedding_offset(node_id + 1, slot_size);
+        let mapped = self.mmap.as_ref().expect("Mmap not initialized").embedding_mmap.is_some();
+        if mapped && required <= self.embedding_mmap_size {
+            return self.write_embedding_header(slot_size);
+        }
+        let file_path = &Self::get_db_path(&self.path)[6];
+        let new_size = Self::initial_mmap_size(file_path, Some(grown_mmap_size(self.embedding_mmap_size.max(4096), required)), 0)?;
+        self.mmap
+            .as_mut()
+            .expect("Mmap not initialized")
+            .remap_embedding(file_path, new_size)?;
+        info!("Grew embedding mmap: {} -> {} bytes", self.embedding_mmap_size, new_size);
+
+        self.embedding_mmap_size = new_size;
+        self.write_embedding_header(slot_size)?;
+        self.save_metadata()
+    }
+
+    /// Writes the header of the embedding file when it is new or its slot size changed
+    ///
+    /// Slots are zeroed when their embedding is removed, and the dimension only changes while no
+    /// node has an embedding, so the slots never need to be rewritten.
+    fn write_embedding_header(&mut self, slot_size: usize) -> Result<(), ReachdbError> {
+        let bytes = self.mmap.as_mut().expect("Mmap not initialized").get_embedding_as_mut()?;
+        let header = FileHeader::new(EMBEDDING_MAGIC, slot_size);
+        if FileHeader::read(bytes)? != header {
+            header.write(bytes)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_db_path(path: &str) -> Vec<String> {
         vec![
             format!("{}/reachdb.nodeid", path),
             format!("{}/reachdb.property", path),
             format!("{}/reachdb.node.db", path),
             format!("{}/reachdb.relationship.db", path),
             format!("{}/reachdb.metadata.json", path),
+            format!("{}/reachdb.wal", path),
+            format!("{}/reachdb.embedding.db", path),
+            format!("{}/reachdb.lock", path),
+            format!("{}/reachdb.graphs", path),
         ]
     }
 
-    fn update_node_links(&mut self, node: &mut NodeRecord, new_relation_id: u64, is_target_node: bool) -> Result<u64, ReachdbError> {
-        let (node_mmap, relation_mmap) = self.mmap
-            .as_mut()
-            .expect("Mmap not initialized")
-            .take_as_mut();
-
+    fn update_node_links(&mut self, node: &mut NodeRecord, new_relation_id: u64) -> Result<u64, ReachdbError> {
         let relation_id = node.first_relationship_id;
 
         // Check if this is Not a new node
         if relation_id != NULL_OFFSET {
-            // Process the src relation
-            let mut relation = RelationshipRecord::read(relation_mmap, relation_id)?;
-
-            // Update this old src relationship record
-            match is_target_node {
-                true => {
-                    relation.update(
-                        None,
-                        None,
-                        None,
-                        Some(new_relation_id),
-                        None
-                    );
-                },
-                false => {
-                    relation.update(
-                        None,
-                        Some(new_relation_id),
-                        None,
-                        None,
-                        None
-                    );
-                }
-            }
+            // The old head links to the new relation through this node's side of the record
+            let mut relation = self.get_relation(relation_id)?;
+            relation.set_next_id_for(node.id, new_relation_id);
+
             // Write the relationship back to the mmap
-            relation.write(relation_mmap, relation_id)?;
+            self.write_relation(relation_id, &relation)?;
         }
 
         // Update the current node
@@ -241,33 +601,31 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
             None
         );
         // Write the node back to the mmap
-        node.write(node_mmap, node.id)?;
+        self.write_node(node.id, node)?;
 
         Ok(relation_id)
     }
 
-    fn add_relation(&mut self, src_id: &u64, tgt_id: &u64, type_id: &u8) -> Result<(), ReachdbError> {
+    pub(crate) fn add_relation(&mut self, src_id: &u64, tgt_id: &u64, type_id: &u8) -> Result<u64, ReachdbError> {
 
-        let node_mmap = self.mmap
-            .as_ref()
-            .expect("Mmap not initialized")
-            .get_node_as_ref();
+        // Reuse the slot of a removed relation before growing the file
+        let new_relation_id = match self.free_relationship_ids.last() {
+            Some(id) => *id,
+            None => self.relationship_count,
+        };
 
-        let mut src_node = NodeRecord::read(node_mmap, *src_id)?;
-        let mut tgt_node = NodeRecord::read(node_mmap, *tgt_id)?;
+        let mut src_node = self.get_node(*src_id)?;
+        let mut tgt_node = self.get_node(*tgt_id)?;
 
-        // Process the nodes's relation
-        let new_relation_id = self.relationship_count;
-        let prev_src_relation_id= self.update_node_links(&mut src_node, new_relation_id, false)?;
-        let prev_tgt_relation_id = self.update_node_links(&mut tgt_node, new_relation_id, true)?;
+        // Process the nodes's relation, a self-loop is only linked into the src chain
+        let prev_src_relation_id = self.update_node_links(&mut src_node, new_relation_id)?;
+        let prev_tgt_relation_id = match src_id == tgt_id {
+            true => NULL_OFFSET,
+            false => self.update_node_links(&mut tgt_node, new_relation_id)?,
+        };
         
         // Write the new relationship record
-        let relation_mmap = self.mmap
-            .as_mut()
-            .expect("Mmap not initialized")
-            .get_relation_as_mut();
-
-        let _relation = RelationshipRecord::new(
+        let relation = RelationshipRecord::new(
             *src_id,
             *tgt_id,
             *type_id,
@@ -276,14 +634,63 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
             Some(prev_src_relation_id),
             None,
             Some(prev_tgt_relation_id),
-        ).write(relation_mmap, new_relation_id)?;
+        );
+        self.write_relation(new_relation_id, &relation)?;
         info!("Added new RelationRecord: [type: {}](id:{})", type_id, new_relation_id);
 
         // Metadata update
-        self.relationship_count += 1;
+        if self.free_relationship_ids.pop().is_none() {
+            self.relationship_count += 1;
+        }
+
+        Ok(new_relation_id)
+
+    }
 
+    /// Removes `relation_id` from the chain of `node_id`, moving the node's head if needed
+    fn unlink_relation(&mut self, node_id: u64, relation_id: u64, relation: &RelationshipRecord) -> Result<(), ReachdbError> {
+        let prev_id = relation.prev_id_for(node_id);
+        let next_id = relation.next_id_for(node_id);
+
+        if prev_id != NULL_OFFSET {
+            let mut prev = self.get_relation(prev_id)?;
+            prev.set_next_id_for(node_id, next_id);
+            self.write_relation(prev_id, &prev)?;
+        }
+        if next_id != NULL_OFFSET {
+            let mut next = self.get_relation(next_id)?;
+            next.set_prev_id_for(node_id, prev_id);
+            self.write_relation(next_id, &next)?;
+        }
+
+        let mut node = self.get_node(node_id)?;
+        if node.first_relationship_id == relation_id {
+            let new_head = if prev_id != NULL_OFFSET { prev_id } else { next_id };
+            node.update(Some(new_head), None);
+            self.write_node(node_id, &node)?;
+        }
         Ok(())
+    }
+
+    /// Splices the relation out of both endpoint chains and frees its slot
+    pub(crate) fn remove_relation(&mut self, relation_id: u64) -> Result<(), ReachdbError> {
+        let relation = self.get_relation(relation_id)?;
+        if relation.is_deleted() {
+            return Err(ReachdbError::OtherError(format!("Relation {} is already removed", relation_id)));
+        }
+
+        self.unlink_relation(relation.source_id, relation_id, &relation)?;
+        if relation.source_id != relation.target_id {
+            self.unlink_relation(relation.target_id, relation_id, &relation)?;
+        }
+
+        self.clear_properties(relation.first_property_id)?;
+        self.clear_edge_provenance(relation_id)?;
+        self.write_relation(relation_id, &RelationshipRecord::tombstone())?;
+        self.free_relationship_ids.push(relation_id);
+        info!("Removed RelationRecord: [type: {}](id:{})", relation.type_id, relation_id);
 
+        Ok(())
     }
     // fn get_mmap(&self) -> Result<(&MmapMut, &MmapMut), ReachdbError> {
     //     let node_mmap = self.node_mmap.as_ref().expect("Node Mmap not initialized");
@@ -291,6 +698,9 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
     //     Ok((node_mmap, relation_mmap))
     // }
     pub fn get_node(&self, node_id: u64) -> Result<NodeRecord, ReachdbError> {
+        if let Some(bytes) = self.pending_record(RecordFile::Node, node_id) {
+            return Ok(NodeRecord::from_bytes(bytes));
+        }
         let (node_mmap, _) = match self.mmap.as_ref() {
             Some(mmap) => mmap.take_as_ref(),
             None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
@@ -298,6 +708,9 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         NodeRecord::read(node_mmap, node_id)
     }
     pub fn get_relation(&self, relation_id: u64) -> Result<RelationshipRecord, ReachdbError> {
+        if let Some(bytes) = self.pending_record(RecordFile::Relation, relation_id) {
+            return Ok(RelationshipRecord::from_bytes(bytes));
+        }
         let (_, relation_mmap) = match self.mmap.as_ref() {
             Some(mmap) => mmap.take_as_ref(),
             None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
@@ -305,11 +718,7 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         RelationshipRecord::read(relation_mmap, relation_id)
     }
     pub fn get_connected_node(&self, node_id: u64, relation_id: u64) -> Result<u64, ReachdbError> {
-        let (_, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let relation = RelationshipRecord::read(relation_mmap, relation_id)?;
+        let relation = self.get_relation(relation_id)?;
         let next_id = if node_id == relation.source_id {
             relation.target_id
         } else {
@@ -319,13 +728,10 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         Ok(next_id)
     }
     pub fn get_all_node_relations(&self, node_id: u64) -> Result<Vec<u64>, ReachdbError> {
-        let (node_mmap, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let node = NodeRecord::read(node_mmap, node_id)?;
+        let node = self.get_node(node_id)?;
+        let read = |id| self.get_relation(id);
         let relations = RelationshipRecord::into_iter(
-            relation_mmap,
+            &read,
             &node_id,
             node.first_relationship_id
         ).filter_map(|rel| {
@@ -335,13 +741,10 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         Ok(relations)
     }
     pub fn get_outgoing_node_relations(&self, node_id: u64) -> Result<Vec<u64>, ReachdbError> {
-        let (node_mmap, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let node = NodeRecord::read(node_mmap, node_id)?;
+        let node = self.get_node(node_id)?;
+        let read = |id| self.get_relation(id);
         let relations = RelationshipRecord::into_iter(
-            relation_mmap,
+            &read,
             &node_id,
             node.first_relationship_id
         ).filter_map(|rel| {
@@ -357,13 +760,10 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         Ok(relations)
     }
     pub fn get_incoming_node_relations(&self, node_id: u64) -> Result<Vec<u64>, ReachdbError> {
-        let (node_mmap, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let node = NodeRecord::read(node_mmap, node_id)?;
+        let node = self.get_node(node_id)?;
+        let read = |id| self.get_relation(id);
         let relations = RelationshipRecord::into_iter(
-            relation_mmap,
+            &read,
             &node_id,
             node.first_relationship_id
         ).filter_map(|rel| {
@@ -378,143 +778,708 @@ impl<E: UserDefinedRelationType> Reachdb<E> {
         debug!("Node: {node:#?} Relations: {relations:?}", node=node, relations=relations);
         Ok(relations)
     }
-    fn if_edge_exists(&self, src_id: &u64, tgt_id: &u64, type_id: &u8) -> Result<bool, ReachdbError> {
-        let (node_mmap, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let src_node = NodeRecord::read(node_mmap, *src_id)?;
+    /// Returns the id of the relation if the edge already exists
+    pub(crate) fn if_edge_exists(&self, src_id: &u64, tgt_id: &u64, type_id: &u8) -> Result<Option<u64>, ReachdbError> {
+        let src_node = self.get_node(*src_id)?;
         debug!("SRC_NODE inloop: {src_node:#?}");
 
-        let exists = RelationshipRecord::into_iter(
-            relation_mmap,
+        let read = |id| self.get_relation(id);
+        let relation_id = RelationshipRecord::into_iter(
+            &read,
             src_id,
             src_node.first_relationship_id
-        ).any(|rel| {
+        ).find_map(|rel| {
             // debug!("RelRec inloop: {rel:#?}");
-            if let Ok((_, rel)) = rel {
-                rel.target_id == *tgt_id &&
-                rel.type_id == *type_id
-            } else {
-                false
+            match rel {
+                Ok((id, rel)) if rel.source_id == *src_id
+                    && rel.target_id == *tgt_id
+                    && rel.type_id == *type_id => Some(id),
+                _ => None,
             }
         });
-        Ok(exists)
+        Ok(relation_id)
     }
 
-    fn get_or_add_node_id(&mut self, node: &str) -> Result<u64, ReachdbError> {
+    /// Returns the id of the node named `node`, without adding it
+    ///
+    /// Aliases and, with a name normalizer, other spellings of the name lead to the same node.
+    pub fn get_node_id(&self, node: &str) -> Result<Option<u64>, ReachdbError> {
+        match self.sled_get(SledTree::NodeId, node.as_bytes())? {
+            Some(id_bytes) => Ok(Some(bincode::deserialize::<u64>(&id_bytes)?)),
+            None => self.get_aliased_node_id(node),
+        }
+    }
 
-        let db = sled::open(&Self::get_db_path(&self.path)[0])?;
-        let property_db = sled::open(&Self::get_db_path(&self.path)[1])?;
+    /// Returns the id of the `source` -[`relationship`]-> `target` edge if it exists
+    pub fn find_edge(&self, source: &str, target: &str, relationship: &str) -> Result<Option<u64>, ReachdbError> {
+        let (src_id, tgt_id) = match (self.get_node_id(source)?, self.get_node_id(target)?) {
+            (Some(src_id), Some(tgt_id)) => (src_id, tgt_id),
+            _ => return Ok(None),
+        };
+        match self.relation_type_id(relationship) {
+            Some(type_id) => self.if_edge_exists(&src_id, &tgt_id, &type_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the `source` -[`relationship`]-> `target` edge
+    ///
+    /// Returns `false` if there was no such edge
+    pub fn remove_edge(&mut self, source: &str, target: &str, relationship: &str) -> Result<bool, ReachdbError> {
+        self.transaction(|db| {
+            match db.find_edge(source, target, relationship)? {
+                Some(relation_id) => {
+                    db.remove_relation(relation_id)?;
+                    info!("\x1b[31mRemoved Edge: \"{}\" - [{}] -> \"{}\"\x1b[0m", source, relationship, target);
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        })
+    }
+
+    /// Removes the node named `node` together with all of its edges
+    ///
+    /// Returns `false` if there was no such node
+    pub fn remove_node(&mut self, node: &str) -> Result<bool, ReachdbError> {
+        self.transaction(|db| {
+            let node_id = match db.get_node_id(node)? {
+                Some(id) => id,
+                None => return Ok(false),
+            };
+            for relation_id in db.get_all_node_relations(node_id)? {
+                db.remove_relation(relation_id)?;
+            }
+            db.drop_node(node_id)?;
+            info!("\x1b[31mRemoved Node: \"{}\"(id:{})\x1b[0m", node, node_id);
+            Ok(true)
+        })
+    }
+
+    /// Tombstones a node without relations, frees its slot and drops its properties, name and aliases
+    pub(crate) fn drop_node(&mut self, node_id: u64) -> Result<(), ReachdbError> {
+        let record = self.get_node(node_id)?;
+        let name = self.get_property(record.first_property_id)?;
+        self.clear_properties(record.first_property_id)?;
+        self.write_node(node_id, &NodeRecord::tombstone())?;
+        self.free_node_ids.push(node_id);
+
+        // Drop the name mappings
+        self.sled_remove(SledTree::NodeId, name.as_bytes())?;
+        self.sled_remove(SledTree::Property, &bincode::serialize(&record.first_property_id)?)?;
+        for alias in self.aliases_of(node_id)? {
+            self.sled_remove(SledTree::NodeAlias, alias.as_bytes())?;
+        }
+        self.unindex_node_name(node_id, &name)?;
+        self.clear_embedding(node_id)?;
+        Ok(())
+    }
+
+    /// Ids of all the nodes that are not removed
+    pub fn get_all_node_ids(&self) -> Result<Vec<u64>, ReachdbError> {
+        let mut ids = Vec::new();
+        for node_id in 0..self.node_count {
+            if !self.get_node(node_id)?.is_deleted() {
+                ids.push(node_id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Ids of all the relations that are not removed
+    pub fn get_all_relation_ids(&self) -> Result<Vec<u64>, ReachdbError> {
+        let mut ids = Vec::new();
+        for rel_id in 0..self.relationship_count {
+            if !self.get_relation(rel_id)?.is_deleted() {
+                ids.push(rel_id);
+            }
+        }
+        Ok(ids)
+    }
+
+    pub(crate) fn get_or_add_node_id(&mut self, node: &str) -> Result<u64, ReachdbError> {
 
         // Check if the String is already mapped
-        if let Some(id_bytes) = db.get(node)? {
-            let id = bincode::deserialize::<u64>(&id_bytes)?;
+        if let Some(id) = self.get_node_id(node)? {
             info!("Found: \"{}\"(id:{})", node, id);
             return Ok(id);
         }
 
-        // Reterieve and update the counter
-        let new_id = self.node_count;
+        // Reterieve and update the counter, reusing the slot of a removed node first
+        let new_id = match self.free_node_ids.last() {
+            Some(id) => *id,
+            None => self.node_count,
+        };
         let new_property_id = self.property_count; // property_id != node_id, because it consists of edge properties as well
 
         // Insert the mapping: string -> new_id, and update the counter.
-        db.insert(node, bincode::serialize(&new_id)?)?;
-        db.flush()?; // Ensure data is persisted
+        self.sled_insert(SledTree::NodeId, node.as_bytes(), bincode::serialize(&new_id)?)?;
         
         // Insert the mapping: new_id -> node
-        property_db.insert(bincode::serialize(&new_property_id)?, bincode::serialize(&node)?)?;
-        property_db.flush()?; // Ensure data is persisted
+        self.sled_insert(SledTree::Property, &bincode::serialize(&new_property_id)?, bincode::serialize(&node)?)?;
+
+        // Other spellings of the name find the node through its normalized form
+        if let Some(normalized) = self.normalized_name(node)
+            && normalized != node
+        {
+            self.sled_insert(SledTree::NodeAlias, normalized.as_bytes(), bincode::serialize(&new_id)?)?;
+        }
+        self.index_node_name(new_id, node)?;
 
         info!("Added: \"{}\"(id:{})", node, new_id);
         
         // Adding the NodeRecord
-        let node_mmap = self.mmap
-            .as_mut()
-            .expect("Mmap not initialized")
-            .get_node_as_mut();
-
-        NodeRecord::new(new_id, new_property_id).write(node_mmap, new_id)?;
+        self.write_node(new_id, &NodeRecord::new(new_id, new_property_id))?;
         info!("Added new NodeRecord \"{}\"(id:{})", node, new_id);
         
-        self.node_count += 1; // Increment the counter
+        if self.free_node_ids.pop().is_none() {
+            self.node_count += 1; // Increment the counter
+        }
         self.property_count += 1; // Increment the counter
         
         Ok(new_id)
     }
 
-    fn get_type_id(relation: &str) -> Option<u8> {
-        debug!("Relation id: {}", relation);
-        match E::get_type_id(relation) {
-            Some(rel_type) => Some(rel_type.type_id()),
-            None => None
-        }
-    }
     pub fn get_property(&self, property_id: u64) -> Result<String, ReachdbError> {
-        let property_db = sled::open(&Self::get_db_path(&self.path)[1])?;
-        match property_db.get(bincode::serialize(&property_id)?)? {
-            Some(property) => Ok(bincode::deserialize::<String>(&property)?),
+        if let Some(property) = self.property_cache.get(property_id) {
+            return Ok(property);
+        }
+        let key = bincode::serialize(&property_id)?;
+        match self.sled_get(SledTree::Property, &key)? {
+            Some(property) => {
+                let property = bincode::deserialize::<String>(&property)?;
+                self.property_cache.insert(property_id, property.clone());
+                Ok(property)
+            }
             None => Err(ReachdbError::OtherError(format!("Property not found for property_id: {}", property_id)))
         }
     }
-    pub fn add_edge(&mut self, source: &str, target: &str, relationship: &str) -> Result<(), ReachdbError> {
-        let src_id = self.get_or_add_node_id(source)?;
-        let tgt_id = self.get_or_add_node_id(target)?;
-        let type_id = match Self::get_type_id(relationship) {
-            Some(id) => id,
-            None => {
-                info!("\x1b[31mError: Relation type '{}' not found, skipping edge\x1b[0m", relationship);
-                return Ok(());
-            }
-        };
+    /// Name of the relation type `type_id` in `E` or the registry, or `type-<id>` when it is unknown
+    pub fn get_relation_type_str(&self, type_id: u8) -> String {
+        E::get_type_str(type_id)
+            .or_else(|| self.relation_registry.types.get(&type_id).map(|info| info.name.clone()))
+            .unwrap_or_else(|| format!("type-{}", type_id))
+    }
+    /// Name of the node, stored as its first property
+    pub fn get_node_name(&self, node_id: u64) -> Result<String, ReachdbError> {
+        let node = self.get_node(node_id)?;
+        if node.is_deleted() {
+            return Err(ReachdbError::OtherError(format!("Node {} is removed", node_id)));
+        }
+        self.get_property(node.first_property_id)
+    }
+    /// Adds the `source` -[`relationship`]-> `target` edge, creating the nodes if needed
+    ///
+    /// Returns the id of the new or already existing relation, `None` if the relation type is unknown
+    pub fn add_edge(&mut self, source: &str, target: &str, relationship: &str) -> Result<Option<u64>, ReachdbError> {
+        self.add_edge_seen(source, target, relationship, None)
+    }
 
-        if !self.if_edge_exists(&src_id, &tgt_id, &type_id)? {
-            // Add the relationship
-            self.add_relation(&src_id, &tgt_id, &type_id)?;
-            info!("\x1b[32mAdded Edge: \"{}\"(id:{}) - [{}] -> \"{}\"(id:{})\x1b[0m", source, src_id, relationship, target, tgt_id);
-        } else {
-            info!("\x1b[33mFound Edge: \"{}\"(id:{}) - [{}] -> \"{}\"(id:{})\x1b[0m", source, src_id, relationship, target, tgt_id);
+    /// Adds the edge, or counts one more sighting of it when it exists
+    pub(crate) fn add_edge_seen(&mut self, source: &str, target: &str, relationship: &str, evidence: Option<&str>) -> Result<Option<u64>, ReachdbError> {
+        self.transaction(|db| {
+            // Resolved first, so an unknown relation type leaves no orphan nodes behind
+            let type_id = match db.resolve_relation_type(relationship)? {
+                Some(id) => id,
+                None => {
+                    info!("\x1b[31mError: Relation type '{}' not found, skipping edge\x1b[0m", relationship);
+                    return Ok(None);
+                }
+            };
+            let src_id = db.get_or_add_node_id(source)?;
+            let tgt_id = db.get_or_add_node_id(target)?;
+
+            let (relation_id, created) = match db.if_edge_exists(&src_id, &tgt_id, &type_id)? {
+                None => {
+                    // Add the relationship
+                    let relation_id = db.add_relation(&src_id, &tgt_id, &type_id)?;
+                    info!("\x1b[32mAdded Edge: \"{}\"(id:{}) - [{}] -> \"{}\"(id:{})\x1b[0m", source, src_id, relationship, target, tgt_id);
+                    (relation_id, true)
+                }
+                Some(relation_id) => {
+                    info!("\x1b[33mFound Edge: \"{}\"(id:{}) - [{}] -> \"{}\"(id:{})\x1b[0m", source, src_id, relationship, target, tgt_id);
+                    (relation_id, false)
+                }
+            };
+            db.record_sighting(relation_id, evidence, created, unix_time())?;
+
+            Ok(Some(relation_id))
+        })
+    }
+
+    fn set_property(&mut self, owner_property_id: u64, key: &str, value: &PropertyValue) -> Result<(), ReachdbError> {
+        self.sled_insert(
+            SledTree::PropertyKv,
+            &PropertyValue::storage_key(owner_property_id, key),
+            bincode::serialize(value)?
+        )
+    }
+
+    fn get_properties(&self, owner_property_id: u64) -> Result<BTreeMap<String, PropertyValue>, ReachdbError> {
+        let mut properties = BTreeMap::new();
+        if owner_property_id == NULL_OFFSET {
+            return Ok(properties);
+        }
+        for (key, value) in self.sled_scan_prefix(SledTree::PropertyKv, &owner_property_id.to_be_bytes())? {
+            properties.insert(
+                PropertyValue::key_from_storage(&key)?,
+                bincode::deserialize::<PropertyValue>(&value)?
+            );
         }
+        Ok(properties)
+    }
 
+    pub(crate) fn clear_properties(&mut self, owner_property_id: u64) -> Result<(), ReachdbError> {
+        if owner_property_id == NULL_OFFSET {
+            return Ok(());
+        }
+        for (key, _) in self.sled_scan_prefix(SledTree::PropertyKv, &owner_property_id.to_be_bytes())? {
+            self.sled_remove(SledTree::PropertyKv, &key)?;
+        }
         Ok(())
     }
 
+    /// Sets the property `key` of a relation, overwriting the previous value
+    pub fn set_edge_property(&mut self, relation_id: u64, key: &str, value: PropertyValue) -> Result<(), ReachdbError> {
+        self.transaction(|db| {
+            let mut relation = db.get_relation(relation_id)?;
+            if relation.is_deleted() {
+                return Err(ReachdbError::OtherError(format!("Relation {} is removed", relation_id)));
+            }
+
+            // Relations get their property id on the first property
+            if relation.first_property_id == NULL_OFFSET {
+                relation.first_property_id = db.property_count;
+                db.property_count += 1;
+                db.write_relation(relation_id, &relation)?;
+            }
+            db.set_property(relation.first_property_id, key, &value)
+        })
+    }
+
+    /// All the properties of a relation, sorted by key
+    pub fn get_edge_properties(&self, relation_id: u64) -> Result<BTreeMap<String, PropertyValue>, ReachdbError> {
+        let relation = self.get_relation(relation_id)?;
+        self.get_properties(relation.first_property_id)
+    }
+
+    /// Sets the property `key` of a node, overwriting the previous value
+    pub fn set_node_property(&mut self, node_id: u64, key: &str, value: PropertyValue) -> Result<(), ReachdbError> {
+        let node = self.get_node(node_id)?;
+        if node.is_deleted() {
+            return Err(ReachdbError::OtherError(format!("Node {} is removed", node_id)));
+        }
+        // The id of the node's name doubles as the owner id of its other properties
+        self.transaction(|db| db.set_property(node.first_property_id, key, &value))
+    }
+
+    /// All the properties of a node, sorted by key
+    pub fn get_node_properties(&self, node_id: u64) -> Result<BTreeMap<String, PropertyValue>, ReachdbError> {
+        let node = self.get_node(node_id)?;
+        self.get_properties(node.first_property_id)
+    }
+
     pub fn print_graph(&self) -> Result<(), ReachdbError> {
-        let (node_mmap, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
         println!("Priniting Records");
         for node_id in 0..self.node_count {
-            info!("{:#?}", NodeRecord::read(node_mmap, node_id));
+            let node = self.get_node(node_id)?;
+            if !node.is_deleted() {
+                info!("{:#?}", node);
+            }
         }
         for rel_id in 0..self.relationship_count {
-            info!("{:#?}", RelationshipRecord::read(relation_mmap, rel_id));
+            let relation = self.get_relation(rel_id)?;
+            if !relation.is_deleted() {
+                info!("{:#?}", relation);
+            }
         }
         Ok(())
     }
 
     pub fn get_recent_edges(&self, k: u64) -> Result<Vec<RelationshipRecord>, ReachdbError> {
-        let (_, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
         let mut edges = Vec::new();
-        for rel_id in (self.relationship_count - k)..self.relationship_count {
-            edges.push(RelationshipRecord::read(relation_mmap, rel_id)?);
+        for rel_id in (0..self.relationship_count).rev() {
+            if edges.len() as u64 == k {
+                break;
+            }
+            let relation = self.get_relation(rel_id)?;
+            if !relation.is_deleted() {
+                edges.push(relation);
+            }
         }
+        edges.reverse();
         Ok(edges)
     }
 
     pub fn get_edge_from_rel_id(&self, rel_id: u64) -> Result<(String, String, u8), ReachdbError> {
-        let (_, relation_mmap) = match self.mmap.as_ref() {
-            Some(mmap) => mmap.take_as_ref(),
-            None => return Err(ReachdbError::OtherError("Mmap not initialized".to_string())),
-        };
-        let rel = RelationshipRecord::read(relation_mmap, rel_id)?;
-        let src = self.get_property(rel.source_id)?;
-        let tgt = self.get_property(rel.target_id)?;
+        let rel = self.get_relation(rel_id)?;
+        let src = self.get_node_name(rel.source_id)?;
+        let tgt = self.get_node_name(rel.target_id)?;
 
         Ok((src, tgt, rel.type_id))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::wal::{WriteAheadLog, SYNCS};
+    use super::{Reachdb, ReachdbError};
+
+    #[test]
+    fn mmaps_grow_past_initial_size() {
+        let (dir, mut db) = temp_db(Some(64), Some(64));
+        for i in 0..50 {
+            db.add_edge(&format!("concept {}", i), &format!("concept {}", i + 1), "RELATES-TO").unwrap();
+        }
+        assert!(db.node_mmap_size >= 51 * 24);
+        db.close().unwrap();
+
+        // The grown sizes are persisted, so reopening without sizes keeps every record
+        let path = dir.path().to_str().unwrap();
+        let db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert_eq!(db.relationship_count, 50);
+        for rel_id in 0..50 {
+            let (src, tgt, _) = db.get_edge_from_rel_id(rel_id).unwrap();
+            assert_eq!(src, format!("concept {}", rel_id));
+            assert_eq!(tgt, format!("concept {}", rel_id + 1));
+        }
+
+        // A smaller requested size must not truncate the files
+        drop(db);
+        let db = Reachdb::<TypeId>::open(path, Some(64), Some(64)).unwrap();
+        assert!(db.get_relation(49).is_ok());
+    }
+
+    fn sorted_relations(db: &Reachdb<TypeId>, node: &str) -> Vec<u64> {
+        let mut relations = db.get_all_node_relations(node_id(db, node)).unwrap();
+        relations.sort();
+        relations
+    }
+
+    #[test]
+    fn chains_keep_source_and_target_links_apart() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("X", "A", "IS-A").unwrap();         // 0: A is the target
+        db.add_edge("A", "Y", "INFLUENCES").unwrap();   // 1: A is the source
+        db.add_edge("A", "A", "RELATES-TO").unwrap();   // 2: self-loop
+
+        assert_eq!(sorted_relations(&db, "X"), vec![0]);
+        assert_eq!(sorted_relations(&db, "A"), vec![0, 1, 2]);
+        assert_eq!(sorted_relations(&db, "Y"), vec![1]);
+    }
+
+    #[test]
+    fn remove_edge_unlinks_both_chains() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();         // 0
+        db.add_edge("A", "C", "IS-A").unwrap();         // 1
+        db.add_edge("C", "A", "INFLUENCES").unwrap();   // 2
+        db.add_edge("B", "A", "RELATES-TO").unwrap();   // 3
+
+        // Middle of A's chain, then its head and its tail
+        assert!(db.remove_edge("A", "C", "IS-A").unwrap());
+        assert_eq!(sorted_relations(&db, "A"), vec![0, 2, 3]);
+        assert_eq!(sorted_relations(&db, "C"), vec![2]);
+        assert!(db.remove_edge("B", "A", "RELATES-TO").unwrap());
+        assert_eq!(sorted_relations(&db, "A"), vec![0, 2]);
+        assert_eq!(sorted_relations(&db, "B"), vec![0]);
+        assert!(db.remove_edge("A", "B", "IS-A").unwrap());
+        assert_eq!(sorted_relations(&db, "A"), vec![2]);
+        assert!(sorted_relations(&db, "B").is_empty());
+
+        assert!(!db.remove_edge("A", "B", "IS-A").unwrap());
+        assert_eq!(db.get_all_relation_ids().unwrap(), vec![2]);
+        assert_eq!(db.get_recent_edges(5).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("Transformer", "Architecture", "IS-A").unwrap();
+        db.add_edge("Transformer", "Hallucination", "INFLUENCES").unwrap();
+        db.add_edge("Hallucination", "Attention", "RELATES-TO").unwrap();
+
+        assert!(db.remove_node("Hallucination").unwrap());
+        assert!(!db.remove_node("Hallucination").unwrap());
+        assert_eq!(db.get_node_id("Hallucination").unwrap(), None);
+        assert_eq!(sorted_relations(&db, "Transformer"), vec![0]);
+        assert!(sorted_relations(&db, "Attention").is_empty());
+        assert_eq!(db.get_all_node_ids().unwrap(), vec![0, 1, 3]);
+
+        // The freed node and relation slots are taken before the counts grow
+        db.add_edge("Transformer", "Encoder", "RELATES-TO").unwrap();
+        db.add_edge("Encoder", "Attention", "RELATES-TO").unwrap();
+        assert_eq!(db.node_count, 4);
+        assert_eq!(db.relationship_count, 3);
+        assert_eq!(db.get_node_id("Encoder").unwrap(), Some(2));
+        let mut edges = vec![db.get_edge_from_rel_id(1).unwrap(), db.get_edge_from_rel_id(2).unwrap()];
+        edges.sort();
+        assert_eq!(edges, vec![
+            ("Encoder".to_string(), "Attention".to_string(), 1),
+            ("Transformer".to_string(), "Encoder".to_string(), 1),
+        ]);
+    }
+
+    #[test]
+    fn edge_and_node_properties() {
+        use crate::PropertyValue;
+
+        let (dir, mut db) = temp_db(None, None);
+        let rel_id = db.add_edge("Diffusion", "Score Matching", "RELATES-TO").unwrap().unwrap();
+        assert_eq!(db.add_edge("Diffusion", "Score Matching", "RELATES-TO").unwrap(), Some(rel_id));
+        assert_eq!(db.add_edge("Diffusion", "Score Matching", "UNKNOWN").unwrap(), None);
+        assert!(db.get_edge_properties(rel_id).unwrap().is_empty());
+
+        db.set_edge_property(rel_id, "source_url", "https://arxiv.org".into()).unwrap();
+        db.set_edge_property(rel_id, "confidence", PropertyValue::Float(0.5)).unwrap();
+        db.set_edge_property(rel_id, "confidence", PropertyValue::Float(0.9)).unwrap();
+        db.set_edge_property(rel_id, "seen", PropertyValue::Timestamp(1_700_000_000)).unwrap();
+        let diffusion = node_id(&db, "Diffusion");
+        db.set_node_property(diffusion, "papers", PropertyValue::Int(3)).unwrap();
+        db.close().unwrap();
+
+        let db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        let properties = db.get_edge_properties(rel_id).unwrap();
+        assert_eq!(properties.keys().collect::<Vec<_>>(), vec!["confidence", "seen", "source_url"]);
+        assert_eq!(properties["confidence"], PropertyValue::Float(0.9));
+        assert_eq!(properties["source_url"].as_str(), Some("https://arxiv.org"));
+        assert_eq!(db.get_node_properties(diffusion).unwrap()["papers"], PropertyValue::Int(3));
+
+        // Properties of the other node and its name are untouched
+        let other_id = node_id(&db, "Score Matching");
+        assert!(db.get_node_properties(other_id).unwrap().is_empty());
+        assert_eq!(db.get_node_name(diffusion).unwrap(), "Diffusion");
+    }
+
+    #[test]
+    fn removal_drops_properties() {
+        let (_dir, mut db) = temp_db(None, None);
+        let rel_id = db.add_edge("A", "B", "IS-A").unwrap().unwrap();
+        db.set_edge_property(rel_id, "query", "what is A".into()).unwrap();
+        let node = node_id(&db, "B");
+        db.set_node_property(node, "note", "leaf".into()).unwrap();
+
+        db.remove_node("B").unwrap();
+        let rel_id = db.add_edge("A", "C", "IS-A").unwrap().unwrap();
+        let node = node_id(&db, "C");
+        assert!(db.get_edge_properties(rel_id).unwrap().is_empty());
+        assert!(db.get_node_properties(node).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_transaction_rolls_back() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+
+        let result: Result<(), ReachdbError> = db.transaction(|db| {
+            db.add_edge("A", "C", "IS-A")?;
+            assert_eq!(db.get_node_name(2)?, "C");
+            db.remove_node("B")?;
+            Err(ReachdbError::OtherError("abort".to_string()))
+        });
+        assert!(result.is_err());
+
+        assert!(db.get_node_id("C").unwrap().is_none());
+        assert!(db.find_edge("A", "B", "IS-A").unwrap().is_some());
+        assert_eq!(db.get_all_node_ids().unwrap().len(), 2);
+        assert_eq!(db.get_all_relation_ids().unwrap().len(), 1);
+
+        // The counters are restored, so the next node takes the slot C had
+        db.add_edge("A", "D", "IS-A").unwrap();
+        assert_eq!(db.get_node_id("D").unwrap(), Some(2));
+        assert_eq!(db.get_node_name(2).unwrap(), "D");
+    }
+
+    #[test]
+    fn failed_commit_rolls_back() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        // A directory in place of the log cannot be written
+        let wal = Reachdb::<TypeId>::get_db_path(&db.path)[5].clone();
+        std::fs::create_dir(&wal).unwrap();
+        assert!(db.add_edge("A", "C", "IS-A").is_err());
+        assert!(db.get_node_id("C").unwrap().is_none());
+        std::fs::remove_dir(&wal).unwrap();
+
+        db.add_edge("A", "D", "IS-A").unwrap();
+        assert_eq!(db.get_node_id("D").unwrap(), Some(2));
+    }
+
+    #[test]
+    fn cached_names_follow_removals_and_rollbacks() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        let b = node_id(&db, "B");
+        let b_property = db.get_node(b).unwrap().first_property_id;
+        assert_eq!(db.get_node_name(b).unwrap(), "B");
+
+        // Key/value properties live next to the name and leave the cached name alone
+        db.set_node_property(b, "note", "leaf".into()).unwrap();
+        db.clear_properties(b_property).unwrap();
+        assert!(db.get_node_properties(b).unwrap().is_empty());
+        assert_eq!(db.get_property(b_property).unwrap(), "B");
+
+        db.remove_node("B").unwrap();
+        assert!(db.get_property(b_property).is_err());
+
+        // A rolled back node frees its property id, the next node takes it under another name
+        let mut c_property = None;
+        let failed: Result<(), ReachdbError> = db.transaction(|db| {
+            db.add_edge("A", "C", "IS-A")?;
+            let c = db.get_node_id("C")?.unwrap();
+            assert_eq!(db.get_node_name(c)?, "C");
+            c_property = Some(db.get_node(c)?.first_property_id);
+            Err(ReachdbError::OtherError("abort".to_string()))
+        });
+        assert!(failed.is_err());
+        db.add_edge("A", "D", "IS-A").unwrap();
+        let d = node_id(&db, "D");
+        let d_property = db.get_node(d).unwrap().first_property_id;
+        assert_eq!(Some(d_property), c_property);
+        assert_eq!(db.get_property(d_property).unwrap(), "D");
+        assert_eq!(db.get_node_name(d).unwrap(), "D");
+    }
+
+    #[test]
+    fn panicking_transaction_rolls_back() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.transaction(|db| -> Result<(), ReachdbError> {
+                db.add_edge("A", "C", "IS-A")?;
+                panic!("extraction failed");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(db.wal.is_none());
+        assert!(db.get_node_id("C").unwrap().is_none());
+
+        // The next transaction runs on its own
+        db.add_edge("A", "D", "IS-A").unwrap();
+        db.close().unwrap();
+        let db = Reachdb::<TypeId>::open(&db.path, None, None).unwrap();
+        assert_eq!(db.get_node_id("D").unwrap(), Some(2));
+    }
+
+    /// Offset of the last entry of a log, each framed as `len: u32 | crc32: u32 | payload`
+    fn last_entry_offset(log: &[u8]) -> usize {
+        let mut offset = 0;
+        loop {
+            let len = u32::from_le_bytes(log[offset..offset + 4].try_into().unwrap()) as usize;
+            if offset + 8 + len == log.len() {
+                return offset;
+            }
+            offset += 8 + len;
+        }
+    }
+
+    #[test]
+    fn log_cut_before_its_commit_marker_is_dropped_on_open() {
+        let cuts: [fn(&mut Vec<u8>); 3] = [
+            // Torn partway into the commit marker
+            |log| log.truncate(log.len() - 3),
+            // Commit marker with a bad checksum
+            |log| *log.last_mut().unwrap() ^= 0xff,
+            // Commit marker never written
+            |log| log.truncate(last_entry_offset(log)),
+        ];
+        for cut in cuts {
+            let (dir, mut db) = temp_db(Some(64), Some(64));
+            db.add_edge("A", "B", "IS-A").unwrap();
+            db.close().unwrap();
+
+            // Write a real log by hand, then damage its tail as a crash during the commit could
+            let mut db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+            let wal_path = Reachdb::<TypeId>::get_db_path(&db.path)[5].clone();
+            db.wal = Some(WriteAheadLog::begin(&wal_path, serde_json::to_string(&db).unwrap()));
+            for i in 0..20 {
+                db.add_edge("A", &format!("C{}", i), "RELATES-TO").unwrap();
+            }
+            db.remove_edge("A", "B", "IS-A").unwrap();
+            let wal = db.wal.take().unwrap();
+            wal.commit(serde_json::to_string(&db).unwrap()).unwrap();
+            drop(db);
+            let mut log = std::fs::read(&wal_path).unwrap();
+            cut(&mut log);
+            std::fs::write(&wal_path, &log).unwrap();
+
+            let db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+            assert!(!std::path::Path::new(&wal_path).exists());
+            assert_eq!(db.get_all_node_ids().unwrap(), vec![0, 1]);
+            assert_eq!(db.get_all_relation_ids().unwrap(), vec![0]);
+            assert!(db.get_node_id("C0").unwrap().is_none());
+            assert_eq!(db.get_all_node_relations(0).unwrap(), vec![0]);
+        }
+    }
+
+    #[test]
+    fn transaction_syncs_the_log_once() {
+        let (_dir, mut db) = temp_db(None, None);
+        let syncs = SYNCS.with(|syncs| syncs.get());
+        db.transaction(|db| {
+            for i in 0..50 {
+                db.add_edge(&format!("A{}", i), &format!("B{}", i), "IS-A")?;
+                db.set_edge_property(i, "query", "batch".into())?;
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(SYNCS.with(|syncs| syncs.get()) - syncs, 1);
+
+        // Nothing is written to the log before the commit
+        let syncs = SYNCS.with(|syncs| syncs.get());
+        let failed: Result<(), ReachdbError> = db.transaction(|db| {
+            db.add_edge("A0", "C", "IS-A")?;
+            Err(ReachdbError::OtherError("abort".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(SYNCS.with(|syncs| syncs.get()), syncs);
+        assert_eq!(db.get_all_relation_ids().unwrap().len(), 50);
+    }
+
+    #[test]
+    fn transaction_reads_its_own_changes() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        db.transaction(|db| {
+            db.add_edge("A", "C", "IS-A")?;
+            let c = db.get_node_id("C")?.unwrap();
+            db.set_node_property(c, "note", "new".into())?;
+            assert_eq!(db.get_outgoing_node_relations(0)?.len(), 2);
+            assert_eq!(db.get_node_properties(c)?["note"].as_str(), Some("new"));
+            db.remove_edge("A", "B", "IS-A")?;
+            assert!(db.find_edge("A", "B", "IS-A")?.is_none());
+            assert_eq!(db.get_all_relation_ids()?, vec![1]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(db.get_all_node_relations(0).unwrap(), vec![1]);
+        assert_eq!(db.get_node_properties(2).unwrap()["note"].as_str(), Some("new"));
+    }
+
+    #[test]
+    fn committed_transaction_is_replayed_on_open() {
+        let (dir, mut db) = temp_db(Some(64), Some(64));
+        db.add_edge("A", "B", "IS-A").unwrap();
+        db.close().unwrap();
+
+        // Commit a transaction by hand and drop the database before its changes are applied
+        let mut db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        db.wal = Some(WriteAheadLog::begin(&Reachdb::<TypeId>::get_db_path(&db.path)[5], serde_json::to_string(&db).unwrap()));
+        for i in 0..20 {
+            db.add_edge("A", &format!("C{}", i), "RELATES-TO").unwrap();
+        }
+        db.remove_edge("A", "B", "IS-A").unwrap();
+        let wal = db.wal.take().unwrap();
+        wal.commit(serde_json::to_string(&db).unwrap()).unwrap();
+        drop(db);
+
+        let db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        assert!(!std::path::Path::new(&Reachdb::<TypeId>::get_db_path(&db.path)[5]).exists());
+        assert_eq!(db.get_all_node_ids().unwrap().len(), 22);
+        assert_eq!(db.get_all_relation_ids().unwrap().len(), 20);
+        assert!(db.find_edge("A", "B", "IS-A").unwrap().is_none());
+        assert_eq!(db.get_node_id("C19").unwrap(), Some(21));
+        assert_eq!(db.get_outgoing_node_relations(0).unwrap().len(), 20);
+    }
+}
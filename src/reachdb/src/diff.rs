@@ -0,0 +1,244 @@
+//! Differences between two databases and merging one into another
+//!
+//! Ids are local to a database, so nodes are matched by name and relations by their source
+//! name, relation type string and target name.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, provenance::Provenance, utils::unix_time};
+
+/// A relation identified by names instead of ids
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EdgeTriple {
+    pub source: String,
+    pub relation: String,
+    pub target: String,
+}
+
+impl fmt::Display for EdgeTriple {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"{}\" -[{}]-> \"{}\"", self.source, self.relation, self.target)
+    }
+}
+
+/// What changed from one database to another, everything sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphDiff {
+    /// Nodes only the other database has
+    pub added_nodes: Vec<String>,
+    /// Nodes only this database has
+    pub removed_nodes: Vec<String>,
+    pub added_edges: Vec<EdgeTriple>,
+    pub removed_edges: Vec<EdgeTriple>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty() && self.removed_nodes.is_empty()
+            && self.added_edges.is_empty() && self.removed_edges.is_empty()
+    }
+}
+
+/// One line per change, `+` for added and `-` for removed, like a unified diff
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.removed_nodes {
+            writeln!(f, "- node \"{}\"", node)?;
+        }
+        for node in &self.added_nodes {
+            writeln!(f, "+ node \"{}\"", node)?;
+        }
+        for edge in &self.removed_edges {
+            writeln!(f, "- edge {}", edge)?;
+        }
+        for edge in &self.added_edges {
+            writeln!(f, "+ edge {}", edge)?;
+        }
+        write!(
+            f, "Nodes: +{} -{}, edges: +{} -{}",
+            self.added_nodes.len(), self.removed_nodes.len(), self.added_edges.len(), self.removed_edges.len()
+        )
+    }
+}
+
+/// Result of `merge_from`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphMergeSummary {
+    /// Nodes the database did not have
+    pub nodes_added: u64,
+    /// Relations the database did not have
+    pub edges_added: u64,
+    /// Relations it already had, their provenance and missing properties were added
+    pub duplicates: u64,
+    /// Relations of a type unknown to the database, see `register_relation`
+    pub skipped: u64,
+}
+
+impl fmt::Display for GraphMergeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "Nodes added: {}, edges added: {}, duplicates: {}, skipped: {}",
+            self.nodes_added, self.edges_added, self.duplicates, self.skipped
+        )
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    fn node_names(&self) -> Result<BTreeSet<String>, ReachdbError> {
+        self.get_all_node_ids()?.into_iter().map(|id| self.get_node_name(id)).collect()
+    }
+
+    fn edge_triple(&self, relation_id: u64) -> Result<EdgeTriple, ReachdbError> {
+        let relation = self.get_relation(relation_id)?;
+        Ok(EdgeTriple {
+            source: self.get_node_name(relation.source_id)?,
+            relation: self.get_relation_type_str(relation.type_id),
+            target: self.get_node_name(relation.target_id)?,
+        })
+    }
+
+    fn edge_triples(&self) -> Result<BTreeSet<EdgeTriple>, ReachdbError> {
+        self.get_all_relation_ids()?.into_iter().map(|id| self.edge_triple(id)).collect()
+    }
+
+    /// Nodes and relations added and removed going from this database to `other`
+    ///
+    /// Names are compared exactly, aliases and name normalizers play no part.
+    pub fn diff(&self, other: &Self) -> Result<GraphDiff, ReachdbError> {
+        let (nodes, other_nodes) = (self.node_names()?, other.node_names()?);
+        let (edges, other_edges) = (self.edge_triples()?, other.edge_triples()?);
+        Ok(GraphDiff {
+            added_nodes: other_nodes.difference(&nodes).cloned().collect(),
+            removed_nodes: nodes.difference(&other_nodes).cloned().collect(),
+            added_edges: other_edges.difference(&edges).cloned().collect(),
+            removed_edges: edges.difference(&other_edges).cloned().collect(),
+        })
+    }
+
+    /// Adds the nodes and relations of `other` to this database, in one transaction
+    ///
+    /// Names go through the aliases and normalizer of this database, and a relation it already
+    /// has is not added twice but combines its provenance with the other one, see
+    /// `Provenance::combine`, so merging the same database again changes nothing. Properties
+    /// this database lacks are copied, existing values are kept.
+    pub fn merge_from(&mut self, other: &Self) -> Result<GraphMergeSummary, ReachdbError> {
+        self.transaction(|db| {
+            let mut summary = GraphMergeSummary::default();
+            for node_id in other.get_all_node_ids()? {
+                let name = other.get_node_name(node_id)?;
+                if db.get_node_id(&name)?.is_none() {
+                    summary.nodes_added += 1;
+                }
+                let id = db.get_or_add_node_id(&name)?;
+                let current = db.get_node_properties(id)?;
+                for (key, value) in other.get_node_properties(node_id)? {
+                    if !current.contains_key(&key) {
+                        db.set_node_property(id, &key, value)?;
+                    }
+                }
+            }
+
+            for relation_id in other.get_all_relation_ids()? {
+                let edge = other.edge_triple(relation_id)?;
+                let Some(type_id) = db.resolve_relation_type(&edge.relation)? else {
+                    summary.skipped += 1;
+                    continue;
+                };
+                let (Some(source), Some(target)) = (db.get_node_id(&edge.source)?, db.get_node_id(&edge.target)?) else {
+                    return Err(ReachdbError::OtherError(format!("Nodes of {} were not merged", edge)));
+                };
+                let (merged_id, created) = match db.if_edge_exists(&source, &target, &type_id)? {
+                    Some(existing) => (existing, false),
+                    None => (db.add_relation(&source, &target, &type_id)?, true),
+                };
+                let current = db.get_edge_properties(merged_id)?;
+                for (key, value) in other.get_edge_properties(relation_id)? {
+                    if !current.contains_key(&key) {
+                        db.set_edge_property(merged_id, &key, value)?;
+                    }
+                }
+                match other.get_edge_provenance(relation_id)? {
+                    Some(provenance) => {
+                        let mut merged = if created {
+                            Provenance::default()
+                        } else {
+                            db.get_edge_provenance(merged_id)?.unwrap_or_default()
+                        };
+                        merged.combine(provenance);
+                        db.set_edge_provenance(merged_id, &merged)?;
+                    }
+                    None if created => db.record_sighting(merged_id, None, true, unix_time())?,
+                    // A copy seen once adds nothing to a relation this database has
+                    None => {}
+                }
+                if created {
+                    summary.edges_added += 1;
+                } else {
+                    summary.duplicates += 1;
+                }
+            }
+            Ok(summary)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::Reachdb;
+    use super::{EdgeTriple, GraphMergeSummary};
+
+    /// Two databases sharing the GPT triple under other ids, each with a triple of its own
+    fn pair() -> ((tempfile::TempDir, Reachdb<TypeId>), (tempfile::TempDir, Reachdb<TypeId>)) {
+        let (a_dir, mut a) = temp_db(None, None);
+        let (b_dir, mut b) = temp_db(None, None);
+        a.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://a.org").unwrap();
+        a.add_edge("BERT", "Transformer", "IS-A").unwrap();
+        b.add_edge("Diffusion Model", "Generative Model", "IS-A").unwrap();
+        b.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://b.org").unwrap();
+        ((a_dir, a), (b_dir, b))
+    }
+
+    fn edge(source: &str, target: &str) -> EdgeTriple {
+        EdgeTriple { source: source.to_string(), relation: "IS-A".to_string(), target: target.to_string() }
+    }
+
+    #[test]
+    fn diffs_compare_nodes_and_edges_by_name() {
+        let ((_a_dir, a), (_b_dir, b)) = pair();
+        let diff = a.diff(&b).unwrap();
+        assert_eq!(diff.added_nodes, vec!["Diffusion Model", "Generative Model"]);
+        assert_eq!(diff.removed_nodes, vec!["BERT"]);
+        assert_eq!(diff.added_edges, vec![edge("Diffusion Model", "Generative Model")]);
+        assert_eq!(diff.removed_edges, vec![edge("BERT", "Transformer")]);
+        assert!(a.diff(&a).unwrap().is_empty());
+    }
+
+    #[test]
+    fn merges_add_what_is_missing_and_pool_provenance() {
+        let ((_a_dir, mut a), (_b_dir, b)) = pair();
+        let summary = a.merge_from(&b).unwrap();
+        assert_eq!(summary, GraphMergeSummary { nodes_added: 2, edges_added: 1, duplicates: 1, skipped: 0 });
+        let gpt = a.find_edge("GPT", "Transformer", "IS-A").unwrap().unwrap();
+        let provenance = a.get_edge_provenance(gpt).unwrap().unwrap();
+        assert_eq!((provenance.support, provenance.sources.len()), (2, 2));
+
+        let diff = a.diff(&b).unwrap();
+        assert!(diff.added_nodes.is_empty() && diff.added_edges.is_empty());
+        assert!(a.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn merging_again_is_not_more_evidence() {
+        let ((_a_dir, mut a), (_b_dir, b)) = pair();
+        a.merge_from(&b).unwrap();
+        assert_eq!(a.merge_from(&b).unwrap().duplicates, 2);
+
+        let gpt = a.find_edge("GPT", "Transformer", "IS-A").unwrap().unwrap();
+        let provenance = a.get_edge_provenance(gpt).unwrap().unwrap();
+        assert_eq!((provenance.support, provenance.sources.len()), (2, 2));
+        let diffusion = a.find_edge("Diffusion Model", "Generative Model", "IS-A").unwrap().unwrap();
+        assert_eq!(a.get_edge_provenance(diffusion).unwrap().unwrap().support, 1);
+    }
+}
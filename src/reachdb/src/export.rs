@@ -0,0 +1,432 @@
+//! Export of a whole graph to formats understood by other tools (Gephi, yEd, Graphviz, notebooks)
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    records::property::PropertyValue,
+};
+
+/// Namespace of the node ids in JSON-LD exports
+const JSON_LD_NODE_PREFIX: &str = "urn:reach:node:";
+/// Vocabulary the relation names of JSON-LD exports expand to
+const JSON_LD_VOCAB: &str = "urn:reach:vocab:";
+/// Compact IRI prefix of the property keys in JSON-LD exports, so no key can replace `@id` or `name`
+const JSON_LD_PROPERTY_PREFIX: &str = "prop";
+/// Prefix of the CSV property columns named like a fixed column of the table, or like a prefixed one
+const CSV_PROPERTY_PREFIX: &str = "prop:";
+/// Fixed columns of the CSV node and edge tables
+const CSV_NODE_COLUMNS: [&str; 2] = ["Id", "Label"];
+const CSV_EDGE_COLUMNS: [&str; 5] = ["Id", "Source", "Target", "Type", "Label"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    GraphMl,
+    Dot,
+    JsonLd,
+    /// A pair of files, `<stem>_nodes.csv` and `<stem>_edges.csv`
+    Csv,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ReachdbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "graphml" => Ok(Self::GraphMl),
+            "dot" | "graphviz" => Ok(Self::Dot),
+            "jsonld" | "json-ld" => Ok(Self::JsonLd),
+            "csv" => Ok(Self::Csv),
+            _ => Err(ReachdbError::OtherError(format!("Unknown export format: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GraphMl => write!(f, "graphml"),
+            Self::Dot => write!(f, "dot"),
+            Self::JsonLd => write!(f, "jsonld"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+struct ExportNode {
+    id: u64,
+    name: String,
+    properties: BTreeMap<String, PropertyValue>,
+}
+
+struct ExportEdge {
+    id: u64,
+    source_id: u64,
+    target_id: u64,
+    relation: String,
+    properties: BTreeMap<String, PropertyValue>,
+}
+
+/// Live nodes and relations with their names resolved
+struct Snapshot {
+    nodes: Vec<ExportNode>,
+    edges: Vec<ExportEdge>,
+}
+
+impl Snapshot {
+    fn node_keys(&self) -> BTreeSet<&str> {
+        self.nodes.iter().flat_map(|n| n.properties.keys().map(String::as_str)).collect()
+    }
+    fn edge_keys(&self) -> BTreeSet<&str> {
+        self.edges.iter().flat_map(|e| e.properties.keys().map(String::as_str)).collect()
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Writes the graph to `path` in the given format.
+    ///
+    /// For `ExportFormat::Csv`, a trailing `.csv` is stripped from `path` and the nodes and edges
+    /// are written to `<path>_nodes.csv` and `<path>_edges.csv`.
+    pub fn export(&self, format: ExportFormat, path: &str) -> Result<(), ReachdbError> {
+        match format {
+            ExportFormat::GraphMl => self.write_graphml(&mut BufWriter::new(File::create(path)?)),
+            ExportFormat::Dot => self.write_dot(&mut BufWriter::new(File::create(path)?)),
+            ExportFormat::JsonLd => self.write_json_ld(&mut BufWriter::new(File::create(path)?)),
+            ExportFormat::Csv => {
+                let stem = path.strip_suffix(".csv").unwrap_or(path);
+                self.write_csv(
+                    &mut BufWriter::new(File::create(format!("{}_nodes.csv", stem))?),
+                    &mut BufWriter::new(File::create(format!("{}_edges.csv", stem))?),
+                )
+            }
+        }
+    }
+
+    /// GraphML with the node names, relation names and all properties as string attributes
+    ///
+    /// Property keys are declared under numbered ids (`n0`, `n1`, … for nodes, `e0`, … for edges),
+    /// the key itself only appears in `attr.name`.
+    pub fn write_graphml<W: Write>(&self, w: &mut W) -> Result<(), ReachdbError> {
+        let snapshot = self.export_snapshot()?;
+        let node_keys = key_ids(snapshot.node_keys());
+        let edge_keys = key_ids(snapshot.edge_keys());
+
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(w, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+        writeln!(w, r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#)?;
+        writeln!(w, r#"  <key id="relation" for="edge" attr.name="relation" attr.type="string"/>"#)?;
+        for (key, index) in &node_keys {
+            writeln!(w, r#"  <key id="n{}" for="node" attr.name="{}" attr.type="string"/>"#, index, xml_escape(key))?;
+        }
+        for (key, index) in &edge_keys {
+            writeln!(w, r#"  <key id="e{}" for="edge" attr.name="{}" attr.type="string"/>"#, index, xml_escape(key))?;
+        }
+        writeln!(w, r#"  <graph id="reachdb" edgedefault="directed">"#)?;
+        for node in &snapshot.nodes {
+            writeln!(w, r#"    <node id="n{}">"#, node.id)?;
+            writeln!(w, r#"      <data key="name">{}</data>"#, xml_escape(&node.name))?;
+            for (key, value) in &node.properties {
+                writeln!(w, r#"      <data key="n{}">{}</data>"#, node_keys[key.as_str()], xml_escape(&value.to_string()))?;
+            }
+            writeln!(w, "    </node>")?;
+        }
+        for edge in &snapshot.edges {
+            writeln!(w, r#"    <edge id="e{}" source="n{}" target="n{}">"#, edge.id, edge.source_id, edge.target_id)?;
+            writeln!(w, r#"      <data key="relation">{}</data>"#, xml_escape(&edge.relation))?;
+            for (key, value) in &edge.properties {
+                writeln!(w, r#"      <data key="e{}">{}</data>"#, edge_keys[key.as_str()], xml_escape(&value.to_string()))?;
+            }
+            writeln!(w, "    </edge>")?;
+        }
+        writeln!(w, "  </graph>")?;
+        writeln!(w, "</graphml>")?;
+        Ok(())
+    }
+
+    /// Graphviz digraph, edges are labelled with their relation
+    pub fn write_dot<W: Write>(&self, w: &mut W) -> Result<(), ReachdbError> {
+        let snapshot = self.export_snapshot()?;
+
+        writeln!(w, "digraph reachdb {{")?;
+        for node in &snapshot.nodes {
+            writeln!(w, "  n{} [{}];", node.id, dot_attributes("label", &node.name, &node.properties))?;
+        }
+        for edge in &snapshot.edges {
+            writeln!(
+                w,
+                "  n{} -> n{} [{}];",
+                edge.source_id,
+                edge.target_id,
+                dot_attributes("label", &edge.relation, &edge.properties)
+            )?;
+        }
+        writeln!(w, "}}")?;
+        Ok(())
+    }
+
+    /// JSON-LD document with one object per node, relations become properties pointing at other nodes.
+    ///
+    /// Edge properties have no place in this shape and are left out.
+    pub fn write_json_ld<W: Write>(&self, w: &mut W) -> Result<(), ReachdbError> {
+        let snapshot = self.export_snapshot()?;
+
+        let mut objects: BTreeMap<u64, Map<String, Value>> = snapshot.nodes
+            .iter()
+            .map(|node| {
+                let mut object = Map::new();
+                object.insert("@id".to_string(), json!(format!("{}{}", JSON_LD_NODE_PREFIX, node.id)));
+                object.insert("name".to_string(), json!(node.name));
+                for (key, value) in &node.properties {
+                    object.insert(format!("{}:{}", JSON_LD_PROPERTY_PREFIX, key), json_value(value));
+                }
+                (node.id, object)
+            })
+            .collect();
+        for edge in &snapshot.edges {
+            let target = json!({ "@id": format!("{}{}", JSON_LD_NODE_PREFIX, edge.target_id) });
+            let object = objects.get_mut(&edge.source_id).expect("Edge source is exported");
+            // A relation named like a field of the node, `name` or `@id`, keeps the field
+            if let Value::Array(targets) = object.entry(edge.relation.clone()).or_insert_with(|| json!([])) {
+                targets.push(target);
+            }
+        }
+
+        let document = json!({
+            "@context": {
+                "@vocab": JSON_LD_VOCAB,
+                "name": "http://schema.org/name",
+                JSON_LD_PROPERTY_PREFIX: "urn:reach:property:",
+            },
+            "@graph": objects.into_values().map(Value::Object).collect::<Vec<_>>(),
+        });
+        serde_json::to_writer_pretty(&mut *w, &document)?;
+        writeln!(w)?;
+        Ok(())
+    }
+
+    /// Node and edge tables in the layout Gephi imports (`Id,Label` and `Source,Target,Type,Label`)
+    ///
+    /// A property named like one of these columns, compared without case, gets its column
+    /// prefixed with `prop:`, as does a property whose name already starts with it.
+    pub fn write_csv<N: Write, W: Write>(&self, nodes: &mut N, edges: &mut W) -> Result<(), ReachdbError> {
+        let snapshot = self.export_snapshot()?;
+
+        let mut nodes = csv_writer(nodes);
+        let node_keys = snapshot.node_keys();
+        let header: Vec<String> = CSV_NODE_COLUMNS.iter().map(|column| column.to_string())
+            .chain(node_keys.iter().map(|key| csv_column(key, &CSV_NODE_COLUMNS)))
+            .collect();
+        write_csv_row(&mut nodes, &header)?;
+        for node in &snapshot.nodes {
+            let mut row = vec![node.id.to_string(), node.name.clone()];
+            row.extend(node_keys.iter().map(|key| property_cell(&node.properties, key)));
+            write_csv_row(&mut nodes, &row)?;
+        }
+        nodes.flush()?;
+
+        let mut edges = csv_writer(edges);
+        let edge_keys = snapshot.edge_keys();
+        let header: Vec<String> = CSV_EDGE_COLUMNS.iter().map(|column| column.to_string())
+            .chain(edge_keys.iter().map(|key| csv_column(key, &CSV_EDGE_COLUMNS)))
+            .collect();
+        write_csv_row(&mut edges, &header)?;
+        for edge in &snapshot.edges {
+            let mut row = vec![
+                edge.id.to_string(),
+                edge.source_id.to_string(),
+                edge.target_id.to_string(),
+                "Directed".to_string(),
+                edge.relation.clone(),
+            ];
+            row.extend(edge_keys.iter().map(|key| property_cell(&edge.properties, key)));
+            write_csv_row(&mut edges, &row)?;
+        }
+        edges.flush()?;
+        Ok(())
+    }
+
+    fn export_snapshot(&self) -> Result<Snapshot, ReachdbError> {
+        let mut nodes = Vec::new();
+        for node_id in self.get_all_node_ids()? {
+            nodes.push(ExportNode {
+                id: node_id,
+                name: self.get_node_name(node_id)?,
+                properties: self.get_node_properties(node_id)?,
+            });
+        }
+        let mut edges = Vec::new();
+        for relation_id in self.get_all_relation_ids()? {
+            let relation = self.get_relation(relation_id)?;
+            edges.push(ExportEdge {
+                id: relation_id,
+                source_id: relation.source_id,
+                target_id: relation.target_id,
+                relation: self.get_relation_type_str(relation.type_id),
+                properties: self.get_edge_properties(relation_id)?,
+            });
+        }
+        Ok(Snapshot { nodes, edges })
+    }
+}
+
+/// Numbers the keys in order, for the GraphML key ids
+fn key_ids(keys: BTreeSet<&str>) -> BTreeMap<&str, usize> {
+    keys.into_iter().enumerate().map(|(index, key)| (key, index)).collect()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn dot_attributes(label_key: &str, label: &str, properties: &BTreeMap<String, PropertyValue>) -> String {
+    std::iter::once(format!("{}=\"{}\"", label_key, dot_escape(label)))
+        .chain(properties.iter().map(|(key, value)| format!("\"{}\"=\"{}\"", dot_escape(key), dot_escape(&value.to_string()))))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn json_value(value: &PropertyValue) -> Value {
+    match value {
+        PropertyValue::String(s) => json!(s),
+        PropertyValue::Int(i) => json!(i),
+        PropertyValue::Float(f) => json!(f),
+        PropertyValue::Timestamp(t) => json!(t),
+    }
+}
+
+fn property_cell(properties: &BTreeMap<String, PropertyValue>, key: &str) -> String {
+    properties.get(key).map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn csv_column(key: &str, fixed: &[&str]) -> String {
+    if key.starts_with(CSV_PROPERTY_PREFIX) || fixed.iter().any(|column| column.eq_ignore_ascii_case(key)) {
+        format!("{}{}", CSV_PROPERTY_PREFIX, key)
+    } else {
+        key.to_string()
+    }
+}
+
+/// RFC 4180 rows, read back by the `csv::Reader` of the importer
+fn csv_writer<W: Write>(w: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new().terminator(csv::Terminator::CRLF).from_writer(w)
+}
+
+fn write_csv_row<W: Write, S: AsRef<str>>(w: &mut csv::Writer<W>, cells: &[S]) -> Result<(), ReachdbError> {
+    w.write_record(cells.iter().map(|cell| cell.as_ref()))
+        .map_err(|e| ReachdbError::OtherError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::{PropertyValue, Reachdb};
+    use super::ExportFormat;
+
+    /// Names that need escaping, a node property shadowing the name and an edge property
+    fn names_needing_escapes() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = temp_db(None, None);
+        let rel_id = db.add_edge("Diffusion", "Generative Model", "IS-A").unwrap().unwrap();
+        let diffusion = node_id(&db, "Diffusion");
+        db.set_node_property(diffusion, "name", PropertyValue::from("shadowing")).unwrap();
+        db.add_edge("Score \"Matching\"", "Diffusion", "INFLUENCES").unwrap();
+        db.set_edge_property(rel_id, "source_url", PropertyValue::from("https://a.org/?x=1&y=2")).unwrap();
+        (dir, db)
+    }
+
+    /// Exports the database in `format` and returns the path of the file
+    fn export(dir: &tempfile::TempDir, db: &Reachdb<TypeId>, format: ExportFormat, file: &str) -> String {
+        let path = dir.path().join(file).to_str().unwrap().to_string();
+        db.export(format, &path).unwrap();
+        path
+    }
+
+    #[test]
+    fn graphml_escapes_names_and_values() {
+        let (dir, db) = names_needing_escapes();
+        let graphml = std::fs::read_to_string(export(&dir, &db, ExportFormat::GraphMl, "g.graphml")).unwrap();
+        assert!(graphml.contains(r#"<edge id="e0" source="n0" target="n1">"#));
+        assert!(graphml.contains(r#"<data key="relation">IS-A</data>"#));
+        assert!(graphml.contains("https://a.org/?x=1&amp;y=2"));
+        assert!(graphml.contains("Score &quot;Matching&quot;"));
+    }
+
+    #[test]
+    fn graphml_numbers_the_property_keys() {
+        let (dir, mut db) = names_needing_escapes();
+        let diffusion = node_id(&db, "Diffusion");
+        db.set_node_property(diffusion, "a\"b", PropertyValue::from("quoted")).unwrap();
+        let graphml = std::fs::read_to_string(export(&dir, &db, ExportFormat::GraphMl, "g.graphml")).unwrap();
+        assert!(graphml.contains(r#"<key id="n0" for="node" attr.name="a&quot;b" attr.type="string"/>"#));
+        assert!(graphml.contains(r#"<key id="n1" for="node" attr.name="name" attr.type="string"/>"#));
+        assert!(graphml.contains(r#"<key id="e0" for="edge" attr.name="source_url" attr.type="string"/>"#));
+        assert!(graphml.contains(r#"<data key="n0">quoted</data>"#));
+        assert!(graphml.contains(r#"<data key="n1">shadowing</data>"#));
+        assert!(graphml.contains(r#"<data key="e0">https://a.org/?x=1&amp;y=2</data>"#));
+    }
+
+    #[test]
+    fn dot_quotes_labels() {
+        let (dir, db) = names_needing_escapes();
+        let dot = std::fs::read_to_string(export(&dir, &db, ExportFormat::Dot, "g.dot")).unwrap();
+        assert!(dot.contains(r#"n2 [label="Score \"Matching\""];"#));
+        assert!(dot.contains(r#"n2 -> n0 [label="INFLUENCES"];"#));
+    }
+
+    #[test]
+    fn json_ld_keeps_properties_apart_from_the_name() {
+        let (dir, db) = names_needing_escapes();
+        let path = export(&dir, &db, ExportFormat::JsonLd, "g.jsonld");
+        let json_ld: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(json_ld["@graph"][0]["name"], "Diffusion");
+        assert_eq!(json_ld["@graph"][0]["IS-A"][0]["@id"], "urn:reach:node:1");
+        assert_eq!(json_ld["@graph"][0]["prop:name"], "shadowing");
+    }
+
+    #[test]
+    fn csv_writes_quoted_node_and_edge_tables() {
+        let (dir, db) = names_needing_escapes();
+        export(&dir, &db, ExportFormat::Csv, "g.csv");
+        let out = |name: &str| dir.path().join(name);
+
+        let nodes = std::fs::read_to_string(out("g_nodes.csv")).unwrap();
+        assert!(nodes.contains("2,\"Score \"\"Matching\"\"\",\r\n"));
+        let mut reader = csv::Reader::from_path(out("g_nodes.csv")).unwrap();
+        let names: Vec<String> = reader.records().map(|record| record.unwrap()[1].to_string()).collect();
+        assert_eq!(names, vec!["Diffusion", "Generative Model", "Score \"Matching\""]);
+        let edges = std::fs::read_to_string(out("g_edges.csv")).unwrap();
+        assert!(edges.starts_with("Id,Source,Target,Type,Label,source_url\r\n"));
+        assert!(edges.contains("0,0,1,Directed,IS-A,https://a.org/?x=1&y=2\r\n"));
+    }
+
+    #[test]
+    fn csv_prefixes_properties_named_like_a_column() {
+        let (dir, mut db) = names_needing_escapes();
+        let diffusion = node_id(&db, "Diffusion");
+        db.set_node_property(diffusion, "Label", PropertyValue::from("x")).unwrap();
+        db.set_node_property(diffusion, "prop:Label", PropertyValue::from("y")).unwrap();
+        let rel_id = db.find_edge("Diffusion", "Generative Model", "IS-A").unwrap().unwrap();
+        db.set_edge_property(rel_id, "type", PropertyValue::from("z")).unwrap();
+        export(&dir, &db, ExportFormat::Csv, "g.csv");
+
+        let nodes = std::fs::read_to_string(dir.path().join("g_nodes.csv")).unwrap();
+        assert!(nodes.starts_with("Id,Label,prop:Label,name,prop:prop:Label\r\n"));
+        assert!(nodes.contains("0,Diffusion,x,shadowing,y\r\n"));
+        let edges = std::fs::read_to_string(dir.path().join("g_edges.csv")).unwrap();
+        assert!(edges.starts_with("Id,Source,Target,Type,Label,source_url,prop:type\r\n"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Named graphs kept inside the directory of a database
+//!
+//! Each named graph is a database of its own under `reachdb.graphs/<name>`, with separate
+//! node and relation ids, its own lock and its own transactions. The main database records
+//! which graphs exist, and graphs created with a shared identity are searched together by
+//! `node_across_graphs`.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    lock::{LockFile, LockMode},
+    utils::unix_time,
+};
+
+/// Settings of a new named graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GraphOptions {
+    /// Nodes of the graph are the same entities as the nodes of the same name in the main
+    /// database and the other graphs sharing their identity, see `node_across_graphs`
+    pub shared_identity: bool,
+}
+
+/// A named graph, as recorded in the metadata of the main database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphInfo {
+    /// Seconds since the UNIX epoch
+    pub created_at: u64,
+    pub shared_identity: bool,
+}
+
+/// Letters, digits, `-`, `_` and `.`, so the name is a safe directory name
+pub(crate) fn check_graph_name(name: &str) -> Result<(), ReachdbError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(ReachdbError::OtherError(format!(
+            "Invalid graph name \"{}\", use letters, digits, '-', '_' and '.'", name
+        )));
+    }
+    Ok(())
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Directory of the named graph `name`
+    pub(crate) fn graph_path(&self, name: &str) -> String {
+        format!("{}/{}", Self::get_db_path(&self.path)[8], name)
+    }
+
+    /// Creates an empty named graph
+    ///
+    /// A graph with a shared identity starts with the name normalizer of the main database, so
+    /// the same spellings lead to the same node everywhere. The directory of a graph that could
+    /// not be recorded is removed again.
+    pub fn create_graph(&mut self, name: &str, options: GraphOptions) -> Result<(), ReachdbError> {
+        check_graph_name(name)?;
+        self.check_writable()?;
+        let path = self.graph_path(name);
+        if self.graphs.contains_key(name) {
+            return Err(ReachdbError::OtherError(format!("Graph {} already exists", name)));
+        }
+        // Left behind by a drop whose removal failed, no graph owns it
+        if Path::new(&path).exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+
+        self.create_graph_dir(&path, options)
+            .and_then(|()| self.transaction(|db| {
+                db.graphs.insert(name.to_string(), GraphInfo { created_at: unix_time(), shared_identity: options.shared_identity });
+                Ok(())
+            }))
+            .inspect_err(|_| {
+                let _ = std::fs::remove_dir_all(&path);
+            })
+    }
+
+    fn create_graph_dir(&self, path: &str, options: GraphOptions) -> Result<(), ReachdbError> {
+        let mut graph = Self::open(path, None, None)?;
+        if options.shared_identity && let Some(normalizer) = self.name_normalizer {
+            graph.set_name_normalizer(Some(normalizer))?;
+        }
+        graph.close()
+    }
+
+    /// Names of the named graphs, sorted
+    pub fn list_graphs(&self) -> Vec<String> {
+        self.graphs.keys().cloned().collect()
+    }
+
+    pub fn graph_info(&self, name: &str) -> Option<&GraphInfo> {
+        self.graphs.get(name)
+    }
+
+    /// Opens the named graph, read-only when this database is
+    ///
+    /// The graph is a separate `Reachdb`, close it before dropping or snapshotting the graph.
+    pub fn graph(&self, name: &str) -> Result<Self, ReachdbError> {
+        if !self.graphs.contains_key(name) {
+            return Err(ReachdbError::OtherError(format!("No graph named {}", name)));
+        }
+        let path = self.graph_path(name);
+        match self.read_only {
+            true => Self::open_read_only(&path),
+            false => Self::open(&path, None, None),
+        }
+    }
+
+    /// Removes the named graph and its files, it must not be open
+    pub fn drop_graph(&mut self, name: &str) -> Result<(), ReachdbError> {
+        if !self.graphs.contains_key(name) {
+            return Err(ReachdbError::OtherError(format!("No graph named {}", name)));
+        }
+        self.check_writable()?;
+        let path = self.graph_path(name);
+        // Fails while the graph is open
+        let lock = LockFile::acquire(&Self::get_db_path(&path)[7], LockMode::ReadWrite)?;
+
+        self.transaction(|db| {
+            db.graphs.remove(name);
+            Ok(())
+        })?;
+        drop(lock);
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    /// Id of the node named `node` in the main database (`None` key) and in every graph sharing its identity
+    ///
+    /// Each graph resolves the name with its own aliases and normalizer. The graphs are opened
+    /// one after the other, so none of them may be open elsewhere.
+    pub fn node_across_graphs(&self, node: &str) -> Result<BTreeMap<Option<String>, u64>, ReachdbError> {
+        let mut ids = BTreeMap::new();
+        if let Some(id) = self.get_node_id(node)? {
+            ids.insert(None, id);
+        }
+        for (name, info) in &self.graphs {
+            if !info.shared_identity {
+                continue;
+            }
+            let mut graph = self.graph(name)?;
+            let id = graph.get_node_id(node);
+            graph.close()?;
+            if let Some(id) = id? {
+                ids.insert(Some(name.clone()), id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::{GraphOptions, NameNormalizer, Reachdb};
+
+    #[test]
+    fn graphs_are_listed_and_names_checked() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.create_graph("iteration-1", GraphOptions { shared_identity: true }).unwrap();
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+        assert!(db.create_graph("scratch", GraphOptions::default()).is_err());
+        assert!(db.create_graph("../escape", GraphOptions::default()).is_err());
+        assert_eq!(db.list_graphs(), vec!["iteration-1", "scratch"]);
+    }
+
+    #[test]
+    fn unrecorded_graphs_leave_no_directory_behind() {
+        let (_dir, mut db) = temp_db(None, None);
+        // A directory in place of the log makes the transaction recording the graph fail
+        let wal = Reachdb::<TypeId>::get_db_path(&db.path)[5].clone();
+        std::fs::create_dir(&wal).unwrap();
+        assert!(db.create_graph("scratch", GraphOptions::default()).is_err());
+        assert!(!std::path::Path::new(&db.graph_path("scratch")).exists());
+
+        std::fs::remove_dir(&wal).unwrap();
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+        assert_eq!(db.list_graphs(), vec!["scratch"]);
+    }
+
+    #[test]
+    fn unrecorded_leftover_directories_are_replaced() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+        let mut scratch = db.graph("scratch").unwrap();
+        scratch.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        scratch.close().unwrap();
+
+        // As a drop that forgot the graph but failed to remove its files leaves it
+        let path = db.graph_path("scratch");
+        let kept = format!("{}.kept", path);
+        std::fs::rename(&path, &kept).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        db.drop_graph("scratch").unwrap();
+        std::fs::rename(&kept, &path).unwrap();
+
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+        let mut scratch = db.graph("scratch").unwrap();
+        assert!(scratch.get_node_id("GPT").unwrap().is_none());
+        scratch.close().unwrap();
+    }
+
+    #[test]
+    fn named_graphs_have_their_own_nodes_and_relations() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+
+        let mut scratch = db.graph("scratch").unwrap();
+        scratch.add_edge("Diffusion Model", "Generative Model", "IS-A").unwrap();
+        assert!(scratch.get_node_id("GPT").unwrap().is_none());
+        scratch.close().unwrap();
+        assert!(db.get_node_id("Diffusion Model").unwrap().is_none());
+    }
+
+    #[test]
+    fn shared_identity_finds_nodes_across_graphs() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.set_name_normalizer(Some(NameNormalizer::default())).unwrap();
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        db.create_graph("iteration-1", GraphOptions { shared_identity: true }).unwrap();
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+
+        let mut graph = db.graph("iteration-1").unwrap();
+        graph.add_edge("transformers", "Neural Network", "IS-A").unwrap();
+        graph.close().unwrap();
+        let mut scratch = db.graph("scratch").unwrap();
+        scratch.add_edge("Transformer", "Architecture", "IS-A").unwrap();
+        scratch.close().unwrap();
+
+        let ids = db.node_across_graphs("Transformer").unwrap();
+        assert_eq!(ids.keys().cloned().collect::<Vec<_>>(), vec![None, Some("iteration-1".to_string())]);
+    }
+
+    #[test]
+    fn open_graphs_cannot_be_dropped() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+
+        let graph = db.graph("scratch").unwrap();
+        assert!(db.drop_graph("scratch").is_err());
+        drop(graph);
+        db.drop_graph("scratch").unwrap();
+        assert!(db.list_graphs().is_empty());
+        assert!(db.graph("scratch").is_err());
+    }
+
+    #[test]
+    fn snapshots_carry_the_named_graphs_along() {
+        let (dir, mut db) = temp_db(None, None);
+        db.create_graph("scratch", GraphOptions::default()).unwrap();
+        let mut scratch = db.graph("scratch").unwrap();
+        scratch.add_edge("Transformer", "Architecture", "IS-A").unwrap();
+        scratch.close().unwrap();
+
+        let backup = tempfile::tempdir().unwrap();
+        let archive = backup.path().join("all.reachdb.gz");
+        db.snapshot_archive(archive.to_str().unwrap()).unwrap();
+        db.drop_graph("scratch").unwrap();
+        db.close().unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        Reachdb::<TypeId>::restore(archive.to_str().unwrap(), path).unwrap();
+        let db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert_eq!(db.list_graphs(), vec!["scratch"]);
+        let mut scratch = db.graph("scratch").unwrap();
+        assert!(scratch.find_edge("Transformer", "Architecture", "IS-A").unwrap().is_some());
+        scratch.close().unwrap();
+    }
+}
@@ -0,0 +1,469 @@
+//! Bulk import of `source -[relationship]-> target` triples
+//!
+//! Every format is read as a stream, so a dump larger than memory is imported batch by batch.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    records::property::PropertyValue,
+};
+
+/// Number of triples added per transaction by `Reachdb::import`
+pub const DEFAULT_IMPORT_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// `{ url: [{ source, relationship, target }] }` as written by rsearch, or a plain array of triples
+    Json,
+    /// One `{ source, relationship, target }` object per line, with an optional `url`
+    Jsonl,
+    /// A header with `source`, `relationship` and `target` columns, and an optional `url` column
+    Csv,
+}
+
+impl ImportFormat {
+    /// Format matching the extension of `path`
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = ReachdbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(ReachdbError::OtherError(format!("Unknown import format: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Jsonl => write!(f, "jsonl"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// A single edge to import, `url` is stored as the `source_url` property of new edges and as a source of each sighting
+#[derive(Debug, Clone, PartialEq)]
+pub struct Triple {
+    pub source: String,
+    pub relationship: String,
+    pub target: String,
+    pub url: Option<String>,
+}
+
+/// What an import did with each triple
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub added: u64,
+    /// Triples whose edge already existed, in the database or earlier in the input
+    pub duplicates: u64,
+    /// Malformed entries, with where they were found
+    pub rejected: Vec<String>,
+    /// Relation types `UserDefinedRelationType` does not know, with their number of triples
+    pub unknown_relations: BTreeMap<String, u64>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Added: {}", self.added)?;
+        writeln!(f, "Duplicates: {}", self.duplicates)?;
+        writeln!(f, "Rejected: {}", self.rejected.len())?;
+        for reason in &self.rejected {
+            writeln!(f, "  - {}", reason)?;
+        }
+        write!(f, "Unknown relation types: {}", self.unknown_relations.values().sum::<u64>())?;
+        for (relation, count) in &self.unknown_relations {
+            write!(f, "\n  - {} ({})", relation, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Triple, or the reason the entry was rejected
+type ParsedTriple = Result<Triple, String>;
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Imports the triples of the file at `path`, committing every `batch_size` triples.
+    ///
+    /// A failing batch is rolled back and stops the import, the batches before it stay.
+    pub fn import(&mut self, format: ImportFormat, path: &str, batch_size: usize) -> Result<ImportSummary, ReachdbError> {
+        match format {
+            ImportFormat::Json => {
+                let mut batches = Batches::new(self, batch_size);
+                let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(File::open(path)?));
+                let parsed = JsonDocument(&mut batches)
+                    .deserialize(&mut deserializer)
+                    .and_then(|()| deserializer.end());
+                // A failed batch stops the parser with a placeholder error, report the real one
+                if let Some(e) = batches.error.take() {
+                    return Err(e);
+                }
+                parsed?;
+                batches.finish()
+            }
+            ImportFormat::Jsonl => {
+                let lines = BufReader::new(File::open(path)?).lines();
+                let triples = lines
+                    .enumerate()
+                    .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+                    .map(|(i, line)| {
+                        line.map_err(|e| e.to_string())
+                            .and_then(|line| serde_json::from_str::<Value>(&line).map_err(|e| e.to_string()))
+                            .and_then(|value| triple_from_json(&value, None))
+                            .map_err(|e| format!("line {}: {}", i + 1, e))
+                    });
+                self.import_triples(triples, batch_size)
+            }
+            ImportFormat::Csv => {
+                let mut reader = csv::Reader::from_path(path).map_err(|e| ReachdbError::OtherError(e.to_string()))?;
+                let columns = CsvColumns::new(reader.headers().map_err(|e| ReachdbError::OtherError(e.to_string()))?)?;
+                let triples = reader.records().enumerate().map(|(i, record)| {
+                    // The header is line 1
+                    record
+                        .map_err(|e| e.to_string())
+                        .and_then(|record| columns.triple(&record))
+                        .map_err(|e| format!("line {}: {}", i + 2, e))
+                });
+                self.import_triples(triples, batch_size)
+            }
+        }
+    }
+
+    /// Imports triples from any source, see `import`
+    pub fn import_triples<I>(&mut self, triples: I, batch_size: usize) -> Result<ImportSummary, ReachdbError>
+    where
+        I: IntoIterator<Item = ParsedTriple>,
+    {
+        let mut batches = Batches::new(self, batch_size);
+        for triple in triples {
+            batches.push(triple)?;
+        }
+        batches.finish()
+    }
+
+    fn import_batch(&mut self, batch: Vec<ParsedTriple>) -> Result<ImportSummary, ReachdbError> {
+        self.transaction(|db| {
+            let mut summary = ImportSummary::default();
+            for triple in batch {
+                match triple {
+                    Ok(triple) => db.import_triple(&triple, &mut summary)?,
+                    Err(reason) => summary.rejected.push(reason),
+                }
+            }
+            Ok(summary)
+        })
+    }
+
+    fn import_triple(&mut self, triple: &Triple, summary: &mut ImportSummary) -> Result<(), ReachdbError> {
+        // Checked first, `add_edge` would create the nodes before skipping the edge
+        if self.relation_type_id(&triple.relationship).is_none() && !self.relation_registry.auto_register {
+            *summary.unknown_relations.entry(triple.relationship.clone()).or_default() += 1;
+            return Ok(());
+        }
+        // A duplicate is not added again, but counts as one more sighting of the edge
+        let existed = self.find_edge(&triple.source, &triple.target, &triple.relationship)?.is_some();
+        let relation_id = self.add_edge_seen(&triple.source, &triple.target, &triple.relationship, triple.url.as_deref())?;
+        match relation_id {
+            Some(_) if existed => summary.duplicates += 1,
+            Some(relation_id) => {
+                summary.added += 1;
+                if let Some(url) = &triple.url {
+                    self.set_edge_property(relation_id, "source_url", PropertyValue::from(url.as_str()))?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl ImportSummary {
+    fn merge(&mut self, other: ImportSummary) {
+        self.added += other.added;
+        self.duplicates += other.duplicates;
+        self.rejected.extend(other.rejected);
+        for (relation, count) in other.unknown_relations {
+            *self.unknown_relations.entry(relation).or_default() += count;
+        }
+    }
+}
+
+/// Triples waiting for their transaction, committed every `batch_size`
+struct Batches<'a, E: UserDefinedRelationType> {
+    db: &'a mut Reachdb<E>,
+    batch_size: usize,
+    batch: Vec<ParsedTriple>,
+    summary: ImportSummary,
+    /// Error of a failed batch, kept while the JSON parser unwinds
+    error: Option<ReachdbError>,
+}
+
+impl<'a, E: UserDefinedRelationType> Batches<'a, E> {
+    fn new(db: &'a mut Reachdb<E>, batch_size: usize) -> Self {
+        let batch_size = batch_size.max(1);
+        Self { db, batch_size, batch: Vec::with_capacity(batch_size), summary: ImportSummary::default(), error: None }
+    }
+
+    fn push(&mut self, triple: ParsedTriple) -> Result<(), ReachdbError> {
+        self.batch.push(triple);
+        if self.batch.len() >= self.batch_size {
+            let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+            self.summary.merge(self.db.import_batch(batch)?);
+        }
+        Ok(())
+    }
+
+    /// Pushes from inside the JSON parser, a failed batch stops it
+    fn push_parsed<Err: de::Error>(&mut self, triple: ParsedTriple) -> Result<(), Err> {
+        self.push(triple).map_err(|e| {
+            self.error = Some(e);
+            Err::custom("import stopped")
+        })
+    }
+
+    fn finish(mut self) -> Result<ImportSummary, ReachdbError> {
+        if !self.batch.is_empty() {
+            let batch = std::mem::take(&mut self.batch);
+            self.summary.merge(self.db.import_batch(batch)?);
+        }
+        Ok(self.summary)
+    }
+}
+
+/// A `{ url: [triple] }` object or a `[triple]` array, read one triple at a time
+struct JsonDocument<'b, 'a, E: UserDefinedRelationType>(&'b mut Batches<'a, E>);
+
+impl<'de, E: UserDefinedRelationType> DeserializeSeed<'de> for JsonDocument<'_, '_, E> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, E: UserDefinedRelationType> Visitor<'de> for JsonDocument<'_, '_, E> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an object of urls or an array of triples")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(url) = map.next_key::<String>()? {
+            map.next_value_seed(UrlTriples { batches: &mut *self.0, url: Some(&url) })?;
+        }
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<(), A::Error> {
+        UrlTriples { batches: self.0, url: None }.visit_seq(seq)
+    }
+}
+
+/// Array of triples, of one url or of the whole document
+struct UrlTriples<'c, 'b, 'a, E: UserDefinedRelationType> {
+    batches: &'b mut Batches<'a, E>,
+    url: Option<&'c str>,
+}
+
+impl<E: UserDefinedRelationType> UrlTriples<'_, '_, '_, E> {
+    /// Anything but an array is rejected as a whole, the import goes on with the next url
+    fn not_an_array<Err: de::Error>(self) -> Result<(), Err> {
+        let reason = format!("{}: expected an array of triples", self.url.unwrap_or_default());
+        self.batches.push_parsed(Err(reason))
+    }
+}
+
+impl<'de, E: UserDefinedRelationType> DeserializeSeed<'de> for UrlTriples<'_, '_, '_, E> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, E: UserDefinedRelationType> Visitor<'de> for UrlTriples<'_, '_, '_, E> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an array of triples")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut i = 0;
+        while let Some(edge) = seq.next_element::<Value>()? {
+            let triple = triple_from_json(&edge, self.url).map_err(|e| format!("{}[{}]: {}", self.url.unwrap_or_default(), i, e));
+            self.batches.push_parsed(triple)?;
+            i += 1;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        self.not_an_array()
+    }
+
+    fn visit_bool<Err: de::Error>(self, _: bool) -> Result<(), Err> {
+        self.not_an_array()
+    }
+
+    fn visit_i64<Err: de::Error>(self, _: i64) -> Result<(), Err> {
+        self.not_an_array()
+    }
+
+    fn visit_u64<Err: de::Error>(self, _: u64) -> Result<(), Err> {
+        self.not_an_array()
+    }
+
+    fn visit_f64<Err: de::Error>(self, _: f64) -> Result<(), Err> {
+        self.not_an_array()
+    }
+
+    fn visit_str<Err: de::Error>(self, _: &str) -> Result<(), Err> {
+        self.not_an_array()
+    }
+
+    fn visit_unit<Err: de::Error>(self) -> Result<(), Err> {
+        self.not_an_array()
+    }
+}
+
+fn triple_from_json(value: &Value, url: Option<&str>) -> ParsedTriple {
+    let field = |name: &str| match value.get(name).and_then(Value::as_str).map(str::trim) {
+        Some(s) if !s.is_empty() => Ok(s.to_string()),
+        _ => Err(format!("missing \"{}\"", name)),
+    };
+    Ok(Triple {
+        source: field("source")?,
+        relationship: field("relationship")?,
+        target: field("target")?,
+        url: url
+            .map(str::to_string)
+            .or_else(|| value.get("url").and_then(Value::as_str).map(str::to_string)),
+    })
+}
+
+/// Positions of the columns of a CSV import
+struct CsvColumns {
+    source: usize,
+    relationship: usize,
+    target: usize,
+    url: Option<usize>,
+}
+
+impl CsvColumns {
+    fn new(headers: &csv::StringRecord) -> Result<Self, ReachdbError> {
+        let position = |names: &[&str]| headers.iter().position(|h| names.contains(&h.trim().to_ascii_lowercase().as_str()));
+        let required = |names: &[&str]| {
+            position(names).ok_or_else(|| ReachdbError::OtherError(format!("CSV header has no \"{}\" column", names[0])))
+        };
+        Ok(Self {
+            source: required(&["source"])?,
+            relationship: required(&["relationship", "relation"])?,
+            target: required(&["target"])?,
+            url: position(&["url", "source_url"]),
+        })
+    }
+
+    fn triple(&self, record: &csv::StringRecord) -> ParsedTriple {
+        let field = |index: usize, name: &str| match record.get(index).map(str::trim) {
+            Some(s) if !s.is_empty() => Ok(s.to_string()),
+            _ => Err(format!("missing \"{}\"", name)),
+        };
+        Ok(Triple {
+            source: field(self.source, "source")?,
+            relationship: field(self.relationship, "relationship")?,
+            target: field(self.target, "target")?,
+            url: self.url.and_then(|i| record.get(i)).map(str::trim).filter(|s| !s.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::temp_db;
+    use super::ImportFormat;
+
+    /// Writes `contents` into the directory and returns the path of the file
+    fn write(dir: &tempfile::TempDir, name: &str, contents: &str) -> String {
+        let path = dir.path().join(name).to_str().unwrap().to_string();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn json_groups_triples_by_source_url() {
+        let (dir, mut db) = temp_db(None, None);
+        let path = write(&dir, "kg.json", r#"{
+            "https://a.org": [
+                {"source": "Diffusion", "relationship": "IS-A", "target": "Generative Model"},
+                {"source": "Diffusion", "relationship": "IS-A", "target": "Generative Model"},
+                {"source": "Diffusion", "target": "ML"}
+            ],
+            "https://b.org": "not triples",
+            "https://c.org": [{"source": "Score Matching", "relationship": "INFLUENCES", "target": "Diffusion"}]
+        }"#);
+        let summary = db.import(ImportFormat::Json, &path, 2).unwrap();
+        assert_eq!((summary.added, summary.duplicates, summary.rejected.len()), (2, 1, 2));
+        assert_eq!(summary.rejected[1], "https://b.org: expected an array of triples");
+        let rel_id = db.find_edge("Diffusion", "Generative Model", "IS-A").unwrap().unwrap();
+        assert_eq!(db.get_edge_properties(rel_id).unwrap()["source_url"].as_str(), Some("https://a.org"));
+    }
+
+    #[test]
+    fn unknown_relations_are_counted_and_leave_no_nodes() {
+        let (dir, mut db) = temp_db(None, None);
+        let path = write(&dir, "kg.json", r#"{"https://a.org": [{"source": "Diffusion", "relationship": "PART-OF", "target": "ML"}]}"#);
+        let summary = db.import(ImportFormat::Json, &path, 2).unwrap();
+        assert_eq!((summary.added, summary.unknown_relations["PART-OF"]), (0, 1));
+        assert!(db.get_node_id("ML").unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_json_arrays_keep_the_batches_before_the_break() {
+        let (dir, mut db) = temp_db(None, None);
+        let path = write(&dir, "array.json", r#"[{"source": "A", "relationship": "IS-A", "target": "B"}, {"source": "C", "relat"#);
+        assert!(db.import(ImportFormat::Json, &path, 1).is_err());
+        assert!(db.find_edge("A", "B", "IS-A").unwrap().is_some());
+    }
+
+    #[test]
+    fn jsonl_rejects_bad_lines_by_number() {
+        let (dir, mut db) = temp_db(None, None);
+        let path = write(&dir, "kg.jsonl", "{\"source\": \"A\", \"relationship\": \"INFLUENCES\", \"target\": \"B\"}\nnot json\n");
+        let summary = db.import(ImportFormat::from_path(&path).unwrap(), &path, 10).unwrap();
+        assert_eq!((summary.added, summary.rejected.len()), (1, 1));
+        assert!(summary.rejected[0].starts_with("line 2"));
+    }
+
+    #[test]
+    fn csv_reads_quoted_fields() {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "INFLUENCES").unwrap();
+        let path = write(&dir, "kg.csv", "source,relation,target,url\n\"Score, Matching\",RELATES-TO,Diffusion,\nA,INFLUENCES,B,\n");
+        let summary = db.import(ImportFormat::Csv, &path, 10).unwrap();
+        assert_eq!((summary.added, summary.duplicates), (1, 1));
+        assert!(db.find_edge("Score, Matching", "Diffusion", "RELATES-TO").unwrap().is_some());
+    }
+}
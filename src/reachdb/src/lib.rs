@@ -5,8 +5,47 @@ pub mod utils;
 
 
 mod data_base;
+mod transaction;
+mod export;
+pub use export::ExportFormat;
+mod import;
+pub use import::{ImportFormat, ImportSummary, Triple, DEFAULT_IMPORT_BATCH_SIZE};
+mod verify;
+mod query;
+pub use query::{NodePattern, Pattern, PatternDirection, Query, QueryResult, RelationPattern, ReturnItem};
+mod wal;
+mod lock;
+mod graphs;
+pub use graphs::{GraphInfo, GraphOptions};
+mod shared;
+mod snapshot;
+pub use snapshot::SnapshotInfo;
+pub use shared::SharedReachdb;
+mod migrate;
+pub use migrate::MigrationReport;
+mod registry;
+mod resolve;
+pub use resolve::{MergeSummary, NameNormalizer};
+mod diff;
+pub use diff::{EdgeTriple, GraphDiff, GraphMergeSummary};
+mod provenance;
+pub use provenance::Provenance;
+mod reasoning;
+pub use reasoning::{Concept, Hierarchy, Taxonomy};
+mod subgraph;
+pub use subgraph::{Subgraph, SubgraphEdge, SubgraphNode};
+mod search;
+pub use search::SearchHit;
+mod vector;
+pub use vector::{cosine_similarity, Embedder, HashingEmbedder, HnswConfig, HnswIndex};
+pub use registry::{BuiltinRelationType, DynamicRelationType, RelationInfo, RelationRegistry};
+pub use verify::{Issue, VerifyReport};
 pub use data_base::Reachdb;
 pub use data_base::UserDefinedRelationType;
+pub use records::property::PropertyValue;
 
 mod errors;
-pub use errors::ReachdbError;
\ No newline at end of file
+pub use errors::ReachdbError;
+
+#[cfg(test)]
+mod test_utils;
\ No newline at end of file
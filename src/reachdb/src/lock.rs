@@ -0,0 +1,113 @@
+//! Advisory lock file of a database directory
+//!
+//! `reachdb.lock` is locked for as long as a `Reachdb` has the directory open, so a second
+//! process (or a second handle in the same process) gets a clear error instead of mapping the
+//! record files next to the first one. The lock is released by the OS when the process exits,
+//! a stale file left behind by a crash does not block anything.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::errors::ReachdbError;
+
+/// How a directory is opened, written into the lock file for the error of the next opener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockMode {
+    ReadWrite,
+    ReadOnly,
+}
+
+/// Held lock on `reachdb.lock`, released when dropped
+#[derive(Debug)]
+pub(crate) struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    /// Locks the file at `path`, failing at once when another handle holds it
+    ///
+    /// sled allows a single process per database, read-only handles included, so the lock is
+    /// always exclusive. Readers of one process share a handle through `SharedReachdb`.
+    pub(crate) fn acquire(path: &str, mode: LockMode) -> Result<Self, ReachdbError> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                let holder = holder.trim();
+                return Err(ReachdbError::OtherError(format!(
+                    "{} is locked{}, close the other handle first",
+                    path,
+                    if holder.is_empty() { String::new() } else { format!(" by {}", holder) },
+                )));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        let mode = match mode {
+            LockMode::ReadWrite => "read-write",
+            LockMode::ReadOnly => "read-only",
+        };
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "process {} ({})", std::process::id(), mode)?;
+        file.flush()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Empty the file so nobody is named as holder, closing it releases the lock
+        let _ = self.file.set_len(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::Reachdb;
+
+    #[test]
+    fn a_writer_locks_the_directory() {
+        let (dir, _db) = temp_db(None, None);
+        let path = dir.path().to_str().unwrap();
+
+        let err = Reachdb::<TypeId>::open(path, None, None).unwrap_err();
+        assert!(err.to_string().contains("locked by process"), "{}", err);
+        assert!(Reachdb::<TypeId>::open_read_only(path).is_err());
+    }
+
+    #[test]
+    fn a_read_only_handle_keeps_writers_out() {
+        let (dir, mut db) = temp_db(None, None);
+        let path = dir.path().to_str().unwrap();
+        db.close().unwrap();
+
+        let mut db = Reachdb::<TypeId>::open_read_only(path).unwrap();
+        assert!(Reachdb::<TypeId>::open(path, None, None).is_err());
+        db.close().unwrap();
+        assert!(Reachdb::<TypeId>::open(path, None, None).is_ok());
+    }
+
+    #[test]
+    fn read_only_handles_read_but_refuse_writes() {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        let path = dir.path().to_str().unwrap();
+        db.close().unwrap();
+
+        let mut db = Reachdb::<TypeId>::open_read_only(path).unwrap();
+        assert!(db.is_read_only());
+        assert!(db.find_edge("GPT", "Transformer", "IS-A").unwrap().is_some());
+        assert_eq!(db.search_nodes("transformer", 1).unwrap()[0].name, "Transformer");
+        assert!(db.add_edge("BERT", "Transformer", "IS-A").is_err());
+        db.close().unwrap();
+
+        // Nothing was written
+        let mut db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert!(db.get_node_id("BERT").unwrap().is_none());
+        db.close().unwrap();
+    }
+}
@@ -1,8 +1,9 @@
 
-use std::{env, fs::File, io::Read};
+use std::{env, fs::File, io::Read, process::ExitCode};
+use clap::Parser;
 use log::trace;
 use serde_json::Value;
-use reachdb::{{Reachdb, UserDefinedRelationType}, ReachdbError, records::NULL_OFFSET};
+use reachdb::{{BuiltinRelationType as TypeId, GraphOptions, Reachdb}, ReachdbError, records::NULL_OFFSET};
 
 fn get_data() -> Result<Value, serde_json::Error> {
     let mut f = File::open("tempdata/c.json")
@@ -13,49 +14,168 @@ fn get_data() -> Result<Value, serde_json::Error> {
     serde_json::from_str::<Value>(&buf)
 }
 
-#[derive(Debug)]
-enum TypeId {
-    IsA(u8),
-    RelatesTo(u8),
-    Influences(u8)
-}
-impl UserDefinedRelationType for TypeId {
-    fn get_type_id(relation: &str) -> Option<Self> {
-        match relation {
-            "IS-A" => Some(Self::IsA(0)),
-            "RELATES-TO" => Some(Self::RelatesTo(1)),
-            "INFLUENCES" => Some(Self::Influences(2)),
-            _ => None
-        }
-    }
-    fn type_id(&self) -> u8 {
-        match self {
-            Self::IsA(id) => *id,
-            Self::RelatesTo(id) => *id,
-            Self::Influences(id) => *id,
-        }
-    }
-    fn get_type_str(id: u8) -> Option<String> {
-        match id {
-            0 => Some("IS-A".to_string()),
-            1 => Some("RELATES-TO".to_string()),
-            2 => Some("INFLUENCES".to_string()),
-            _ => None
-        }
-    }
+#[derive(clap::Subcommand, Debug)]
+enum Commands {
+    /// Print the relations of the first nodes of a database
+    Show {
+        /// Directory of the database
+        #[arg(default_value = "data")]
+        path: String,
+    },
+
+    /// Check the integrity of a database
+    Check {
+        /// Directory of the database
+        path: String,
+
+        /// Repair the problems that were found
+        #[arg(long, default_value_t = false)]
+        repair: bool,
+    },
+
+    /// List the relation types of a database, built-in and registered
+    Relations {
+        /// Directory of the database
+        path: String,
+
+        /// Register a new relation type with this name
+        #[arg(long)]
+        add: Option<String>,
+
+        /// Description of the type added with --add
+        #[arg(long, requires = "add")]
+        description: Option<String>,
+    },
+
+    /// List, create or drop the named graphs of a database
+    Graphs {
+        /// Directory of the database
+        path: String,
+
+        /// Create a named graph
+        #[arg(long, conflicts_with = "drop")]
+        create: Option<String>,
+
+        /// Let the graph created with --create share node identity with the main database
+        #[arg(long, requires = "create", default_value_t = false)]
+        shared: bool,
+
+        /// Remove a named graph and its files
+        #[arg(long)]
+        drop: Option<String>,
+    },
+
+    /// Convert a database written with an older record format
+    Migrate {
+        /// Directory of the database
+        path: String,
+    },
+
+    /// Copy a database into a new directory, or into a compressed archive with --archive
+    Snapshot {
+        /// Directory of the database
+        path: String,
+
+        /// New directory, or archive file with --archive
+        dest: String,
+
+        /// Write a single gzip archive instead of a directory
+        #[arg(long, default_value_t = false)]
+        archive: bool,
+    },
+
+    /// Replace a closed database with a snapshot directory or archive
+    Restore {
+        /// Snapshot directory or archive
+        source: String,
+
+        /// Directory of the database, created if missing
+        path: String,
+    },
+
+    /// Merge a duplicate node into another one, its name becomes an alias
+    MergeNodes {
+        /// Directory of the database
+        path: String,
+
+        /// Node that stays
+        keep: String,
+
+        /// Node whose relations and properties move to `keep`
+        absorb: String,
+    },
+
+    /// Print the nodes and edges added and removed going from one database to another
+    Diff {
+        /// Directory of the old database
+        old: String,
+
+        /// Directory of the new database
+        new: String,
+    },
+
+    /// Add the nodes and edges of one database to another
+    Merge {
+        /// Directory of the database to read from
+        source: String,
+
+        /// Directory of the database to add to
+        dest: String,
+    },
+
+    /// Find nodes by name, tolerating prefixes and typos
+    Search {
+        /// Directory of the database
+        path: String,
+
+        /// Words to look for, e.g. "difusion model"
+        query: String,
+
+        /// Maximum number of nodes to print
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+
+        /// Open the database without writing to it
+        #[arg(long, default_value_t = false)]
+        read_only: bool,
+    },
+
+    /// Print the concept tree of a hierarchical relation
+    Taxonomy {
+        /// Directory of the database
+        path: String,
+
+        /// Relation from a concept to its parent
+        #[arg(long, default_value = "IS-A")]
+        relation: String,
+
+        /// Only print the concepts below this one
+        #[arg(long)]
+        root: Option<String>,
+    },
+
+    /// Run a MATCH ... RETURN query against a database
+    Query {
+        /// Directory of the database
+        path: String,
+
+        /// Query, e.g. MATCH (a)-[IS-A]->(b {name:"Transformer"}) RETURN a LIMIT 10
+        query: String,
+
+        /// Open the database without writing to it
+        #[arg(long, default_value_t = false)]
+        read_only: bool,
+    },
 }
-impl TypeId {
-    fn from_id(id: u8) -> Self {
-        match id {
-            0 => Self::IsA(id),
-            1 => Self::RelatesTo(id),
-            2 => Self::Influences(id),
-            _ => panic!("Invalid type id")
-        }
-    }
+
+#[derive(Parser, Debug)]
+#[command(about = "Tools for reachdb databases")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
 }
 
-fn main() -> Result<(), ReachdbError> {
+fn main() -> Result<ExitCode, ReachdbError> {
 
     unsafe {
         // env::set_var("RUST_LOG", "reachdb=trace");
@@ -63,10 +183,179 @@ fn main() -> Result<(), ReachdbError> {
     }
     let _ = env_logger::try_init();
     trace!("NULL_OFFSET: {}", NULL_OFFSET);
-    
+
+    match Cli::parse().command {
+        Commands::Show { path } => show(&path)?,
+        Commands::Check { path, repair } => return check(&path, repair),
+        Commands::Query { path, query, read_only } => run_query(&path, &query, read_only)?,
+        Commands::Relations { path, add, description } => relations(&path, add.as_deref(), description.as_deref())?,
+        Commands::Graphs { path, create, shared, drop } => graphs(&path, create.as_deref(), shared, drop.as_deref())?,
+        Commands::Migrate { path } => println!("{}", Reachdb::<TypeId>::migrate(&path)?),
+        Commands::Taxonomy { path, relation, root } => taxonomy(&path, &relation, root.as_deref())?,
+        Commands::Snapshot { path, dest, archive } => snapshot(&path, &dest, archive)?,
+        Commands::Restore { source, path } => println!("Restored {}", Reachdb::<TypeId>::restore(&source, &path)?),
+        Commands::MergeNodes { path, keep, absorb } => merge_nodes(&path, &keep, &absorb)?,
+        Commands::Diff { old, new } => diff(&old, &new)?,
+        Commands::Merge { source, dest } => merge(&source, &dest)?,
+        Commands::Search { path, query, limit, read_only } => search(&path, &query, limit, read_only)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Exits with a failure when problems remain in the database
+fn check(path: &str, repair: bool) -> Result<ExitCode, ReachdbError> {
+    // Opening a directory without metadata would turn it into an empty database
+    if !std::path::Path::new(path).join("reachdb.metadata.json").exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    // Opening for writing recovers the log and rewrites the metadata, a plain check changes nothing
+    let mut db = match repair {
+        true => Reachdb::<TypeId>::open(path, None, None)?,
+        false => Reachdb::<TypeId>::open_read_only(path)?,
+    };
+    let report = db.verify()?;
+    println!("{}", report);
+
+    let healthy = if repair && !report.is_ok() {
+        db.repair()?;
+        let remaining = db.verify()?;
+        println!("After repair: {}", remaining);
+        remaining.is_ok()
+    } else {
+        report.is_ok()
+    };
+    db.close()?;
+
+    Ok(if healthy { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Opens `path` for the commands that only read, failing instead of creating a missing database
+fn open_for_reading(path: &str, read_only: bool) -> Result<Reachdb<TypeId>, ReachdbError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    match read_only {
+        true => Reachdb::<TypeId>::open_read_only(path),
+        false => Reachdb::<TypeId>::open(path, None, None),
+    }
+}
+
+fn run_query(path: &str, query: &str, read_only: bool) -> Result<(), ReachdbError> {
+    let mut db = open_for_reading(path, read_only)?;
+    let result = db.query(query);
+    db.close()?;
+    println!("{}", result?);
+    Ok(())
+}
+
+fn relations(path: &str, add: Option<&str>, description: Option<&str>) -> Result<(), ReachdbError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    let mut db = Reachdb::<TypeId>::open(path, None, None)?;
+    if let Some(name) = add {
+        let id = db.register_relation(name, description)?;
+        println!("Registered {} as {}", name, id);
+    }
+    let registry = db.relation_registry();
+    for (id, name) in db.relation_types() {
+        let description = registry.types.get(&id).and_then(|info| info.description.clone()).unwrap_or_default();
+        let aliases: Vec<&str> = registry.aliases.iter().filter(|(_, a)| **a == id).map(|(alias, _)| alias.as_str()).collect();
+        let line = format!("{:>3}  {:<20} {:<30} {}", id, name, aliases.join(", "), description);
+        println!("{}", line.trim_end());
+    }
+    db.close()?;
+    Ok(())
+}
+
+fn graphs(path: &str, create: Option<&str>, shared: bool, drop: Option<&str>) -> Result<(), ReachdbError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    let mut db = Reachdb::<TypeId>::open(path, None, None)?;
+    if let Some(name) = create {
+        db.create_graph(name, GraphOptions { shared_identity: shared })?;
+        println!("Created {}", name);
+    }
+    if let Some(name) = drop {
+        db.drop_graph(name)?;
+        println!("Dropped {}", name);
+    }
+    for name in db.list_graphs() {
+        let shared = db.graph_info(&name).is_some_and(|info| info.shared_identity);
+        println!("{}{}", name, if shared { "  (shared identity)" } else { "" });
+    }
+    db.close()?;
+    Ok(())
+}
+
+fn taxonomy(path: &str, relation: &str, root: Option<&str>) -> Result<(), ReachdbError> {
+    let mut db = open_for_reading(path, false)?;
+    let taxonomy = db.taxonomy(relation, root);
+    db.close()?;
+    print!("{}", taxonomy?);
+    Ok(())
+}
+
+fn snapshot(path: &str, dest: &str, archive: bool) -> Result<(), ReachdbError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    let mut db = Reachdb::<TypeId>::open(path, None, None)?;
+    let info = match archive {
+        true => db.snapshot_archive(dest),
+        false => db.snapshot(dest),
+    };
+    db.close()?;
+    println!("Snapshot of {}", info?);
+    Ok(())
+}
+
+fn merge_nodes(path: &str, keep: &str, absorb: &str) -> Result<(), ReachdbError> {
+    if !std::path::Path::new(path).exists() {
+        return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+    }
+    let mut db = Reachdb::<TypeId>::open(path, None, None)?;
+    let summary = db.merge_nodes(keep, absorb);
+    db.close()?;
+    println!("{}", summary?);
+    Ok(())
+}
+
+fn diff(old: &str, new: &str) -> Result<(), ReachdbError> {
+    let mut old_db = open_for_reading(old, true)?;
+    let mut new_db = open_for_reading(new, true)?;
+    let diff = old_db.diff(&new_db);
+    old_db.close()?;
+    new_db.close()?;
+    println!("{}", diff?);
+    Ok(())
+}
+
+fn merge(source: &str, dest: &str) -> Result<(), ReachdbError> {
+    let mut source_db = open_for_reading(source, true)?;
+    let mut dest_db = open_for_reading(dest, false)?;
+    let summary = dest_db.merge_from(&source_db);
+    source_db.close()?;
+    dest_db.close()?;
+    println!("{}", summary?);
+    Ok(())
+}
+
+fn search(path: &str, query: &str, limit: usize, read_only: bool) -> Result<(), ReachdbError> {
+    let mut db = open_for_reading(path, read_only)?;
+    let hits = db.search_nodes(query, limit);
+    db.close()?;
+    for hit in hits? {
+        println!("{:.3}  {} (id:{})", hit.score, hit.name, hit.node_id);
+    }
+    Ok(())
+}
+
+fn show(path: &str) -> Result<(), ReachdbError> {
     // let mut db = Reachdb::<TypeId>::new()?;
     // db.prepare(Some(10000), Some(10000))?;
-    let mut db = Reachdb::<TypeId>::open("data", Some(60000), Some(60000))?;
+    let mut db = Reachdb::<TypeId>::open(path, None, None)?;
 
     // let data = get_data().unwrap();
     // // db.print_graph()?;
@@ -88,11 +377,11 @@ fn main() -> Result<(), ReachdbError> {
     // }
 
     println!("{}", "-----------------".repeat(5));
-    for i in 0..10 {
+    for i in db.get_all_node_ids()?.into_iter().take(10) {
         let node = db.get_all_node_relations(i)?;
         for rel_id in node {
             let rel = db.get_relation(rel_id)?;
-            println!("{:#?}[{:?}] -> {:#?}", db.get_property(rel.source_id)?, TypeId::from_id(rel.type_id), db.get_property(rel.target_id)?);
+            println!("{:#?}[{}] -> {:#?}", db.get_node_name(rel.source_id)?, db.get_relation_type_str(rel.type_id), db.get_node_name(rel.target_id)?);
         }
         println!("{}", "-----------------".repeat(5));
     }
@@ -0,0 +1,305 @@
+//! Conversion of directories written with an older record format
+//!
+//! Format 0 had no file headers and stored the bincode encoding of the records at
+//! `id * size_of::<Record>()`: 24-byte node slots and 64-byte relation slots holding 57 bytes,
+//! with `type_id` right after the target id. Embedding slots were the same, without the header.
+
+use std::fmt;
+use std::path::Path;
+
+use log::info;
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    lock::{LockFile, LockMode},
+    records::{node::NodeRecord, relationship::RelationshipRecord, FileHeader, Record, FORMAT_VERSION, HEADER_SIZE},
+    vector::{embedding_slot_size, EMBEDDING_MAGIC},
+};
+
+/// Slot sizes of format 0
+const V0_NODE_SLOT: usize = 24;
+const V0_RELATION_SLOT: usize = 64;
+
+/// What `Reachdb::migrate` converted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from_version: u32,
+    pub to_version: u32,
+    /// Record slots rewritten, removed and unused ones included
+    pub node_slots: u64,
+    pub relation_slots: u64,
+    /// Old files, kept next to the new ones until they are deleted by hand
+    pub backups: Vec<String>,
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.from_version == self.to_version {
+            return write!(f, "Already at record format {}", self.to_version);
+        }
+        write!(
+            f,
+            "Migrated record format {} -> {}: {} node slots, {} relation slots",
+            self.from_version, self.to_version, self.node_slots, self.relation_slots
+        )?;
+        for backup in &self.backups {
+            write!(f, "\n  old file kept as {}", backup)?;
+        }
+        Ok(())
+    }
+}
+
+/// Format 0 relation record, bincode put `type_id` unaligned after the target id
+fn relation_from_v0(bytes: &[u8]) -> RelationshipRecord {
+    let mut v1 = Vec::with_capacity(RelationshipRecord::record_size());
+    v1.extend_from_slice(&bytes[0..17]);
+    v1.extend_from_slice(&[0; 7]);
+    v1.extend_from_slice(&bytes[17..57]);
+    RelationshipRecord::from_bytes(&v1)
+}
+
+/// Converts the file at `path` into `<path>.migrating`, to be swapped in by `swap_file`
+///
+/// Returns the size of the new file, and `false` when an interrupted migration had already
+/// swapped it in. The conversion never touches the file itself.
+fn convert_file(path: &str, header: FileHeader, version: u32, convert: impl FnOnce(&[u8]) -> Vec<u8>) -> Result<(usize, bool), ReachdbError> {
+    let migrating = format!("{}.migrating", path);
+    let backup = format!("{}.v{}", path, version);
+    // Interrupted between the two renames of `swap_file`
+    if !Path::new(path).exists() && Path::new(&migrating).exists() && Path::new(&backup).exists() {
+        std::fs::rename(&migrating, path)?;
+    }
+
+    let old = std::fs::read(path).unwrap_or_default();
+    if FileHeader::read(&old).is_ok_and(|found| found == header) {
+        info!("{} is already in record format {}", path, header.version);
+        return Ok((old.len(), false));
+    }
+    // The backup may be the only copy of the data left
+    if Path::new(&backup).exists() {
+        return Err(ReachdbError::OtherError(format!(
+            "{} already exists and {} is not converted, move one of them away first", backup, path
+        )));
+    }
+
+    let mut bytes = vec![0; HEADER_SIZE];
+    header.write(&mut bytes)?;
+    bytes.extend_from_slice(&convert(&old));
+    std::fs::write(&migrating, &bytes)?;
+    Ok((bytes.len(), true))
+}
+
+/// Keeps the old file as `<path>.v<version>` and puts the converted one in its place
+fn swap_file(path: &str, version: u32) -> Result<(), ReachdbError> {
+    if Path::new(path).exists() {
+        std::fs::rename(path, format!("{}.v{}", path, version))?;
+    }
+    std::fs::rename(format!("{}.migrating", path), path)?;
+    Ok(())
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Rewrites the record files of the directory `path` in the current format
+    ///
+    /// The database must be closed. Every file is converted next to the old one before any is
+    /// swapped in, and the old files are kept with the old version as extension, e.g.
+    /// `reachdb.node.db.v0`. An interrupted migration is finished by running it again, files
+    /// that already have the new header are left as they are and backups are never overwritten.
+    pub fn migrate(path: &str) -> Result<MigrationReport, ReachdbError> {
+        let paths = Self::get_db_path(path);
+        if !Path::new(&paths[4]).exists() {
+            return Err(ReachdbError::OtherError(format!("No database at {}", path)));
+        }
+        // Held until the end of the conversion, nothing may open the directory meanwhile
+        let _lock = LockFile::acquire(&paths[7], LockMode::ReadWrite)?;
+        if Path::new(&paths[5]).exists() {
+            return Err(ReachdbError::OtherError(format!(
+                "{} holds an unfinished transaction, open it with the version that wrote it first", paths[5]
+            )));
+        }
+        let mut db: Self = serde_json::from_str(&std::fs::read_to_string(&paths[4])?)?;
+        db.path = path.to_string();
+
+        let mut report = MigrationReport { from_version: db.format_version, to_version: FORMAT_VERSION, ..Default::default() };
+        if db.format_version == FORMAT_VERSION {
+            return Ok(report);
+        }
+        if db.format_version > FORMAT_VERSION {
+            return Err(ReachdbError::OtherError(format!(
+                "{} has record format {}, newer than the format {} of this build", path, db.format_version, FORMAT_VERSION
+            )));
+        }
+        info!("Migrating {} from record format {} to {}", path, db.format_version, FORMAT_VERSION);
+
+        // Format 0 is the only older one
+        let version = db.format_version;
+        let mut converted = Vec::new();
+        let (size, pending) = convert_file(&paths[2], NodeRecord::header(), version, |old| {
+            // Bincode wrote the three ids in order, already the current layout
+            old.chunks_exact(V0_NODE_SLOT).flat_map(|chunk| NodeRecord::from_bytes(chunk).to_bytes()).collect()
+        })?;
+        db.node_mmap_size = size;
+        report.node_slots = ((size - HEADER_SIZE) / NodeRecord::record_size()) as u64;
+        converted.push((&paths[2], pending));
+
+        let (size, pending) = convert_file(&paths[3], RelationshipRecord::header(), version, |old| {
+            old.chunks_exact(V0_RELATION_SLOT).flat_map(|chunk| relation_from_v0(chunk).to_bytes()).collect()
+        })?;
+        db.relation_mmap_size = size;
+        report.relation_slots = ((size - HEADER_SIZE) / RelationshipRecord::record_size()) as u64;
+        converted.push((&paths[3], pending));
+
+        if Path::new(&paths[6]).exists() || Path::new(&format!("{}.v{}", paths[6], version)).exists() {
+            let header = FileHeader::new(EMBEDDING_MAGIC, embedding_slot_size(db.embedding_dim));
+            let (size, pending) = convert_file(&paths[6], header, version, <[u8]>::to_vec)?;
+            db.embedding_mmap_size = size;
+            converted.push((&paths[6], pending));
+        }
+
+        // Nothing was swapped in before every file was converted
+        for (path, pending) in converted {
+            if pending {
+                swap_file(path, version)?;
+            }
+            let backup = format!("{}.v{}", path, version);
+            if Path::new(&backup).exists() {
+                report.backups.push(backup);
+            }
+        }
+
+        db.format_version = FORMAT_VERSION;
+        db.save_metadata()?;
+        info!("{}", report);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::records::{relationship::RelationshipRecord, Record, HEADER_SIZE};
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::Reachdb;
+
+    /// Turns a current directory back into format 0
+    fn downgrade(path: &str) {
+        let paths = Reachdb::<TypeId>::get_db_path(path);
+        let nodes = std::fs::read(&paths[2]).unwrap();
+        std::fs::write(&paths[2], &nodes[HEADER_SIZE..]).unwrap();
+
+        let relations = std::fs::read(&paths[3]).unwrap();
+        let mut v0 = Vec::new();
+        for chunk in relations[HEADER_SIZE..].chunks_exact(RelationshipRecord::record_size()) {
+            v0.extend_from_slice(&chunk[0..17]);
+            v0.extend_from_slice(&chunk[24..64]);
+            v0.extend_from_slice(&[0; 7]);
+        }
+        std::fs::write(&paths[3], v0).unwrap();
+
+        downgrade_metadata(path);
+    }
+
+    fn downgrade_metadata(path: &str) {
+        let paths = Reachdb::<TypeId>::get_db_path(path);
+        let metadata = std::fs::read_to_string(&paths[4]).unwrap().replace("\"format_version\":1,", "");
+        std::fs::write(&paths[4], metadata).unwrap();
+    }
+
+    /// A database in format 0 with a removed relation, returns the id of the relation
+    fn old_db() -> (tempfile::TempDir, u64) {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        db.add_edge("BERT", "Transformer", "IS-A").unwrap();
+        let removed = db.add_edge("BERT", "GPT", "RELATES-TO").unwrap().unwrap();
+        db.remove_edge("BERT", "GPT", "RELATES-TO").unwrap();
+        db.close().unwrap();
+        downgrade(dir.path().to_str().unwrap());
+        (dir, removed)
+    }
+
+    /// Copies the files a migration leaves behind once it swapped in the node file only
+    fn interrupted_copy(migrated: &str) -> tempfile::TempDir {
+        let paths = Reachdb::<TypeId>::get_db_path(migrated);
+        let interrupted = tempfile::tempdir().unwrap();
+        let copy_paths = Reachdb::<TypeId>::get_db_path(interrupted.path().to_str().unwrap());
+        std::fs::copy(&paths[4], &copy_paths[4]).unwrap();
+        std::fs::copy(&paths[2], &copy_paths[2]).unwrap();
+        std::fs::copy(format!("{}.v0", paths[2]), format!("{}.v0", copy_paths[2])).unwrap();
+        std::fs::copy(format!("{}.v0", paths[3]), &copy_paths[3]).unwrap();
+        downgrade_metadata(interrupted.path().to_str().unwrap());
+        interrupted
+    }
+
+    #[test]
+    fn old_directories_are_refused_until_migrated() {
+        let (dir, _) = old_db();
+        let path = dir.path().to_str().unwrap();
+        let err = Reachdb::<TypeId>::open(path, None, None).unwrap_err();
+        assert!(err.to_string().contains("migrate"), "{}", err);
+
+        let report = Reachdb::<TypeId>::migrate(path).unwrap();
+        assert_eq!((report.from_version, report.to_version), (0, 1));
+        assert_eq!(report.backups.len(), 2);
+        // Nothing left to do the second time
+        assert_eq!(Reachdb::<TypeId>::migrate(path).unwrap().from_version, 1);
+    }
+
+    #[test]
+    fn migrated_databases_keep_their_records_and_free_slots() {
+        let (dir, removed) = old_db();
+        let path = dir.path().to_str().unwrap();
+        Reachdb::<TypeId>::migrate(path).unwrap();
+
+        let mut db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert!(db.find_edge("BERT", "Transformer", "IS-A").unwrap().is_some());
+        assert!(db.get_relation(removed).unwrap().is_deleted());
+        assert_eq!(db.add_edge("T5", "Transformer", "IS-A").unwrap(), Some(removed));
+    }
+
+    #[test]
+    fn interrupted_migrations_are_finished_by_running_them_again() {
+        let (dir, _) = old_db();
+        let path = dir.path().to_str().unwrap();
+        Reachdb::<TypeId>::migrate(path).unwrap();
+        let paths = Reachdb::<TypeId>::get_db_path(path);
+
+        let interrupted = interrupted_copy(path);
+        let copy_paths = Reachdb::<TypeId>::get_db_path(interrupted.path().to_str().unwrap());
+        let report = Reachdb::<TypeId>::migrate(interrupted.path().to_str().unwrap()).unwrap();
+        assert_eq!(report.backups.len(), 2);
+        assert_eq!(std::fs::read(&copy_paths[2]).unwrap(), std::fs::read(&paths[2]).unwrap());
+        assert_eq!(std::fs::read(format!("{}.v0", copy_paths[2])).unwrap(), std::fs::read(format!("{}.v0", paths[2])).unwrap());
+        assert_eq!(std::fs::read(&copy_paths[3]).unwrap(), std::fs::read(&paths[3]).unwrap());
+    }
+
+    #[test]
+    fn existing_backups_are_never_overwritten() {
+        let (dir, _) = old_db();
+        let path = dir.path().to_str().unwrap();
+        Reachdb::<TypeId>::migrate(path).unwrap();
+        let paths = Reachdb::<TypeId>::get_db_path(path);
+        let interrupted = interrupted_copy(path);
+        let copy = interrupted.path().to_str().unwrap();
+        let copy_paths = Reachdb::<TypeId>::get_db_path(copy);
+        Reachdb::<TypeId>::migrate(copy).unwrap();
+
+        // An unconverted file whose backup already exists is refused
+        std::fs::copy(format!("{}.v0", paths[3]), &copy_paths[3]).unwrap();
+        downgrade_metadata(copy);
+        assert!(Reachdb::<TypeId>::migrate(copy).is_err());
+        assert_eq!(std::fs::read(format!("{}.v0", copy_paths[3])).unwrap(), std::fs::read(format!("{}.v0", paths[3])).unwrap());
+    }
+
+    #[test]
+    fn files_of_another_kind_are_refused() {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        db.close().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let paths = Reachdb::<TypeId>::get_db_path(path);
+        std::fs::copy(&paths[3], &paths[2]).unwrap();
+        assert!(Reachdb::<TypeId>::open(path, None, None).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! When and from where each relation was extracted
+//!
+//! Adding an edge that already exists counts as one more sighting of the triple instead of
+//! being dropped, so facts confirmed by many extractions stand out from one-off mistakes.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, wal::SledTree};
+
+/// Provenance of a relation, kept in the `reachdb.property.provenance` tree
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Seconds since the UNIX epoch of the first sighting, 0 for relations added before provenance was kept
+    pub created_at: u64,
+    /// Seconds since the UNIX epoch of the latest sighting
+    pub last_seen: u64,
+    /// Number of times the triple was added
+    pub support: u64,
+    /// Documents or URLs the triple was extracted from
+    pub sources: BTreeSet<String>,
+}
+
+impl Provenance {
+    /// Adds the sightings of `other`, for relations merged into one
+    pub fn absorb(&mut self, other: Provenance) {
+        self.created_at = match (self.created_at, other.created_at) {
+            (0, other) | (other, 0) => other,
+            (a, b) => a.min(b),
+        };
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.support += other.support;
+        self.sources.extend(other.sources);
+    }
+
+    /// Takes in `other`, a copy of the same relation from another database
+    ///
+    /// The copies may share sightings, so the support is the larger of the two, and at least one
+    /// per distinct source. Merging the same copy again changes nothing.
+    pub fn combine(&mut self, other: Provenance) {
+        let support = self.support.max(other.support);
+        self.absorb(other);
+        self.support = support.max(self.sources.len() as u64);
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Adds the edge like `add_edge`, and records `evidence` (a URL, a document id) as a source of it
+    pub fn add_edge_with_evidence(&mut self, source: &str, target: &str, relationship: &str, evidence: &str) -> Result<Option<u64>, ReachdbError> {
+        self.add_edge_seen(source, target, relationship, Some(evidence))
+    }
+
+    /// Provenance of the relation, `None` for a relation added before provenance was kept
+    pub fn get_edge_provenance(&self, relation_id: u64) -> Result<Option<Provenance>, ReachdbError> {
+        match self.sled_get(SledTree::EdgeProvenance, &relation_id.to_be_bytes())? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Relations first seen at or after `timestamp` (seconds since the UNIX epoch), oldest first
+    pub fn get_edges_since(&self, timestamp: u64) -> Result<Vec<u64>, ReachdbError> {
+        let mut edges = self.provenances()?;
+        edges.retain(|(_, provenance)| provenance.created_at >= timestamp);
+        edges.sort_by_key(|(id, provenance)| (provenance.created_at, *id));
+        Ok(edges.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Relations seen again at or after `timestamp`, including those first seen before it
+    pub fn get_edges_seen_since(&self, timestamp: u64) -> Result<Vec<u64>, ReachdbError> {
+        let mut edges = self.provenances()?;
+        edges.retain(|(_, provenance)| provenance.last_seen >= timestamp);
+        edges.sort_by_key(|(id, provenance)| (provenance.last_seen, *id));
+        Ok(edges.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Relations added at least `min_support` times, best supported first
+    pub fn get_edges_by_support(&self, min_support: u64) -> Result<Vec<(u64, u64)>, ReachdbError> {
+        let mut edges: Vec<(u64, u64)> = self.provenances()?.into_iter()
+            .filter(|(_, provenance)| provenance.support >= min_support)
+            .map(|(id, provenance)| (id, provenance.support))
+            .collect();
+        edges.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(edges)
+    }
+
+    fn provenances(&self) -> Result<Vec<(u64, Provenance)>, ReachdbError> {
+        let mut provenances = Vec::new();
+        for (key, value) in self.sled_scan_prefix(SledTree::EdgeProvenance, &[])? {
+            let id = u64::from_be_bytes(key.as_slice().try_into()
+                .map_err(|_| ReachdbError::OtherError("Bad key in the provenance tree".to_string()))?);
+            provenances.push((id, bincode::deserialize(&value)?));
+        }
+        Ok(provenances)
+    }
+
+    /// Counts one sighting of the relation at `now`, `created` when the relation was just added
+    pub(crate) fn record_sighting(&mut self, relation_id: u64, evidence: Option<&str>, created: bool, now: u64) -> Result<(), ReachdbError> {
+        let mut provenance = match self.get_edge_provenance(relation_id)? {
+            Some(provenance) if !created => provenance,
+            // Seen once already, when it was added before provenance was kept
+            None if !created => Provenance { support: 1, ..Default::default() },
+            // A new relation may reuse the slot of a removed one, whose provenance is not its own
+            _ => Provenance { created_at: now, ..Default::default() },
+        };
+        provenance.last_seen = now;
+        provenance.support += 1;
+        if let Some(evidence) = evidence {
+            provenance.sources.insert(evidence.to_string());
+        }
+        self.set_edge_provenance(relation_id, &provenance)
+    }
+
+    pub(crate) fn set_edge_provenance(&mut self, relation_id: u64, provenance: &Provenance) -> Result<(), ReachdbError> {
+        self.sled_insert(SledTree::EdgeProvenance, &relation_id.to_be_bytes(), bincode::serialize(provenance)?)
+    }
+
+    pub(crate) fn clear_edge_provenance(&mut self, relation_id: u64) -> Result<(), ReachdbError> {
+        self.sled_remove(SledTree::EdgeProvenance, &relation_id.to_be_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::temp_db;
+
+    #[test]
+    fn repeated_triples_add_support_and_sources() {
+        let (_dir, mut db) = temp_db(None, None);
+        let gpt = db.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://a.org").unwrap().unwrap();
+        assert_eq!(db.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://b.org").unwrap(), Some(gpt));
+        assert_eq!(db.add_edge("GPT", "Transformer", "IS-A").unwrap(), Some(gpt));
+
+        let provenance = db.get_edge_provenance(gpt).unwrap().unwrap();
+        assert_eq!(provenance.support, 3);
+        assert_eq!(provenance.sources.iter().map(String::as_str).collect::<Vec<_>>(), vec!["https://a.org", "https://b.org"]);
+        assert!(provenance.created_at > 0 && provenance.created_at <= provenance.last_seen);
+    }
+
+    #[test]
+    fn edges_by_support_are_best_supported_first() {
+        let (_dir, mut db) = temp_db(None, None);
+        let gpt = db.add_edge("GPT", "Transformer", "IS-A").unwrap().unwrap();
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        let bert = db.add_edge("BERT", "GPT", "IS-A").unwrap().unwrap();
+
+        assert_eq!(db.get_edges_by_support(2).unwrap(), vec![(gpt, 2)]);
+        assert_eq!(db.get_edges_by_support(0).unwrap(), vec![(gpt, 2), (bert, 1)]);
+    }
+
+    #[test]
+    fn edges_since_follow_first_and_last_sightings() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        let bert = db.add_edge("BERT", "GPT", "IS-A").unwrap().unwrap();
+
+        db.transaction(|db| db.record_sighting(bert, None, false, u64::MAX)).unwrap();
+        assert_eq!(db.get_edges_seen_since(u64::MAX).unwrap(), vec![bert]);
+        assert!(db.get_edges_since(u64::MAX).unwrap().is_empty());
+        assert_eq!(db.get_edges_since(0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn merged_nodes_pool_the_evidence_of_their_relations() {
+        let (_dir, mut db) = temp_db(None, None);
+        let gpt = db.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://a.org").unwrap().unwrap();
+        db.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://b.org").unwrap();
+        let gpt2 = db.add_edge_with_evidence("GPT-2", "Transformer", "IS-A", "https://c.org").unwrap().unwrap();
+
+        db.merge_nodes("GPT", "GPT-2").unwrap();
+        let provenance = db.get_edge_provenance(gpt).unwrap().unwrap();
+        assert_eq!((provenance.support, provenance.sources.len()), (3, 3));
+        assert!(db.get_edge_provenance(gpt2).unwrap().is_none());
+    }
+
+    #[test]
+    fn reused_slots_start_with_fresh_provenance() {
+        let (_dir, mut db) = temp_db(None, None);
+        let gpt = db.add_edge_with_evidence("GPT", "Transformer", "IS-A", "https://a.org").unwrap().unwrap();
+        let bert = db.add_edge("BERT", "GPT", "IS-A").unwrap().unwrap();
+        db.remove_edge("BERT", "GPT", "IS-A").unwrap();
+        assert!(db.get_edge_provenance(bert).unwrap().is_none());
+
+        // Even if the slot kept stale provenance
+        let stale = db.get_edge_provenance(gpt).unwrap().unwrap();
+        db.transaction(|db| db.set_edge_provenance(bert, &stale)).unwrap();
+        assert_eq!(db.add_edge("BERT", "Transformer", "IS-A").unwrap(), Some(bert));
+        let provenance = db.get_edge_provenance(bert).unwrap().unwrap();
+        assert_eq!((provenance.support, provenance.sources.len()), (1, 0));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::errors::ReachdbError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    /// Keyword or name, relation names like `IS-A` included
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Colon,
+    Comma,
+    Dot,
+    DotDot,
+    Star,
+    Pipe,
+    Dash,
+    /// `->`
+    ArrowRight,
+    /// `<-`
+    ArrowLeft,
+}
+
+/// Token along with the byte offset where it starts, used in error messages
+pub(crate) type Spanned = (Token, usize);
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits a query into tokens
+///
+/// A `-` inside a word (`IS-A`, `RELATES-TO`) belongs to the word, so relation names need no quoting.
+pub(crate) fn tokenize(query: &str) -> Result<Vec<Spanned>, ReachdbError> {
+    let chars: Vec<(usize, char)> = query.char_indices().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        let peek = chars.get(i + 1).map(|(_, c)| *c);
+        let single = match c {
+            '(' => Some(Token::LParen),
+            ')' => Some(Token::RParen),
+            '[' => Some(Token::LBracket),
+            ']' => Some(Token::RBracket),
+            '{' => Some(Token::LBrace),
+            '}' => Some(Token::RBrace),
+            ':' => Some(Token::Colon),
+            ',' => Some(Token::Comma),
+            '*' => Some(Token::Star),
+            '|' => Some(Token::Pipe),
+            _ => None,
+        };
+        if let Some(token) = single {
+            tokens.push((token, pos));
+            i += 1;
+            continue;
+        }
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '.' if peek == Some('.') => {
+                tokens.push((Token::DotDot, pos));
+                i += 2;
+            }
+            '.' => {
+                tokens.push((Token::Dot, pos));
+                i += 1;
+            }
+            '-' if peek == Some('>') => {
+                tokens.push((Token::ArrowRight, pos));
+                i += 2;
+            }
+            '<' if peek == Some('-') => {
+                tokens.push((Token::ArrowLeft, pos));
+                i += 2;
+            }
+            '-' if peek.is_some_and(|c| c.is_ascii_digit()) && !matches!(tokens.last(), Some((Token::RParen | Token::RBracket, _))) => {
+                let (token, end) = number(&chars, i)?;
+                tokens.push((token, pos));
+                i = end;
+            }
+            '-' => {
+                tokens.push((Token::Dash, pos));
+                i += 1;
+            }
+            '"' | '\'' => {
+                let (value, end) = string(&chars, i)?;
+                tokens.push((Token::Str(value), pos));
+                i = end;
+            }
+            c if c.is_ascii_digit() => {
+                let (token, end) = number(&chars, i)?;
+                tokens.push((token, pos));
+                i = end;
+            }
+            c if is_ident_char(c) => {
+                let mut end = i;
+                let mut word = String::new();
+                while end < chars.len() {
+                    let c = chars[end].1;
+                    let next_is_word = chars.get(end + 1).is_some_and(|(_, c)| is_ident_char(*c));
+                    if is_ident_char(c) || (c == '-' && next_is_word) {
+                        word.push(c);
+                        end += 1;
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push((Token::Ident(word), pos));
+                i = end;
+            }
+            _ => return Err(ReachdbError::OtherError(format!("Unexpected character '{}' at {}", c, pos))),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a quoted string starting at the quote `chars[start]`, returns it with the index after the closing quote
+fn string(chars: &[(usize, char)], start: usize) -> Result<(String, usize), ReachdbError> {
+    let quote = chars[start].1;
+    let mut value = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i].1 {
+            '\\' if i + 1 < chars.len() => {
+                value.push(chars[i + 1].1);
+                i += 2;
+            }
+            c if c == quote => return Ok((value, i + 1)),
+            c => {
+                value.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(ReachdbError::OtherError(format!("Unterminated string at {}", chars[start].0)))
+}
+
+/// Reads an integer or a float, a `..` after the digits is a range and not a decimal point
+fn number(chars: &[(usize, char)], start: usize) -> Result<(Token, usize), ReachdbError> {
+    let mut text = String::new();
+    let mut i = start;
+    if chars[i].1 == '-' {
+        text.push('-');
+        i += 1;
+    }
+    let mut is_float = false;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        if c.is_ascii_digit() {
+            text.push(c);
+        } else if c == '.' && !is_float && next.is_some_and(|c| c.is_ascii_digit()) {
+            is_float = true;
+            text.push(c);
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    let invalid = || ReachdbError::OtherError(format!("Invalid number '{}' at {}", text, chars[start].0));
+    let token = if is_float {
+        Token::Float(text.parse().map_err(|_| invalid())?)
+    } else {
+        Token::Int(text.parse().map_err(|_| invalid())?)
+    };
+    Ok((token, i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Token};
+
+    #[test]
+    fn relation_names_and_ranges() {
+        let tokens: Vec<Token> = tokenize(r#"(a)-[IS-A*1..3]->(b {year: 2017})"#)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(tokens, vec![
+            Token::LParen, Token::Ident("a".into()), Token::RParen,
+            Token::Dash, Token::LBracket, Token::Ident("IS-A".into()),
+            Token::Star, Token::Int(1), Token::DotDot, Token::Int(3),
+            Token::RBracket, Token::ArrowRight,
+            Token::LParen, Token::Ident("b".into()), Token::LBrace,
+            Token::Ident("year".into()), Token::Colon, Token::Int(2017),
+            Token::RBrace, Token::RParen,
+        ]);
+    }
+}
@@ -0,0 +1,342 @@
+//! A small Cypher-like query language
+//!
+//! ```text
+//! MATCH (a)-[IS-A]->(b {name: "Transformer"}) RETURN a LIMIT 10
+//! MATCH (a {name: "BERT"})-[r:IS-A|RELATES-TO*1..3]->(b) RETURN DISTINCT b.name, r
+//! ```
+//!
+//! Patterns are matched by walking the relation chains of the nodes, starting from a node
+//! pinned by a `name` when there is one, and otherwise from every node.
+
+mod lexer;
+mod parser;
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+pub use parser::{NodePattern, Pattern, PatternDirection, Query, RelationPattern, ReturnItem};
+
+/// Rows of a query, each value is a node name, a property or relation names
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl fmt::Display for QueryResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut widths: Vec<usize> = self.columns.iter().map(|c| c.chars().count()).collect();
+        for row in &self.rows {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        let line = |f: &mut fmt::Formatter<'_>, values: &[String]| {
+            let cells: Vec<String> = values.iter().zip(&widths).map(|(v, w)| format!("{:<w$}", v, w = w)).collect();
+            writeln!(f, "{}", cells.join(" | ").trim_end())
+        };
+        line(f, &self.columns)?;
+        let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+        writeln!(f, "{}", rule.join("-+-"))?;
+        for row in &self.rows {
+            line(f, row)?;
+        }
+        write!(f, "({} rows)", self.rows.len())
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Parses and runs a query
+    pub fn query(&self, query: &str) -> Result<QueryResult, ReachdbError> {
+        self.execute(&query.parse()?)
+    }
+
+    /// Runs a parsed query
+    pub fn execute(&self, query: &Query) -> Result<QueryResult, ReachdbError> {
+        let mut type_filters = Vec::with_capacity(query.pattern.relations.len());
+        for relation in &query.pattern.relations {
+            let mut ids = Vec::with_capacity(relation.types.len());
+            for name in &relation.types {
+                match self.relation_type_id(name) {
+                    Some(type_id) => ids.push(type_id),
+                    None => return Err(ReachdbError::OtherError(format!(
+                        "Unknown relation type {}, write {}: to bind a relation variable", name, name
+                    ))),
+                }
+            }
+            type_filters.push(ids);
+        }
+
+        let mut matcher = Matcher {
+            db: self,
+            query,
+            type_filters,
+            steps: Vec::new(),
+            nodes: vec![None; query.pattern.nodes.len()],
+            paths: vec![Vec::new(); query.pattern.relations.len()],
+            used: HashSet::new(),
+            seen: HashSet::new(),
+            result: QueryResult {
+                columns: query.returns.iter().map(ReturnItem::column).collect(),
+                rows: Vec::new(),
+            },
+        };
+        if query.limit == Some(0) {
+            return Ok(matcher.result);
+        }
+
+        // Start from the first node pinned by its name, and grow the match to both sides
+        let anchor = query.pattern.nodes.iter()
+            .position(|node| node.properties.iter().any(|(key, _)| key == "name"))
+            .unwrap_or(0);
+        matcher.steps.extend((anchor..query.pattern.relations.len()).map(|i| (i, true)));
+        matcher.steps.extend((0..anchor).rev().map(|i| (i, false)));
+
+        let start_ids = match query.pattern.nodes[anchor].properties.iter().find(|(key, _)| key == "name") {
+            Some((_, name)) => self.get_node_id(&name.to_string())?.into_iter().collect(),
+            None => self.get_all_node_ids()?,
+        };
+        for node_id in start_ids {
+            if matcher.bind(anchor, node_id)? {
+                let done = matcher.step(0)?;
+                matcher.nodes[anchor] = None;
+                if done {
+                    break;
+                }
+            }
+        }
+        Ok(matcher.result)
+    }
+}
+
+struct Matcher<'a, E: UserDefinedRelationType> {
+    db: &'a Reachdb<E>,
+    query: &'a Query,
+    /// Type ids each relation pattern may follow, empty for any
+    type_filters: Vec<Vec<u8>>,
+    /// Relation pattern to expand at each step, and whether it is walked left to right
+    steps: Vec<(usize, bool)>,
+    /// Node bound to each node pattern
+    nodes: Vec<Option<u64>>,
+    /// Relations walked for each relation pattern, in the order they were walked
+    paths: Vec<Vec<u64>>,
+    /// A relation is used at most once in a match, which also ends unbounded paths
+    used: HashSet<u64>,
+    /// Rows already returned, for DISTINCT
+    seen: HashSet<Vec<String>>,
+    result: QueryResult,
+}
+
+impl<E: UserDefinedRelationType> Matcher<'_, E> {
+    /// Binds `node_id` to the node pattern `position` if it passes its properties
+    /// and agrees with the other uses of its variable
+    fn bind(&mut self, position: usize, node_id: u64) -> Result<bool, ReachdbError> {
+        let pattern = &self.query.pattern.nodes[position];
+        if let Some(variable) = &pattern.variable {
+            let clash = self.query.pattern.nodes.iter().zip(&self.nodes).any(|(other, bound)| {
+                other.variable.as_ref() == Some(variable) && bound.is_some_and(|id| id != node_id)
+            });
+            if clash {
+                return Ok(false);
+            }
+        }
+        if !pattern.properties.is_empty() {
+            let properties = self.db.get_node_properties(node_id)?;
+            for (key, expected) in &pattern.properties {
+                // Aliases of the node match its name too
+                let matches = if key == "name" {
+                    match expected.as_str() {
+                        Some(name) => self.db.get_node_id(name)? == Some(node_id),
+                        None => false,
+                    }
+                } else {
+                    properties.get(key).is_some_and(|value| match (value.as_f64(), expected.as_f64()) {
+                        (Some(a), Some(b)) => a == b,
+                        _ => value == expected,
+                    })
+                };
+                if !matches {
+                    return Ok(false);
+                }
+            }
+        }
+        self.nodes[position] = Some(node_id);
+        Ok(true)
+    }
+
+    /// Expands the pattern from `steps[step]` on, returns `true` once the LIMIT is reached
+    fn step(&mut self, step: usize) -> Result<bool, ReachdbError> {
+        let Some(&(relation, forward)) = self.steps.get(step) else {
+            return self.emit();
+        };
+        let (from, to) = if forward { (relation, relation + 1) } else { (relation + 1, relation) };
+        let start = self.nodes[from].expect("the node a step starts from is bound");
+        self.walk(step, relation, forward, to, start)
+    }
+
+    /// Follows relation pattern `relation` from `node`, trying to close the path at every allowed length
+    fn walk(&mut self, step: usize, relation: usize, forward: bool, to: usize, node: u64) -> Result<bool, ReachdbError> {
+        let pattern = &self.query.pattern.relations[relation];
+        let hops = self.paths[relation].len();
+
+        if hops >= pattern.min_hops {
+            let previous = self.nodes[to];
+            let bound = match previous {
+                // The other end was bound by an earlier step, the path has to reach it
+                Some(id) => id == node,
+                None => self.bind(to, node)?,
+            };
+            if bound {
+                let done = self.step(step + 1)?;
+                self.nodes[to] = previous;
+                if done {
+                    return Ok(true);
+                }
+            }
+        }
+        if pattern.max_hops.is_some_and(|max| hops >= max) {
+            return Ok(false);
+        }
+
+        // Walking right to left follows the arrows backwards
+        let (outgoing, incoming) = match (pattern.direction, forward) {
+            (PatternDirection::Either, _) => (true, true),
+            (PatternDirection::Outgoing, true) | (PatternDirection::Incoming, false) => (true, false),
+            (PatternDirection::Outgoing, false) | (PatternDirection::Incoming, true) => (false, true),
+        };
+        for rel_id in self.db.get_all_node_relations(node)? {
+            if self.used.contains(&rel_id) {
+                continue;
+            }
+            let rel = self.db.get_relation(rel_id)?;
+            let filter = &self.type_filters[relation];
+            if !filter.is_empty() && !filter.contains(&rel.type_id) {
+                continue;
+            }
+            let next = if outgoing && rel.source_id == node {
+                rel.target_id
+            } else if incoming && rel.target_id == node {
+                rel.source_id
+            } else {
+                continue;
+            };
+
+            self.used.insert(rel_id);
+            self.paths[relation].push(rel_id);
+            let done = self.walk(step, relation, forward, to, next)?;
+            self.paths[relation].pop();
+            self.used.remove(&rel_id);
+            if done {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Adds the row of the current bindings, returns `true` once the LIMIT is reached
+    fn emit(&mut self) -> Result<bool, ReachdbError> {
+        let mut row = Vec::with_capacity(self.query.returns.len());
+        for item in &self.query.returns {
+            row.push(match item {
+                ReturnItem::Variable(variable) => self.render_variable(variable)?,
+                ReturnItem::Property(variable, key) => {
+                    let node_id = self.node_of(variable);
+                    if key == "name" {
+                        self.db.get_node_name(node_id)?
+                    } else {
+                        self.db.get_node_properties(node_id)?.get(key).map(|v| v.to_string()).unwrap_or_default()
+                    }
+                }
+            });
+        }
+        if !self.query.distinct || self.seen.insert(row.clone()) {
+            self.result.rows.push(row);
+        }
+        Ok(self.query.limit.is_some_and(|limit| self.result.rows.len() >= limit))
+    }
+
+    fn node_of(&self, variable: &str) -> u64 {
+        self.query.pattern.nodes.iter()
+            .position(|node| node.variable.as_deref() == Some(variable))
+            .and_then(|position| self.nodes[position])
+            .expect("returned variables are bound by the pattern")
+    }
+
+    /// Name of a node, the relation name of a single relation, or the list of names along a path
+    fn render_variable(&self, variable: &str) -> Result<String, ReachdbError> {
+        let relation = self.query.pattern.relations.iter()
+            .position(|relation| relation.variable.as_deref() == Some(variable));
+        let Some(relation) = relation else {
+            return self.db.get_node_name(self.node_of(variable));
+        };
+
+        let pattern = &self.query.pattern.relations[relation];
+        let forward = self.steps.iter().any(|&(r, forward)| r == relation && forward);
+        let mut names = Vec::with_capacity(self.paths[relation].len());
+        for rel_id in &self.paths[relation] {
+            names.push(self.db.get_relation_type_str(self.db.get_relation(*rel_id)?.type_id));
+        }
+        if !forward {
+            names.reverse();
+        }
+        if pattern.min_hops == 1 && pattern.max_hops == Some(1) {
+            Ok(names.pop().unwrap_or_default())
+        } else {
+            Ok(format!("[{}]", names.join(", ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, transformer_taxonomy};
+    use crate::PropertyValue;
+
+    #[test]
+    fn matches_single_relation_patterns() {
+        let (_dir, db) = transformer_taxonomy();
+        let result = db.query(r#"MATCH (a)-[IS-A]->(b {name:"Transformer"}) RETURN a LIMIT 10"#).unwrap();
+        assert_eq!(result.columns, vec!["a"]);
+        let mut names: Vec<&str> = result.rows.iter().map(|row| row[0].as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["BERT", "GPT"]);
+    }
+
+    #[test]
+    fn returns_the_relations_of_variable_length_paths() {
+        let (_dir, db) = transformer_taxonomy();
+        let result = db.query(r#"MATCH (a {name: "BERT"})-[p:IS-A*]->(b) RETURN b, p"#).unwrap();
+        let mut rows = result.rows.clone();
+        rows.sort();
+        assert_eq!(rows, vec![
+            vec!["Neural Network".to_string(), "[IS-A, IS-A]".to_string()],
+            vec!["Transformer".to_string(), "[IS-A]".to_string()],
+        ]);
+    }
+
+    #[test]
+    fn walks_from_an_anchor_on_the_right() {
+        let (_dir, db) = transformer_taxonomy();
+        let result = db.query(r#"MATCH (x)-[r:]->(t)-[IS-A]->(n {name: "Neural Network"}) RETURN DISTINCT r"#).unwrap();
+        let mut relations: Vec<&str> = result.rows.iter().map(|row| row[0].as_str()).collect();
+        relations.sort();
+        assert_eq!(relations, vec!["INFLUENCES", "IS-A"]);
+    }
+
+    #[test]
+    fn filters_and_returns_properties() {
+        let (_dir, mut db) = transformer_taxonomy();
+        let gpt = node_id(&db, "GPT");
+        db.set_node_property(gpt, "year", PropertyValue::Int(2018)).unwrap();
+        let result = db.query("MATCH (a {year: 2018})--(b) RETURN a.name, a.year, b").unwrap();
+        assert_eq!(result.rows, vec![vec!["GPT".to_string(), "2018".to_string(), "Transformer".to_string()]]);
+    }
+
+    #[test]
+    fn limits_rows_and_rejects_unknown_relations() {
+        let (_dir, db) = transformer_taxonomy();
+        assert_eq!(db.query("MATCH (a)-->(b) RETURN a LIMIT 2").unwrap().rows.len(), 2);
+        assert!(db.query("MATCH (a)-[CITES]->(b) RETURN a").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::{errors::ReachdbError, records::property::PropertyValue};
+use super::lexer::{tokenize, Spanned, Token};
+
+/// Parsed form of `MATCH <pattern> RETURN [DISTINCT] <items> [LIMIT <n>]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub pattern: Pattern,
+    pub distinct: bool,
+    pub returns: Vec<ReturnItem>,
+    pub limit: Option<usize>,
+}
+
+/// Nodes joined by relations, `relations[i]` links `nodes[i]` to `nodes[i + 1]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub nodes: Vec<NodePattern>,
+    pub relations: Vec<RelationPattern>,
+}
+
+/// `(variable {key: value, ...})`, both parts optional
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NodePattern {
+    pub variable: Option<String>,
+    pub properties: Vec<(String, PropertyValue)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternDirection {
+    /// `-[]->`
+    Outgoing,
+    /// `<-[]-`
+    Incoming,
+    /// `-[]-`
+    Either,
+}
+
+/// `-[variable:TYPE|TYPE*min..max]->`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationPattern {
+    pub variable: Option<String>,
+    /// Relation names to follow, empty for any type
+    pub types: Vec<String>,
+    pub direction: PatternDirection,
+    pub min_hops: usize,
+    /// `None` for an unbounded path
+    pub max_hops: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReturnItem {
+    /// Name of a node, or the relation name(s) of a relation variable
+    Variable(String),
+    /// `variable.key`, `name` gives the node name
+    Property(String, String),
+}
+
+impl ReturnItem {
+    /// Column header of the item
+    pub fn column(&self) -> String {
+        match self {
+            Self::Variable(variable) => variable.clone(),
+            Self::Property(variable, key) => format!("{}.{}", variable, key),
+        }
+    }
+}
+
+impl std::str::FromStr for Query {
+    type Err = ReachdbError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        Parser { tokens: tokenize(query)?, pos: 0, end: query.len() }.query()
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    /// Length of the query, reported for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn error(&self, expected: &str) -> ReachdbError {
+        match self.tokens.get(self.pos) {
+            Some((token, at)) => ReachdbError::OtherError(format!("Expected {} at {}, found {:?}", expected, at, token)),
+            None => ReachdbError::OtherError(format!("Expected {} at {}, found the end of the query", expected, self.end)),
+        }
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), ReachdbError> {
+        if self.eat(&token) { Ok(()) } else { Err(self.error(expected)) }
+    }
+
+    /// Consumes the keyword, keywords are case-insensitive
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<String, ReachdbError> {
+        match self.peek() {
+            Some(Token::Ident(word)) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    fn integer(&mut self, expected: &str) -> Result<usize, ReachdbError> {
+        match self.peek() {
+            Some(Token::Int(n)) if *n >= 0 => {
+                let n = *n as usize;
+                self.pos += 1;
+                Ok(n)
+            }
+            _ => Err(self.error(expected)),
+        }
+    }
+
+    fn query(mut self) -> Result<Query, ReachdbError> {
+        if !self.eat_keyword("MATCH") {
+            return Err(self.error("MATCH"));
+        }
+        let pattern = self.pattern()?;
+
+        if !self.eat_keyword("RETURN") {
+            return Err(self.error("RETURN"));
+        }
+        let distinct = self.eat_keyword("DISTINCT");
+        let mut returns = vec![self.return_item()?];
+        while self.eat(&Token::Comma) {
+            returns.push(self.return_item()?);
+        }
+
+        let limit = if self.eat_keyword("LIMIT") { Some(self.integer("a LIMIT count")?) } else { None };
+        if self.peek().is_some() {
+            return Err(self.error("the end of the query"));
+        }
+
+        let query = Query { pattern, distinct, returns, limit };
+        query.check_variables()?;
+        Ok(query)
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, ReachdbError> {
+        let mut nodes = vec![self.node()?];
+        let mut relations = Vec::new();
+        while matches!(self.peek(), Some(Token::Dash | Token::ArrowLeft)) {
+            relations.push(self.relation()?);
+            nodes.push(self.node()?);
+        }
+        Ok(Pattern { nodes, relations })
+    }
+
+    fn node(&mut self) -> Result<NodePattern, ReachdbError> {
+        self.expect(Token::LParen, "'('")?;
+        let mut node = NodePattern::default();
+        if let Some(Token::Ident(_)) = self.peek() {
+            node.variable = Some(self.ident("a variable")?);
+        }
+        if self.eat(&Token::LBrace) {
+            loop {
+                let key = self.ident("a property name")?;
+                self.expect(Token::Colon, "':'")?;
+                let value = match self.next() {
+                    Some(Token::Str(s)) => PropertyValue::String(s),
+                    Some(Token::Int(i)) => PropertyValue::Int(i),
+                    Some(Token::Float(f)) => PropertyValue::Float(f),
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("a string or a number"));
+                    }
+                };
+                node.properties.push((key, value));
+                if !self.eat(&Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::RBrace, "'}'")?;
+        }
+        self.expect(Token::RParen, "')'")?;
+        Ok(node)
+    }
+
+    fn relation(&mut self) -> Result<RelationPattern, ReachdbError> {
+        let incoming = self.eat(&Token::ArrowLeft);
+        if !incoming {
+            self.expect(Token::Dash, "'-'")?;
+        }
+
+        let mut relation = RelationPattern {
+            variable: None,
+            types: Vec::new(),
+            direction: PatternDirection::Either,
+            min_hops: 1,
+            max_hops: Some(1),
+        };
+        if self.eat(&Token::LBracket) {
+            self.relation_detail(&mut relation)?;
+            self.expect(Token::RBracket, "']'")?;
+        }
+
+        let outgoing = match self.next() {
+            Some(Token::ArrowRight) => true,
+            Some(Token::Dash) => false,
+            _ => {
+                self.pos -= 1;
+                return Err(self.error("'->' or '-'"));
+            }
+        };
+        relation.direction = match (incoming, outgoing) {
+            (false, true) => PatternDirection::Outgoing,
+            (true, false) => PatternDirection::Incoming,
+            (false, false) => PatternDirection::Either,
+            (true, true) => return Err(ReachdbError::OtherError("A relation cannot point both ways".to_string())),
+        };
+        Ok(relation)
+    }
+
+    /// Inside the brackets: `r:IS-A|RELATES-TO`, `:IS-A`, a bare `IS-A` and the `*min..max` range
+    fn relation_detail(&mut self, relation: &mut RelationPattern) -> Result<(), ReachdbError> {
+        if let Some(Token::Ident(_)) = self.peek() {
+            let word = self.ident("a relation")?;
+            if self.eat(&Token::Colon) {
+                relation.variable = Some(word);
+            } else {
+                // A bare word names the relation type, as in `-[IS-A]->`
+                relation.types.push(word);
+                while self.eat(&Token::Pipe) {
+                    relation.types.push(self.ident("a relation name")?);
+                }
+            }
+        } else {
+            self.eat(&Token::Colon);
+        }
+        if relation.types.is_empty() && matches!(self.peek(), Some(Token::Ident(_))) {
+            relation.types.push(self.ident("a relation name")?);
+            while self.eat(&Token::Pipe) {
+                relation.types.push(self.ident("a relation name")?);
+            }
+        }
+
+        if self.eat(&Token::Star) {
+            relation.min_hops = 1;
+            relation.max_hops = None;
+            if let Some(Token::Int(_)) = self.peek() {
+                let min = self.integer("a hop count")?;
+                relation.min_hops = min;
+                if !self.eat(&Token::DotDot) {
+                    // `*2` is exactly two hops
+                    relation.max_hops = Some(min);
+                    return Ok(());
+                }
+            } else if !self.eat(&Token::DotDot) {
+                return Ok(());
+            }
+            if let Some(Token::Int(_)) = self.peek() {
+                relation.max_hops = Some(self.integer("a hop count")?);
+            }
+            if relation.max_hops.is_some_and(|max| max < relation.min_hops) {
+                return Err(ReachdbError::OtherError(format!(
+                    "The hop range {}..{} is empty", relation.min_hops, relation.max_hops.unwrap_or_default()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn return_item(&mut self) -> Result<ReturnItem, ReachdbError> {
+        let variable = self.ident("a variable to return")?;
+        if self.eat(&Token::Dot) {
+            Ok(ReturnItem::Property(variable, self.ident("a property name")?))
+        } else {
+            Ok(ReturnItem::Variable(variable))
+        }
+    }
+}
+
+impl Query {
+    /// Returned variables must be bound by the pattern, and a name cannot stand for both a node and a relation
+    fn check_variables(&self) -> Result<(), ReachdbError> {
+        let nodes: Vec<&String> = self.pattern.nodes.iter().filter_map(|n| n.variable.as_ref()).collect();
+        let mut relations: Vec<&String> = Vec::new();
+        for variable in self.pattern.relations.iter().filter_map(|r| r.variable.as_ref()) {
+            if nodes.contains(&variable) || relations.contains(&variable) {
+                return Err(ReachdbError::OtherError(format!("The variable {} is bound twice", variable)));
+            }
+            relations.push(variable);
+        }
+        for item in &self.returns {
+            match item {
+                ReturnItem::Variable(v) if !nodes.contains(&v) && !relations.contains(&v) => {
+                    return Err(ReachdbError::OtherError(format!("Unknown variable {}", v)));
+                }
+                ReturnItem::Property(v, _) if !nodes.contains(&v) => {
+                    return Err(ReachdbError::OtherError(format!("Unknown node variable {}", v)));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns_ranges_and_returns() {
+        let query: Query = r#"match (a)-[IS-A|RELATES-TO*..3]->(b {name:"Transformer"})<-[r:]-(c) RETURN DISTINCT a, r, b.name LIMIT 10"#
+            .parse()
+            .unwrap();
+        assert_eq!(query.pattern.nodes.len(), 3);
+        assert_eq!(query.pattern.nodes[1].properties, vec![("name".to_string(), PropertyValue::from("Transformer"))]);
+
+        let first = &query.pattern.relations[0];
+        assert_eq!(first.types, vec!["IS-A", "RELATES-TO"]);
+        assert_eq!((first.direction, first.min_hops, first.max_hops), (PatternDirection::Outgoing, 1, Some(3)));
+
+        let second = &query.pattern.relations[1];
+        assert_eq!(second.variable.as_deref(), Some("r"));
+        assert!(second.types.is_empty());
+        assert_eq!((second.direction, second.min_hops, second.max_hops), (PatternDirection::Incoming, 1, Some(1)));
+
+        assert!(query.distinct);
+        assert_eq!(query.returns[2], ReturnItem::Property("b".into(), "name".into()));
+        assert_eq!(query.limit, Some(10));
+
+        assert!("MATCH (a)-[*3..1]->(b) RETURN a".parse::<Query>().is_err());
+        assert!("MATCH (a)-->(b) RETURN c".parse::<Query>().is_err());
+        assert!("MATCH (a)<-[]->(b) RETURN a".parse::<Query>().is_err());
+    }
+}
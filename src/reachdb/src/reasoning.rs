@@ -0,0 +1,366 @@
+//! Reasoning over a hierarchical relation such as `IS-A`
+//!
+//! A relation `child -[IS-A]-> parent` makes the target the more general concept. Following
+//! those relations transitively gives the inferred ones: `GPT -[IS-A]-> Transformer` and
+//! `Transformer -[IS-A]-> Neural Network` make GPT a neural network too.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+
+/// The relations of one hierarchical type, read once from the chains
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hierarchy {
+    pub type_id: u8,
+    /// Direct parents and children of every concept, sorted
+    parents: BTreeMap<u64, Vec<u64>>,
+    children: BTreeMap<u64, Vec<u64>>,
+}
+
+/// Nodes reachable from `start` through `links`, nearest first, `start` included only on a cycle
+fn reach(links: &BTreeMap<u64, Vec<u64>>, start: u64) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([start]);
+    let mut reached = Vec::new();
+    while let Some(node) = queue.pop_front() {
+        for next in links.get(&node).into_iter().flatten() {
+            if seen.insert(*next) {
+                reached.push(*next);
+                queue.push_back(*next);
+            }
+        }
+    }
+    reached
+}
+
+impl Hierarchy {
+    /// Hierarchy from `(child, parent)` pairs
+    pub fn from_pairs(type_id: u8, pairs: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let mut hierarchy = Self { type_id, ..Default::default() };
+        for (child, parent) in pairs {
+            hierarchy.parents.entry(child).or_default().push(parent);
+            hierarchy.children.entry(parent).or_default().push(child);
+            hierarchy.parents.entry(parent).or_default();
+            hierarchy.children.entry(child).or_default();
+        }
+        for links in hierarchy.parents.values_mut().chain(hierarchy.children.values_mut()) {
+            links.sort_unstable();
+            links.dedup();
+        }
+        hierarchy
+    }
+
+    /// Nodes with at least one relation of the type, sorted
+    pub fn concepts(&self) -> Vec<u64> {
+        self.parents.keys().copied().collect()
+    }
+
+    pub fn parents(&self, node_id: u64) -> &[u64] {
+        self.parents.get(&node_id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn children(&self, node_id: u64) -> &[u64] {
+        self.children.get(&node_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Direct and inferred parents, nearest first
+    pub fn ancestors(&self, node_id: u64) -> Vec<u64> {
+        let mut ancestors = reach(&self.parents, node_id);
+        ancestors.retain(|id| *id != node_id);
+        ancestors
+    }
+
+    /// Direct and inferred children, nearest first
+    pub fn descendants(&self, node_id: u64) -> Vec<u64> {
+        let mut descendants = reach(&self.children, node_id);
+        descendants.retain(|id| *id != node_id);
+        descendants
+    }
+
+    /// Whether `ancestor` is a direct or inferred parent of `node_id`
+    pub fn is_a(&self, node_id: u64, ancestor: u64) -> bool {
+        node_id != ancestor && reach(&self.parents, node_id).contains(&ancestor)
+    }
+
+    /// Concepts without parents that have children
+    pub fn roots(&self) -> Vec<u64> {
+        self.parents.iter()
+            .filter(|(id, parents)| parents.is_empty() && !self.children(**id).is_empty())
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// All ancestors of every concept
+    pub fn transitive_closure(&self) -> BTreeMap<u64, BTreeSet<u64>> {
+        self.parents.keys()
+            .map(|id| (*id, self.ancestors(*id).into_iter().collect()))
+            .collect()
+    }
+
+    /// `(child, ancestor)` pairs of the closure that are not stored as relations
+    pub fn inferred_pairs(&self) -> Vec<(u64, u64)> {
+        let mut pairs = Vec::new();
+        for (child, ancestors) in self.transitive_closure() {
+            for ancestor in ancestors {
+                if self.parents(child).binary_search(&ancestor).is_err() {
+                    pairs.push((child, ancestor));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Groups of concepts that are each other's ancestors, a self-loop is a group of one
+    ///
+    /// A hierarchy should have none, they usually come from an extraction that swapped
+    /// source and target.
+    pub fn cycles(&self) -> Vec<Vec<u64>> {
+        let mut assigned = HashSet::new();
+        let mut cycles = Vec::new();
+        for id in self.parents.keys() {
+            if assigned.contains(id) {
+                continue;
+            }
+            let ancestors = reach(&self.parents, *id);
+            if !ancestors.contains(id) {
+                continue;
+            }
+            let descendants: HashSet<u64> = reach(&self.children, *id).into_iter().collect();
+            let mut cycle: Vec<u64> = ancestors.into_iter().filter(|a| descendants.contains(a)).collect();
+            cycle.sort_unstable();
+            assigned.extend(cycle.iter().copied());
+            cycles.push(cycle);
+        }
+        cycles
+    }
+}
+
+/// A concept placed in a `Taxonomy`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Concept {
+    pub node_id: u64,
+    pub name: String,
+    /// Parent in the tree, on a shortest path to the root, `None` for a root
+    pub parent: Option<u64>,
+    /// Concepts whose tree parent this is, sorted by name
+    pub children: Vec<u64>,
+    /// Relations from the root
+    pub depth: usize,
+    /// Direct and inferred parents and children, nearest first, including those outside the tree
+    pub ancestors: Vec<u64>,
+    pub descendants: Vec<u64>,
+}
+
+/// Tree extracted from a hierarchy, created by `Reachdb::taxonomy`
+///
+/// A concept with several parents is placed under the one closest to a root, the others stay
+/// in its `ancestors`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Taxonomy {
+    pub roots: Vec<u64>,
+    pub concepts: BTreeMap<u64, Concept>,
+    /// Cycles of the hierarchy, their concepts are only in the tree when a root reaches them
+    pub cycles: Vec<Vec<u64>>,
+}
+
+impl Taxonomy {
+    pub fn get(&self, node_id: u64) -> Option<&Concept> {
+        self.concepts.get(&node_id)
+    }
+
+    /// Height of the tree, 0 for a lone root
+    pub fn depth(&self) -> usize {
+        self.concepts.values().map(|concept| concept.depth).max().unwrap_or_default()
+    }
+
+    fn fmt_concept(&self, f: &mut fmt::Formatter<'_>, node_id: u64) -> fmt::Result {
+        let concept = &self.concepts[&node_id];
+        writeln!(f, "{}{}", "  ".repeat(concept.depth), concept.name)?;
+        for child in &concept.children {
+            self.fmt_concept(f, *child)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Taxonomy {
+    /// One concept per line, indented by depth
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for root in &self.roots {
+            self.fmt_concept(f, *root)?;
+        }
+        for cycle in &self.cycles {
+            let names: Vec<String> = cycle.iter()
+                .map(|id| self.concepts.get(id).map_or(format!("(id:{})", id), |concept| concept.name.clone()))
+                .collect();
+            writeln!(f, "cycle: {}", names.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Reads the relations of type `relation` as a hierarchy, from the child to the parent
+    pub fn hierarchy(&self, relation: &str) -> Result<Hierarchy, ReachdbError> {
+        let type_id = self.relation_type_id(relation)
+            .ok_or_else(|| ReachdbError::OtherError(format!("Unknown relation type {}", relation)))?;
+        let mut pairs = Vec::new();
+        for node_id in self.get_all_node_ids()? {
+            for rel_id in self.get_outgoing_node_relations(node_id)? {
+                let rel = self.get_relation(rel_id)?;
+                if rel.type_id == type_id {
+                    pairs.push((rel.source_id, rel.target_id));
+                }
+            }
+        }
+        Ok(Hierarchy::from_pairs(type_id, pairs))
+    }
+
+    fn concept_id(&self, concept: &str) -> Result<u64, ReachdbError> {
+        self.get_node_id(concept)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node {} not found", concept)))
+    }
+
+    /// Direct and inferred subtypes of `concept` under `relation`, nearest first
+    pub fn subtypes_of(&self, concept: &str, relation: &str) -> Result<Vec<u64>, ReachdbError> {
+        Ok(self.hierarchy(relation)?.descendants(self.concept_id(concept)?))
+    }
+
+    /// Direct and inferred supertypes of `concept` under `relation`, nearest first
+    pub fn supertypes_of(&self, concept: &str, relation: &str) -> Result<Vec<u64>, ReachdbError> {
+        Ok(self.hierarchy(relation)?.ancestors(self.concept_id(concept)?))
+    }
+
+    /// Tree of the concepts below `root`, or of the whole hierarchy from its roots
+    pub fn taxonomy(&self, relation: &str, root: Option<&str>) -> Result<Taxonomy, ReachdbError> {
+        let hierarchy = self.hierarchy(relation)?;
+        let roots = match root {
+            Some(root) => vec![self.concept_id(root)?],
+            None => hierarchy.roots(),
+        };
+
+        // Breadth first from all roots, a concept hangs under the parent that reached it first
+        let mut placed: BTreeMap<u64, (Option<u64>, usize)> = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        for root in &roots {
+            placed.insert(*root, (None, 0));
+            queue.push_back(*root);
+        }
+        while let Some(node_id) = queue.pop_front() {
+            let depth = placed[&node_id].1;
+            for child in hierarchy.children(node_id) {
+                if !placed.contains_key(child) {
+                    placed.insert(*child, (Some(node_id), depth + 1));
+                    queue.push_back(*child);
+                }
+            }
+        }
+
+        let mut taxonomy = Taxonomy { roots, ..Default::default() };
+        for (node_id, (parent, depth)) in &placed {
+            taxonomy.concepts.insert(*node_id, Concept {
+                node_id: *node_id,
+                name: self.get_node_name(*node_id)?,
+                parent: *parent,
+                children: Vec::new(),
+                depth: *depth,
+                ancestors: hierarchy.ancestors(*node_id),
+                descendants: hierarchy.descendants(*node_id),
+            });
+        }
+        for (node_id, (parent, _)) in &placed {
+            if let Some(parent) = parent {
+                taxonomy.concepts.get_mut(parent).expect("parents are placed first").children.push(*node_id);
+            }
+        }
+        let names: BTreeMap<u64, String> = taxonomy.concepts.iter().map(|(id, concept)| (*id, concept.name.clone())).collect();
+        for concept in taxonomy.concepts.values_mut() {
+            concept.children.sort_by(|a, b| names[a].cmp(&names[b]));
+        }
+        taxonomy.roots.sort_by_key(|id| names.get(id).cloned());
+
+        taxonomy.cycles = hierarchy.cycles();
+        if root.is_some() {
+            taxonomy.cycles.retain(|cycle| cycle.iter().any(|id| placed.contains_key(id)));
+        }
+        Ok(taxonomy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, node_names, transformer_taxonomy, TypeId};
+    use crate::Reachdb;
+
+    /// The transformer taxonomy with "CNN" and "GPT-4", which also reaches the root directly
+    fn taxonomy_with_a_shortcut() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = transformer_taxonomy();
+        db.add_edge("CNN", "Neural Network", "IS-A").unwrap();
+        db.add_edge("GPT-4", "GPT", "IS-A").unwrap();
+        // A second, longer way up
+        db.add_edge("GPT-4", "Neural Network", "IS-A").unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn infers_subtypes_and_supertypes() {
+        let (_dir, db) = taxonomy_with_a_shortcut();
+        assert_eq!(node_names(&db, db.subtypes_of("Transformer", "IS-A").unwrap()), vec!["BERT", "GPT", "GPT-4"]);
+        assert_eq!(node_names(&db, db.supertypes_of("GPT-4", "IS-A").unwrap()), vec!["GPT", "Neural Network", "Transformer"]);
+        assert!(db.hierarchy("CITES").is_err());
+    }
+
+    #[test]
+    fn queries_reach_the_inferred_subtypes() {
+        let (_dir, db) = taxonomy_with_a_shortcut();
+        // Through paths of any length
+        let result = db.query(r#"MATCH (a)-[IS-A*]->(t {name: "Transformer"}) RETURN DISTINCT a"#).unwrap();
+        let mut queried: Vec<String> = result.rows.into_iter().map(|mut row| row.remove(0)).collect();
+        queried.sort();
+        assert_eq!(queried, node_names(&db, db.subtypes_of("Transformer", "IS-A").unwrap()));
+    }
+
+    #[test]
+    fn hierarchy_answers_is_a_and_lists_inferred_pairs() {
+        let (_dir, db) = taxonomy_with_a_shortcut();
+        let hierarchy = db.hierarchy("IS-A").unwrap();
+        assert!(hierarchy.is_a(node_id(&db, "BERT"), node_id(&db, "Neural Network")));
+        assert!(!hierarchy.is_a(node_id(&db, "Attention"), node_id(&db, "Neural Network")));
+        assert!(hierarchy.inferred_pairs().contains(&(node_id(&db, "BERT"), node_id(&db, "Neural Network"))));
+        // Stated directly, so not inferred
+        assert!(!hierarchy.inferred_pairs().contains(&(node_id(&db, "GPT-4"), node_id(&db, "Neural Network"))));
+        assert!(hierarchy.cycles().is_empty());
+    }
+
+    #[test]
+    fn taxonomy_places_concepts_under_their_nearest_root() {
+        let (_dir, db) = taxonomy_with_a_shortcut();
+        let taxonomy = db.taxonomy("IS-A", None).unwrap();
+        assert_eq!(taxonomy.roots, vec![node_id(&db, "Neural Network")]);
+        // Placed under the root it reaches directly, GPT stays an ancestor
+        let gpt4 = taxonomy.get(node_id(&db, "GPT-4")).unwrap();
+        assert_eq!((gpt4.parent, gpt4.depth), (Some(node_id(&db, "Neural Network")), 1));
+        assert!(gpt4.ancestors.contains(&node_id(&db, "GPT")));
+        assert_eq!(taxonomy.get(node_id(&db, "BERT")).unwrap().depth, 2);
+        assert_eq!(taxonomy.to_string(), "Neural Network\n  CNN\n  GPT-4\n  Transformer\n    BERT\n    GPT\n");
+    }
+
+    #[test]
+    fn taxonomy_below_a_root() {
+        let (_dir, db) = taxonomy_with_a_shortcut();
+        let subtree = db.taxonomy("IS-A", Some("Transformer")).unwrap();
+        assert_eq!(subtree.concepts.len(), 4);
+        assert_eq!(subtree.depth(), 2);
+    }
+
+    #[test]
+    fn cycles_are_found_and_have_no_root() {
+        let (_dir, mut db) = taxonomy_with_a_shortcut();
+        db.add_edge("Neural Network", "GPT", "IS-A").unwrap();
+        let mut cycle = vec![node_id(&db, "GPT"), node_id(&db, "Transformer"), node_id(&db, "Neural Network")];
+        cycle.sort_unstable();
+        assert_eq!(db.hierarchy("IS-A").unwrap().cycles(), vec![cycle]);
+        assert!(db.taxonomy("IS-A", None).unwrap().roots.is_empty());
+    }
+}
@@ -1,27 +1,148 @@
 pub mod node;
 pub mod relationship;
+pub mod property;
 
 use super::errors::ReachdbError;
-use memmap2::MmapMut;
 
 // Define a constant to represent a null offset for node & relationship records
 pub const NULL_OFFSET: u64 = u64::MAX;
 // pub const NULL_OFFSET: u64 = 100;
 
-// Trait for a record that can be written to a memory-mapped file
+/// Version of the layout of the record files, bumped whenever it changes
+///
+/// 0 is the layout before the file headers, where records were bincode-encoded at
+/// `id * size_of::<Record>()`. `Reachdb::migrate` rewrites such directories.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Bytes reserved at the start of every record file for its `FileHeader`
+pub const HEADER_SIZE: usize = 64;
+
+/// First bytes of a record file: what it holds and how its records are laid out
+///
+/// Layout: magic `[u8; 8]`, format version `u32`, record size `u32`, zeros up to `HEADER_SIZE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub record_size: u32,
+}
+
+impl FileHeader {
+    pub fn new(magic: [u8; 8], record_size: usize) -> Self {
+        Self { magic, version: FORMAT_VERSION, record_size: record_size as u32 }
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Self, ReachdbError> {
+        let bytes = bytes.get(..HEADER_SIZE)
+            .ok_or_else(|| ReachdbError::OtherError("File too short for a header".to_string()))?;
+        Ok(Self {
+            magic: bytes[0..8].try_into().unwrap(),
+            version: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            record_size: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+        })
+    }
+
+    pub fn write(&self, bytes: &mut [u8]) -> Result<(), ReachdbError> {
+        let header = bytes.get_mut(..HEADER_SIZE)
+            .ok_or_else(|| ReachdbError::OtherError("File too short for a header".to_string()))?;
+        header.fill(0);
+        header[0..8].copy_from_slice(&self.magic);
+        header[8..12].copy_from_slice(&self.version.to_le_bytes());
+        header[12..16].copy_from_slice(&self.record_size.to_le_bytes());
+        Ok(())
+    }
+
+    /// A file that was just created, without header yet
+    pub fn is_blank(bytes: &[u8]) -> bool {
+        bytes.iter().take(HEADER_SIZE).all(|b| *b == 0)
+    }
+
+    /// Checks that the file `name` starts with the header `expected`
+    pub(crate) fn validate(bytes: &[u8], expected: &FileHeader, name: &str) -> Result<(), ReachdbError> {
+        let found = Self::read(bytes)?;
+        if found.magic != expected.magic {
+            return Err(ReachdbError::OtherError(format!("{} is not a reachdb record file of this kind", name)));
+        }
+        if found.version != expected.version {
+            return Err(ReachdbError::OtherError(format!(
+                "{} has record format {}, this build reads format {}", name, found.version, expected.version
+            )));
+        }
+        if found.record_size != expected.record_size {
+            return Err(ReachdbError::OtherError(format!(
+                "{} holds records of {} bytes, expected {}", name, found.record_size, expected.record_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A record stored with a fixed little-endian layout in a memory-mapped file, after its header
 pub trait Record {
-    fn write(&self, mmap: &mut MmapMut, id: u64) -> Result<(), ReachdbError>;
-    fn read(mmap: &MmapMut, id: u64) -> Result<Self, ReachdbError>
+    /// Identifies the file of the records in its header
+    const MAGIC: [u8; 8];
+
+    /// Bytes of an encoded record
+    fn record_size() -> usize
+    where
+        Self: Sized;
+
+    /// Encodes the record into `record_size` bytes
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Decodes `record_size` bytes written by `to_bytes`
+    fn from_bytes(bytes: &[u8]) -> Self
     where
         Self: Sized;
 
-    fn record_size() -> usize 
+    fn read(mmap: &[u8], id: u64) -> Result<Self, ReachdbError>
     where
         Self: Sized,
     {
-        std::mem::size_of::<Self>()
+        let offset = Self::id2offset(id);
+        let data = mmap.get(offset..offset + Self::record_size()).ok_or_else(|| out_of_bounds(id, mmap.len()))?;
+        Ok(Self::from_bytes(data))
     }
-    fn id2offset(id: u64) -> usize;
+
+    fn write(&self, mmap: &mut [u8], id: u64) -> Result<(), ReachdbError>
+    where
+        Self: Sized,
+    {
+        let offset = Self::id2offset(id);
+        let mmap_len = mmap.len();
+        mmap.get_mut(offset..offset + Self::record_size())
+            .ok_or_else(|| out_of_bounds(id, mmap_len))?
+            .copy_from_slice(&self.to_bytes());
+        Ok(())
+    }
+
+    fn id2offset(id: u64) -> usize
+    where
+        Self: Sized,
+    {
+        match id {
+            NULL_OFFSET => id as usize,
+            _ => HEADER_SIZE + id as usize * Self::record_size(),
+        }
+    }
+
+    /// Header of the files of these records
+    fn header() -> FileHeader
+    where
+        Self: Sized,
+    {
+        FileHeader::new(Self::MAGIC, Self::record_size())
+    }
+}
+
+/// Little-endian `u64` at `offset`
+pub(crate) fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Error for a record id that lies outside of the mapped file
+pub(crate) fn out_of_bounds(id: u64, mmap_len: usize) -> ReachdbError {
+    ReachdbError::OtherError(format!("Record id {} is out of bounds for a mmap of {} bytes", id, mmap_len))
 }
 
 // #[cfg(test)]
@@ -1,11 +1,6 @@
-use bincode;
-use memmap2::MmapMut;
-use serde::{Deserialize, Serialize};
+use super::{read_u64, Record, NULL_OFFSET};
 
-use super::{ReachdbError, Record, NULL_OFFSET};
-
-
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct NodeRecord {
     pub id: u64,
     pub first_relationship_id: u64,
@@ -20,6 +15,17 @@ impl NodeRecord {
             first_property_id: property_id,
         }
     }
+    /// A deleted record whose slot is kept in the free list until it gets reused
+    pub fn tombstone() -> Self {
+        Self {
+            id: NULL_OFFSET,
+            first_relationship_id: NULL_OFFSET,
+            first_property_id: NULL_OFFSET,
+        }
+    }
+    pub fn is_deleted(&self) -> bool {
+        self.id == NULL_OFFSET
+    }
     pub fn update(
         &mut self,
         first_relationship_id: Option<u64>,
@@ -32,33 +38,26 @@ impl NodeRecord {
 
 
 impl Record for NodeRecord {
-    fn read(mmap: &MmapMut, id: u64) -> Result<Self, ReachdbError>
-    where
-        Self: Sized,
-    {   
-        let offset = Self::id2offset(id);
-        let end = offset + Self::record_size();
-        let data = &mmap[offset..end];
-        Ok(bincode::deserialize(data)?)
-    }
-    fn write(&self, mmap: &mut MmapMut, id: u64) -> Result<(), ReachdbError> {
-
-        let offset = Self::id2offset(id);
-        let encoded = bincode::serialize(self)?;
-        let end = offset + encoded.len();
+    const MAGIC: [u8; 8] = *b"REACHNOD";
 
-        mmap[offset..end].copy_from_slice(&encoded);
-        mmap.flush()?;
-
-        Ok(())
+    /// `id`, `first_relationship_id`, `first_property_id`
+    fn record_size() -> usize {
+        24
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::record_size());
+        bytes.extend_from_slice(&self.id.to_le_bytes());
+        bytes.extend_from_slice(&self.first_relationship_id.to_le_bytes());
+        bytes.extend_from_slice(&self.first_property_id.to_le_bytes());
+        bytes
     }
-    fn id2offset(id: u64) -> usize {
-        match id {
-            NULL_OFFSET => id as usize,
-            _ => id as usize * Self::record_size()
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            id: read_u64(bytes, 0),
+            first_relationship_id: read_u64(bytes, 8),
+            first_property_id: read_u64(bytes, 16),
         }
     }
-
 }
 
 #[cfg(test)]
@@ -72,7 +71,9 @@ mod tests {
         use memmap2::MmapOptions;
         use std::fs::OpenOptions;
 
-        let file_path = "reachdb.node.db";
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("reachdb.node.db");
+        let file_path = file_path.to_str().unwrap();
 
         // Create file and write node
         {
@@ -121,8 +122,5 @@ mod tests {
                 assert_eq!(read_node.first_property_id, i*i);
             }
         }
-
-        // Clean up
-        // std::fs::remove_file(file_path).unwrap();
     }
 }
\ No newline at end of file
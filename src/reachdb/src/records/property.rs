@@ -0,0 +1,85 @@
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use super::ReachdbError;
+use crate::utils::unix_time;
+
+/// Typed value of a node or edge property
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    String(String),
+    Int(i64),
+    Float(f64),
+    /// Seconds since the UNIX epoch
+    Timestamp(u64),
+}
+
+impl PropertyValue {
+    /// Timestamp of the current system time
+    pub fn now() -> Self {
+        Self::Timestamp(unix_time())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Numeric value of the property, `None` for strings
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(i) => Some(*i as f64),
+            Self::Float(f) => Some(*f),
+            Self::Timestamp(t) => Some(*t as f64),
+            Self::String(_) => None,
+        }
+    }
+
+    /// Key under which the property `key` of the owner `owner_property_id` is stored
+    ///
+    /// The owner id comes first in big-endian, so all the properties of one owner share a prefix.
+    pub(crate) fn storage_key(owner_property_id: u64, key: &str) -> Vec<u8> {
+        let mut bytes = owner_property_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(key.as_bytes());
+        bytes
+    }
+
+    /// Splits a key produced by `storage_key` back into the property name
+    pub(crate) fn key_from_storage(bytes: &[u8]) -> Result<String, ReachdbError> {
+        Ok(String::from_utf8(bytes[8..].to_vec())?)
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", s),
+            Self::Int(i) => write!(f, "{}", i),
+            Self::Float(x) => write!(f, "{}", x),
+            Self::Timestamp(t) => write!(f, "{}", t),
+        }
+    }
+}
+
+impl From<&str> for PropertyValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+impl From<String> for PropertyValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+impl From<i64> for PropertyValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+impl From<f64> for PropertyValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
@@ -1,10 +1,6 @@
-use bincode;
-use memmap2::MmapMut;
-use serde::{Deserialize, Serialize};
+use super::{read_u64, ReachdbError, Record, NULL_OFFSET};
 
-use super::{ReachdbError, Record, NULL_OFFSET};
-
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct RelationshipRecord {
     pub source_id: u64,
     pub target_id: u64,   
@@ -17,29 +13,40 @@ pub struct RelationshipRecord {
 }
 
 impl Record for RelationshipRecord {
-    fn read(mmap: &MmapMut, id: u64) -> Result<Self, ReachdbError>
-    where
-        Self: Sized,
-    {   
-        let offset = Self::id2offset(id);
-        let end = offset + Self::record_size();
-        let data = &mmap[offset..end];
-        Ok(bincode::deserialize(data)?)
-    }
-    fn write(&self, mmap: &mut MmapMut, id: u64) -> Result<(), ReachdbError> {
+    const MAGIC: [u8; 8] = *b"REACHREL";
 
-        let offset = Self::id2offset(id);
-        let encoded = bincode::serialize(self)?;
-        let end = offset + encoded.len();
-        mmap[offset..end].copy_from_slice(&encoded);
-        mmap.flush()?;
-
-        Ok(())
+    /// `source_id`, `target_id`, `type_id` and 7 reserved bytes, `first_property_id`, then the
+    /// next/prev links of the source chain and of the target chain
+    fn record_size() -> usize {
+        64
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::record_size());
+        bytes.extend_from_slice(&self.source_id.to_le_bytes());
+        bytes.extend_from_slice(&self.target_id.to_le_bytes());
+        bytes.push(self.type_id);
+        bytes.extend_from_slice(&[0; 7]);
+        for id in [
+            self.first_property_id,
+            self.next_src_relationship_id,
+            self.prev_src_relationship_id,
+            self.next_tgt_relationship_id,
+            self.prev_tgt_relationship_id,
+        ] {
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        bytes
     }
-    fn id2offset(id: u64) -> usize {
-        match id {
-            NULL_OFFSET => id as usize,
-            _ => id as usize * Self::record_size()
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            source_id: read_u64(bytes, 0),
+            target_id: read_u64(bytes, 8),
+            type_id: bytes[16],
+            first_property_id: read_u64(bytes, 24),
+            next_src_relationship_id: read_u64(bytes, 32),
+            prev_src_relationship_id: read_u64(bytes, 40),
+            next_tgt_relationship_id: read_u64(bytes, 48),
+            prev_tgt_relationship_id: read_u64(bytes, 56),
         }
     }
 }
@@ -87,8 +94,55 @@ impl RelationshipRecord {
         self.prev_tgt_relationship_id = prev_tgt_relationship_id.unwrap_or(self.prev_tgt_relationship_id);
     }
 
-    /// Initializes an iterator externally by providing `current_offset` and `mmap`
-    pub fn into_iter<'a>(mmap: &'a MmapMut, node_id: &u64, current_id: u64) -> RelationshipIterator<'a> {
+    /// A deleted record whose slot is kept in the free list until it gets reused
+    pub fn tombstone() -> Self {
+        Self::new(NULL_OFFSET, NULL_OFFSET, 0, None, None, None, None, None)
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.source_id == NULL_OFFSET && self.target_id == NULL_OFFSET
+    }
+
+    // Every node keeps a single chain through all of its relationships. A relationship stores the
+    // links of its source's chain in the `src` fields and the links of its target's chain in the
+    // `tgt` fields, self-loops only take part in the `src` chain.
+
+    /// The next (newer) relationship in the chain of `node_id`
+    pub fn next_id_for(&self, node_id: u64) -> u64 {
+        if self.source_id == node_id {
+            self.next_src_relationship_id
+        } else {
+            self.next_tgt_relationship_id
+        }
+    }
+
+    /// The previous (older) relationship in the chain of `node_id`
+    pub fn prev_id_for(&self, node_id: u64) -> u64 {
+        if self.source_id == node_id {
+            self.prev_src_relationship_id
+        } else {
+            self.prev_tgt_relationship_id
+        }
+    }
+
+    pub fn set_next_id_for(&mut self, node_id: u64, relationship_id: u64) {
+        if self.source_id == node_id {
+            self.next_src_relationship_id = relationship_id;
+        } else {
+            self.next_tgt_relationship_id = relationship_id;
+        }
+    }
+
+    pub fn set_prev_id_for(&mut self, node_id: u64, relationship_id: u64) {
+        if self.source_id == node_id {
+            self.prev_src_relationship_id = relationship_id;
+        } else {
+            self.prev_tgt_relationship_id = relationship_id;
+        }
+    }
+
+    /// Initializes an iterator externally by providing `current_id` and a reader of the records
+    pub fn into_iter<'a>(read: &'a RelationshipReader<'a>, node_id: &u64, current_id: u64) -> RelationshipIterator<'a> {
 
         if current_id == NULL_OFFSET {
             // Create an empty iterator when current_id is NULL_OFFSET
@@ -97,7 +151,7 @@ impl RelationshipRecord {
             initial_id: NULL_OFFSET,
             current_id: NULL_OFFSET,
             visited_prev: true, // Already visited to ensure next() returns None
-            mmap,
+            read,
             }
         } else {
             RelationshipIterator {
@@ -105,19 +159,22 @@ impl RelationshipRecord {
             initial_id: current_id,
             current_id,
             visited_prev: false,
-            mmap,
+            read,
             }
         }
     }
 
 }
 
+/// Reads the relationship record of an id
+pub type RelationshipReader<'a> = dyn Fn(u64) -> Result<RelationshipRecord, ReachdbError> + 'a;
+
 pub struct RelationshipIterator<'a> {
     node_id: u64,
     initial_id: u64,
     current_id: u64,
     visited_prev: bool, // Tracks if we finished iterating in the prev direction
-    mmap: &'a MmapMut,
+    read: &'a RelationshipReader<'a>,
 }
 
 impl<'a> Iterator for RelationshipIterator<'a> {
@@ -127,50 +184,37 @@ impl<'a> Iterator for RelationshipIterator<'a> {
         
         // If current_id is NULL_OFFSET, check if we should switch to next direction
         if self.current_id == NULL_OFFSET {
-            if !self.visited_prev {
-                // trace!("SWITCHING TO NEXT RELATIONSHIPS FROM INITIAL ID");
-                self.visited_prev = true;
-                self.current_id = self.initial_id; // Restart from initial_id
-            } else {
+            if self.visited_prev {
                 return None; // Fully exhausted both directions
             }
+            // trace!("SWITCHING TO NEXT RELATIONSHIPS FROM INITIAL ID");
+            // The initial record was already yielded, so continue right after it
+            self.visited_prev = true;
+            match (self.read)(self.initial_id) {
+                Ok(record) => self.current_id = record.next_id_for(self.node_id),
+                Err(e) => return Some(Err(e)),
+            }
+            if self.current_id == NULL_OFFSET {
+                return None;
+            }
         }
         let this_id = self.current_id;
         // Read the current record
-        match RelationshipRecord::read(self.mmap, self.current_id) {
+        match (self.read)(self.current_id) {
             Ok(record) => {
-                let is_source = record.source_id == self.node_id;
-                let is_target = record.target_id == self.node_id;
-                
-                if !self.visited_prev {
+                self.current_id = if !self.visited_prev {
                     // Iterate using prev relationships first
-                    if is_source && record.prev_src_relationship_id != NULL_OFFSET {
-                        // trace!("INSIDE PREV SRC");
-                        self.current_id = record.prev_src_relationship_id;
-                    } else if is_target && record.prev_tgt_relationship_id != NULL_OFFSET {
-                        // trace!("INSIDE PREV TGT");
-                        self.current_id = record.prev_tgt_relationship_id;
-                    } else {
-                        // If no more prev links, signal a switch
-                        self.current_id = NULL_OFFSET;
-                    }
+                    record.prev_id_for(self.node_id)
                 } else {
                     // Now iterate using next relationships
-                    if is_source && record.next_src_relationship_id != NULL_OFFSET {
-                        // trace!("INSIDE NEXT SRC");
-                        self.current_id = record.next_src_relationship_id;
-                    } else if is_target && record.next_tgt_relationship_id != NULL_OFFSET {
-                        // trace!("INSIDE NEXT TGT");
-                        self.current_id = record.next_tgt_relationship_id;
-                    } else {
-                        self.current_id = NULL_OFFSET;
-                    }
-                }
+                    record.next_id_for(self.node_id)
+                };
                 Some(Ok((this_id, record)))
             }
             Err(e) => {
                 // Return the error and stop iteration
                 self.current_id = NULL_OFFSET;
+                self.visited_prev = true;
                 Some(Err(e))
             }
         }
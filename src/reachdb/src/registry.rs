@@ -0,0 +1,258 @@
+//! Relation types named at runtime, next to the closed vocabulary of `UserDefinedRelationType`
+//!
+//! The registry lives in the metadata, so it is saved and rolled back with the transactions.
+//! Names of `E` always win, registered names take the ids `E` leaves free.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelationInfo {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelationRegistry {
+    /// Unknown relation names get an id on first use instead of being skipped
+    pub auto_register: bool,
+    /// Registered types by id
+    pub types: BTreeMap<u8, RelationInfo>,
+    /// Other names of registered or built-in types
+    pub aliases: BTreeMap<String, u8>,
+}
+
+/// `IS-A`, `RELATES-TO` and `INFLUENCES` with ids 0, 1 and 2, the vocabulary of the rsearch graphs
+///
+/// Used by the command line tool, so it reads those databases with their relation names.
+#[derive(Debug)]
+pub enum BuiltinRelationType {
+    IsA(u8),
+    RelatesTo(u8),
+    Influences(u8)
+}
+
+impl UserDefinedRelationType for BuiltinRelationType {
+    fn get_type_id(relation: &str) -> Option<Self> {
+        match relation {
+            "IS-A" => Some(Self::IsA(0)),
+            "RELATES-TO" => Some(Self::RelatesTo(1)),
+            "INFLUENCES" => Some(Self::Influences(2)),
+            _ => None
+        }
+    }
+    fn type_id(&self) -> u8 {
+        match self {
+            Self::IsA(id) => *id,
+            Self::RelatesTo(id) => *id,
+            Self::Influences(id) => *id,
+        }
+    }
+    fn get_type_str(id: u8) -> Option<String> {
+        match id {
+            0 => Some("IS-A".to_string()),
+            1 => Some("RELATES-TO".to_string()),
+            2 => Some("INFLUENCES".to_string()),
+            _ => None
+        }
+    }
+}
+
+/// Relation type without any built-in name, for databases that only use the registry
+///
+/// `Reachdb<DynamicRelationType>` with `auto_register` on accepts every relation name.
+#[derive(Debug)]
+pub enum DynamicRelationType {}
+
+impl UserDefinedRelationType for DynamicRelationType {
+    fn get_type_id(_relation: &str) -> Option<Self> {
+        None
+    }
+    fn type_id(&self) -> u8 {
+        match *self {}
+    }
+    fn get_type_str(_id: u8) -> Option<String> {
+        None
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Id of a relation name, looked up in `E`, then in the registered names and aliases
+    pub fn relation_type_id(&self, relation: &str) -> Option<u8> {
+        if let Some(rel_type) = E::get_type_id(relation) {
+            return Some(rel_type.type_id());
+        }
+        self.relation_registry.types.iter()
+            .find(|(_, info)| info.name == relation)
+            .map(|(id, _)| *id)
+            .or_else(|| self.relation_registry.aliases.get(relation).copied())
+    }
+
+    /// Like `relation_type_id`, registering the name when `auto_register` is on
+    pub(crate) fn resolve_relation_type(&mut self, relation: &str) -> Result<Option<u8>, ReachdbError> {
+        match self.relation_type_id(relation) {
+            Some(id) => Ok(Some(id)),
+            None if self.relation_registry.auto_register => Ok(Some(self.register_relation(relation, None)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Registers a relation type and returns its id
+    ///
+    /// A known name keeps its id, and a registered one gets the new description if there is one.
+    /// Ids are `u8` like the `type_id` of the relation records, so at most 256 types exist.
+    pub fn register_relation(&mut self, name: &str, description: Option<&str>) -> Result<u8, ReachdbError> {
+        if let Some(id) = self.relation_type_id(name) {
+            let recorded = self.relation_registry.types.get(&id).and_then(|info| info.description.as_deref());
+            if let Some(description) = description
+                && recorded != Some(description)
+            {
+                self.transaction(|db| {
+                    // A built-in name has no entry until the first registration
+                    db.record_builtin_relations();
+                    if let Some(info) = db.relation_registry.types.get_mut(&id) {
+                        info.description = Some(description.to_string());
+                    }
+                    Ok(())
+                })?;
+            }
+            return Ok(id);
+        }
+        if name.trim().is_empty() {
+            return Err(ReachdbError::OtherError("A relation type needs a name".to_string()));
+        }
+
+        let id = (0..=u8::MAX)
+            .find(|id| E::get_type_str(*id).is_none() && !self.relation_registry.types.contains_key(id))
+            .ok_or_else(|| ReachdbError::OtherError(format!("No relation type id left for {}", name)))?;
+        self.transaction(|db| {
+            db.record_builtin_relations();
+            db.relation_registry.types.insert(id, RelationInfo {
+                name: name.to_string(),
+                description: description.map(str::to_string),
+            });
+            Ok(())
+        })?;
+        Ok(id)
+    }
+
+    /// Records the built-in names in the registry too, so the database still reads the same without `E`
+    fn record_builtin_relations(&mut self) {
+        for builtin in 0..=u8::MAX {
+            if let Some(builtin_name) = E::get_type_str(builtin) {
+                self.relation_registry.types.entry(builtin)
+                    .or_insert(RelationInfo { name: builtin_name, description: None });
+            }
+        }
+    }
+
+    /// Makes `alias` another name of the known relation type `relation`
+    pub fn add_relation_alias(&mut self, alias: &str, relation: &str) -> Result<(), ReachdbError> {
+        let id = self.relation_type_id(relation)
+            .ok_or_else(|| ReachdbError::OtherError(format!("Unknown relation type {}", relation)))?;
+        match self.relation_type_id(alias) {
+            Some(existing) if existing == id => Ok(()),
+            Some(_) => Err(ReachdbError::OtherError(format!("{} already names another relation type", alias))),
+            None => self.transaction(|db| {
+                db.relation_registry.aliases.insert(alias.to_string(), id);
+                Ok(())
+            }),
+        }
+    }
+
+    /// Turns the registration of unknown relation names on first use on or off
+    pub fn set_relation_auto_register(&mut self, auto_register: bool) -> Result<(), ReachdbError> {
+        self.transaction(|db| {
+            db.relation_registry.auto_register = auto_register;
+            Ok(())
+        })
+    }
+
+    pub fn relation_registry(&self) -> &RelationRegistry {
+        &self.relation_registry
+    }
+
+    /// Every relation type with a name, built-in and registered, by id
+    pub fn relation_types(&self) -> BTreeMap<u8, String> {
+        let mut types: BTreeMap<u8, String> = (0..=u8::MAX)
+            .filter_map(|id| E::get_type_str(id).map(|name| (id, name)))
+            .collect();
+        for (id, info) in &self.relation_registry.types {
+            types.entry(*id).or_insert_with(|| info.name.clone());
+        }
+        types
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::temp_db;
+    use crate::Reachdb;
+    use super::DynamicRelationType;
+
+    #[test]
+    fn registered_relations_and_aliases_resolve_to_one_id() {
+        let (_dir, mut db) = temp_db(None, None);
+        assert_eq!(db.add_edge("GAN", "Diffusion", "COMPETES-WITH").unwrap(), None);
+
+        let id = db.register_relation("COMPETES-WITH", Some("Alternative approaches")).unwrap();
+        assert_eq!(id, 3);
+        db.add_relation_alias("RIVALS", "COMPETES-WITH").unwrap();
+        db.add_relation_alias("is a", "IS-A").unwrap();
+        assert!(db.add_relation_alias("IS-A", "COMPETES-WITH").is_err());
+
+        let rel_id = db.add_edge("GAN", "Diffusion", "RIVALS").unwrap().unwrap();
+        assert_eq!(db.find_edge("GAN", "Diffusion", "COMPETES-WITH").unwrap(), Some(rel_id));
+        assert_eq!(db.get_edge_from_rel_id(rel_id).unwrap().2, id);
+        assert_eq!(db.get_relation_type_str(id), "COMPETES-WITH");
+        assert!(db.add_edge("BERT", "Transformer", "is a").unwrap().is_some());
+    }
+
+    #[test]
+    fn builtin_relations_take_a_description() {
+        let (dir, mut db) = temp_db(None, None);
+        assert_eq!(db.register_relation("IS-A", Some("Subtype of")).unwrap(), 0);
+        assert_eq!(db.relation_registry().types[&0].description.as_deref(), Some("Subtype of"));
+        db.close().unwrap();
+
+        let mut db = Reachdb::<DynamicRelationType>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        assert_eq!(db.relation_registry().types[&0].description.as_deref(), Some("Subtype of"));
+        assert_eq!(db.relation_type_id("INFLUENCES"), Some(2));
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn failed_transactions_forget_the_names_they_registered() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.set_relation_auto_register(true).unwrap();
+        let failed: Result<(), _> = db.transaction(|db| {
+            db.add_edge("A", "B", "CITES")?;
+            Err(crate::ReachdbError::OtherError("abort".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(db.relation_type_id("CITES"), None);
+    }
+
+    #[test]
+    fn registered_relations_persist_next_to_the_builtin_ones() {
+        let (dir, mut db) = temp_db(None, None);
+        let id = db.register_relation("COMPETES-WITH", None).unwrap();
+        db.add_relation_alias("RIVALS", "COMPETES-WITH").unwrap();
+        db.set_relation_auto_register(true).unwrap();
+        db.close().unwrap();
+
+        // The registry is stored with the metadata, a database without built-in names relies on it
+        let mut db = Reachdb::<DynamicRelationType>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        assert_eq!(db.relation_type_id("RIVALS"), Some(id));
+        assert_eq!(db.relation_type_id("IS-A"), Some(0));
+        assert_eq!(db.get_relation_type_str(0), "IS-A");
+        let cites = db.add_edge("A", "B", "CITES").unwrap().unwrap();
+        assert_eq!(db.get_relation(cites).unwrap().type_id, 4);
+        assert_eq!(db.get_relation_type_str(4), "CITES");
+        assert_eq!(db.relation_types().get(&id).map(String::as_str), Some("COMPETES-WITH"));
+        db.close().unwrap();
+    }
+}
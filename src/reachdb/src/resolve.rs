@@ -0,0 +1,334 @@
+//! Entity resolution: node aliases, name normalization and merging duplicate nodes
+//!
+//! Aliases live in the `reachdb.nodeid.alias` tree next to the exact names. With a name
+//! normalizer every new node also gets its normalized name there, so later spellings that
+//! normalize the same way find the node instead of creating another one.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, wal::SledTree};
+
+/// Cleanup steps applied to node names before they are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameNormalizer {
+    /// Lowercase the name
+    pub case_fold: bool,
+    /// Turn the plural of the last word into its singular, `Transformers` -> `Transformer`
+    pub strip_plurals: bool,
+    /// Replace punctuation by spaces and collapse the whitespace
+    pub clean_punctuation: bool,
+}
+
+impl Default for NameNormalizer {
+    fn default() -> Self {
+        Self { case_fold: true, strip_plurals: true, clean_punctuation: true }
+    }
+}
+
+impl NameNormalizer {
+    pub fn normalize(&self, name: &str) -> String {
+        let mut name = if self.clean_punctuation {
+            name.chars()
+                .map(|c| if c.is_alphanumeric() || c.is_whitespace() { c } else { ' ' })
+                .collect::<String>()
+        } else {
+            name.to_string()
+        };
+        // Whitespace is always collapsed, a name differing only in spacing is the same name
+        name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if self.case_fold {
+            name = name.to_lowercase();
+        }
+        if self.strip_plurals {
+            let cut = name.rfind(' ').map_or(0, |i| i + 1);
+            let singular = singular(&name[cut..]);
+            name.truncate(cut);
+            name.push_str(&singular);
+        }
+        name
+    }
+}
+
+/// Singular words that end like a plural, words ending with one of them ("Astrophysics") are kept too
+const SINGULAR_WORDS: &[&str] = &[
+    "series", "species", "news", "means", "lens", "bias", "alias", "atlas", "canvas", "chaos", "cosmos",
+    "ethos", "physics", "statistics", "mathematics", "economics", "econometrics", "linguistics",
+    "robotics", "genetics", "genomics", "proteomics", "dynamics", "mechanics", "kinematics",
+    "graphics", "analytics", "semantics", "pragmatics", "phonetics", "ethics", "optics",
+    "photonics", "electronics", "informatics", "cybernetics", "logistics", "acoustics", "ergonomics",
+];
+
+/// English singular of a word, for the regular plurals
+pub(crate) fn singular(word: &str) -> String {
+    let lower = word.to_lowercase();
+    let len = word.chars().count();
+    if len <= 3 || !lower.ends_with('s') || lower.ends_with("ss") || lower.ends_with("us") || lower.ends_with("is")
+        || SINGULAR_WORDS.iter().any(|singular| lower.ends_with(singular))
+    {
+        return word.to_string();
+    }
+    if len > 4 && lower.ends_with("ies") {
+        let stem = &word[..word.len() - 3];
+        let y = if word.ends_with("IES") { "Y" } else { "y" };
+        return format!("{}{}", stem, y);
+    }
+    if ["sses", "xes", "ches", "shes", "zes"].iter().any(|suffix| lower.ends_with(suffix)) {
+        return word[..word.len() - 2].to_string();
+    }
+    word[..word.len() - 1].to_string()
+}
+
+/// What `merge_nodes` did with the relations of the absorbed node
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// Relations moved over to the kept node
+    pub moved: u64,
+    /// Relations the kept node already had, their properties were added to the existing relation
+    pub duplicates: u64,
+    /// Relations between the two nodes, which would have become self-loops
+    pub self_loops: u64,
+}
+
+impl fmt::Display for MergeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Moved: {}, duplicates dropped: {}, self-loops dropped: {}", self.moved, self.duplicates, self.self_loops)
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Normalized form of `name` when a normalizer is set
+    pub(crate) fn normalized_name(&self, name: &str) -> Option<String> {
+        self.name_normalizer.map(|normalizer| normalizer.normalize(name))
+    }
+
+    /// Node an alias, or the normalized form of the name, points to
+    pub(crate) fn get_aliased_node_id(&self, name: &str) -> Result<Option<u64>, ReachdbError> {
+        if let Some(id_bytes) = self.sled_get(SledTree::NodeAlias, name.as_bytes())? {
+            return Ok(Some(bincode::deserialize::<u64>(&id_bytes)?));
+        }
+        let Some(normalized) = self.normalized_name(name) else {
+            return Ok(None);
+        };
+        // A node whose exact name is already normalized has no alias for it
+        for tree in [SledTree::NodeId, SledTree::NodeAlias] {
+            if let Some(id_bytes) = self.sled_get(tree, normalized.as_bytes())? {
+                return Ok(Some(bincode::deserialize::<u64>(&id_bytes)?));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Aliases pointing to the node, its normalized name included
+    pub fn aliases_of(&self, node_id: u64) -> Result<Vec<String>, ReachdbError> {
+        let mut aliases = Vec::new();
+        for (alias, id_bytes) in self.sled_scan_prefix(SledTree::NodeAlias, &[])? {
+            if bincode::deserialize::<u64>(&id_bytes)? == node_id {
+                aliases.push(String::from_utf8(alias)?);
+            }
+        }
+        Ok(aliases)
+    }
+
+    /// Makes `alias` another name of the node `node`
+    ///
+    /// Fails when `alias` is the exact name of a node or already points to another node.
+    pub fn add_node_alias(&mut self, alias: &str, node: &str) -> Result<(), ReachdbError> {
+        let node_id = self.get_node_id(node)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node \"{}\" not found", node)))?;
+        if self.sled_contains_key(SledTree::NodeId, alias.as_bytes())? {
+            return Err(ReachdbError::OtherError(format!("\"{}\" is the name of a node", alias)));
+        }
+        match self.get_aliased_node_id(alias)? {
+            Some(id) if id != node_id => Err(ReachdbError::OtherError(format!("\"{}\" already names node {}", alias, id))),
+            _ => self.transaction(|db| db.sled_insert(SledTree::NodeAlias, alias.as_bytes(), bincode::serialize(&node_id)?)),
+        }
+    }
+
+    /// Removes an alias, returns `false` if there was none
+    pub fn remove_node_alias(&mut self, alias: &str) -> Result<bool, ReachdbError> {
+        if !self.sled_contains_key(SledTree::NodeAlias, alias.as_bytes())? {
+            return Ok(false);
+        }
+        self.transaction(|db| db.sled_remove(SledTree::NodeAlias, alias.as_bytes()))?;
+        Ok(true)
+    }
+
+    pub fn name_normalizer(&self) -> Option<NameNormalizer> {
+        self.name_normalizer
+    }
+
+    /// Sets the normalization applied to node names on insert, `None` turns it off
+    ///
+    /// The existing nodes get the normalized aliases of the new normalizer. When two nodes
+    /// normalize the same way, the one with the smaller id keeps the alias: merge them to join them.
+    pub fn set_name_normalizer(&mut self, normalizer: Option<NameNormalizer>) -> Result<(), ReachdbError> {
+        self.transaction(|db| {
+            db.name_normalizer = normalizer;
+            if normalizer.is_none() {
+                return Ok(());
+            }
+            for node_id in db.get_all_node_ids()? {
+                let name = db.get_node_name(node_id)?;
+                let normalized = db.normalized_name(&name).expect("a normalizer is set");
+                if normalized == name
+                    || db.sled_contains_key(SledTree::NodeId, normalized.as_bytes())?
+                    || db.sled_contains_key(SledTree::NodeAlias, normalized.as_bytes())?
+                {
+                    continue;
+                }
+                db.sled_insert(SledTree::NodeAlias, normalized.as_bytes(), bincode::serialize(&node_id)?)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Merges the node `absorb` into the node `keep`
+    ///
+    /// Every relation of `absorb` is moved to `keep`, except those `keep` already has and those
+    /// between the two nodes. Properties `keep` lacks are copied over, and the name and aliases
+    /// of `absorb` become aliases of `keep`, so adding an edge with the old name finds `keep`.
+    pub fn merge_nodes(&mut self, keep: &str, absorb: &str) -> Result<MergeSummary, ReachdbError> {
+        let keep_id = self.get_node_id(keep)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node \"{}\" not found", keep)))?;
+        let absorb_id = self.get_node_id(absorb)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node \"{}\" not found", absorb)))?;
+        if keep_id == absorb_id {
+            return Err(ReachdbError::OtherError(format!("\"{}\" and \"{}\" are the same node", keep, absorb)));
+        }
+
+        self.transaction(|db| {
+            let mut summary = MergeSummary::default();
+            for rel_id in db.get_all_node_relations(absorb_id)? {
+                let rel = db.get_relation(rel_id)?;
+                let moved = |id: u64| if id == absorb_id { keep_id } else { id };
+                let (source, target) = (moved(rel.source_id), moved(rel.target_id));
+                let properties = db.get_edge_properties(rel_id)?;
+                let provenance = db.get_edge_provenance(rel_id)?;
+                db.remove_relation(rel_id)?;
+
+                if source == target && rel.source_id != rel.target_id {
+                    summary.self_loops += 1;
+                    continue;
+                }
+                let (target_rel, existed) = match db.if_edge_exists(&source, &target, &rel.type_id)? {
+                    Some(existing) => (existing, true),
+                    None => (db.add_relation(&source, &target, &rel.type_id)?, false),
+                };
+                let current = db.get_edge_properties(target_rel)?;
+                for (key, value) in properties {
+                    if !current.contains_key(&key) {
+                        db.set_edge_property(target_rel, &key, value)?;
+                    }
+                }
+                if let Some(provenance) = provenance {
+                    let mut merged = db.get_edge_provenance(target_rel)?.unwrap_or_default();
+                    merged.absorb(provenance);
+                    db.set_edge_provenance(target_rel, &merged)?;
+                }
+                if existed {
+                    summary.duplicates += 1;
+                } else {
+                    summary.moved += 1;
+                }
+            }
+
+            let kept_properties = db.get_node_properties(keep_id)?;
+            for (key, value) in db.get_node_properties(absorb_id)? {
+                if !kept_properties.contains_key(&key) {
+                    db.set_node_property(keep_id, &key, value)?;
+                }
+            }
+
+            // The old names keep pointing to the merged node
+            let mut names = db.aliases_of(absorb_id)?;
+            names.push(db.get_node_name(absorb_id)?);
+            db.drop_node(absorb_id)?;
+            for name in names {
+                if !db.sled_contains_key(SledTree::NodeId, name.as_bytes())? {
+                    db.sled_insert(SledTree::NodeAlias, name.as_bytes(), bincode::serialize(&keep_id)?)?;
+                }
+            }
+            Ok(summary)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db};
+    use crate::PropertyValue;
+    use super::{MergeSummary, NameNormalizer};
+
+    #[test]
+    fn normalizes_names() {
+        let normalizer = NameNormalizer::default();
+        assert_eq!(normalizer.normalize("  Transformers "), "transformer");
+        assert_eq!(normalizer.normalize("Graph-Neural   Networks!"), "graph neural network");
+        assert_eq!(normalizer.normalize("Diffusion Processes"), "diffusion process");
+        assert_eq!(normalizer.normalize("Ontologies"), "ontology");
+        assert_eq!(normalizer.normalize("Analysis"), "analysis");
+        assert_eq!(normalizer.normalize("Gaussians"), "gaussian");
+
+        // Singular words that look plural
+        assert_eq!(normalizer.normalize("Time Series"), "time series");
+        assert_eq!(normalizer.normalize("Species"), "species");
+        assert_eq!(normalizer.normalize("Inductive Bias"), "inductive bias");
+        assert_eq!(normalizer.normalize("Physics"), "physics");
+        assert_eq!(normalizer.normalize("Astrophysics"), "astrophysics");
+        assert_eq!(normalizer.normalize("Statistics"), "statistics");
+        assert_eq!(normalizer.normalize("news"), "news");
+        assert_eq!(normalizer.normalize("Metrics"), "metric");
+    }
+
+    #[test]
+    fn merges_move_relations_onto_the_kept_node() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("BERT", "Transformer", "IS-A").unwrap();
+        db.add_edge("GPT", "transformer architecture", "IS-A").unwrap();
+        let duplicate = db.add_edge("BERT", "transformer architecture", "IS-A").unwrap().unwrap();
+        db.set_edge_property(duplicate, "source_url", PropertyValue::from("https://a.org")).unwrap();
+        db.add_edge("transformer architecture", "Transformer", "RELATES-TO").unwrap();
+        let keep = node_id(&db, "Transformer");
+
+        let summary = db.merge_nodes("Transformer", "transformer architecture").unwrap();
+        assert_eq!(summary, MergeSummary { moved: 1, duplicates: 1, self_loops: 1 });
+        assert_eq!(db.get_node_id("transformer architecture").unwrap(), Some(keep));
+        assert_eq!(db.get_all_node_ids().unwrap().len(), 3);
+        assert_eq!(db.get_incoming_node_relations(keep).unwrap().len(), 2);
+        let bert_edge = db.find_edge("BERT", "Transformer", "IS-A").unwrap().unwrap();
+        assert_eq!(db.get_edge_properties(bert_edge).unwrap()["source_url"], PropertyValue::from("https://a.org"));
+        assert!(db.verify().unwrap().is_ok());
+
+        // Edges added under the old name land on the kept node
+        db.add_edge("T5", "transformer architecture", "IS-A").unwrap();
+        assert_eq!(db.get_incoming_node_relations(keep).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn aliases_resolve_until_their_node_is_removed() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("BERT", "Transformer", "IS-A").unwrap();
+        let keep = node_id(&db, "Transformer");
+        db.add_node_alias("Vaswani Transformer", "Transformer").unwrap();
+        assert!(db.add_node_alias("BERT", "Transformer").is_err());
+        assert_eq!(db.get_node_id("Vaswani Transformer").unwrap(), Some(keep));
+
+        assert!(db.remove_node("Transformer").unwrap());
+        assert_eq!(db.get_node_id("Vaswani Transformer").unwrap(), None);
+        assert!(db.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn normalizer_resolves_variants_of_a_name() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("BERT", "Transformer", "IS-A").unwrap();
+        let keep = node_id(&db, "Transformer");
+        db.set_name_normalizer(Some(NameNormalizer::default())).unwrap();
+        assert_eq!(db.get_node_id("TRANSFORMERS").unwrap(), Some(keep));
+        db.add_edge("Vision  Transformers", "Transformers", "IS-A").unwrap();
+        assert_eq!(db.get_incoming_node_relations(keep).unwrap().len(), 2);
+        assert_eq!(db.get_node_id("vision transformer").unwrap(), db.get_node_id("Vision  Transformers").unwrap());
+    }
+}
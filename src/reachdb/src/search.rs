@@ -0,0 +1,287 @@
+//! Full-text and fuzzy search over node names
+//!
+//! Every node name is split into lowercase, singular tokens stored in the `reachdb.nodeid.token`
+//! tree as `token \0 node id`, so the nodes holding a token, or a token prefix, are one range scan.
+//! Typo-tolerant matches compare the query tokens against every distinct token of the index,
+//! seeking past the nodes of each token instead of reading them.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, resolve::singular, wal::SledTree};
+
+/// Query words that do not narrow a search down, dropped unless the query has nothing else
+const STOP_WORDS: &[&str] = &["a", "an", "and", "for", "in", "of", "on", "or", "the", "to", "with"];
+
+/// A node found by `search_nodes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub node_id: u64,
+    pub name: String,
+    /// Between 0 and 1, 1 when every query word is a word of the name and the name has no other
+    pub score: f64,
+}
+
+/// Lowercase singular words of a name
+pub(crate) fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| singular(&word.to_lowercase()))
+        .collect()
+}
+
+/// Edits allowed between a query word and a word of the index
+fn max_typos(token: &str) -> usize {
+    match token.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// Levenshtein distance counting the swap of two neighboring letters as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>(); a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// How well the index word `token` matches the query word `query`, 0 when it does not
+fn token_score(query: &str, token: &str) -> f64 {
+    if query == token {
+        return 1.0;
+    }
+    let (query_len, token_len) = (query.chars().count(), token.chars().count());
+    if query_len >= 2 && token.starts_with(query) {
+        // "diff" is a better prefix of "diffusion" than "d"
+        return 0.5 + 0.4 * query_len as f64 / token_len as f64;
+    }
+    let allowed = max_typos(query);
+    if allowed == 0 || query_len.abs_diff(token_len) > allowed {
+        return 0.0;
+    }
+    match edit_distance(query, token) {
+        distance if distance <= allowed => 0.7 - 0.2 * (distance - 1) as f64,
+        _ => 0.0,
+    }
+}
+
+fn index_key(token: &str, node_id: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(token.len() + 9);
+    key.extend_from_slice(token.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&node_id.to_be_bytes());
+    key
+}
+
+fn split_index_key(key: &[u8]) -> Result<(&[u8], u64), ReachdbError> {
+    match key.len().checked_sub(9) {
+        Some(split) if key[split] == 0 => {
+            let node_id = u64::from_be_bytes(key[split + 1..].try_into().expect("8 bytes left"));
+            Ok((&key[..split], node_id))
+        }
+        _ => Err(ReachdbError::OtherError("Malformed key in the search index".to_string())),
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    pub(crate) fn index_node_name(&mut self, node_id: u64, name: &str) -> Result<(), ReachdbError> {
+        for token in tokenize(name) {
+            self.sled_insert(SledTree::NodeToken, &index_key(&token, node_id), Vec::new())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unindex_node_name(&mut self, node_id: u64, name: &str) -> Result<(), ReachdbError> {
+        for token in tokenize(name) {
+            self.sled_remove(SledTree::NodeToken, &index_key(&token, node_id))?;
+        }
+        Ok(())
+    }
+
+    /// Builds the search index again from the names of the nodes
+    pub fn rebuild_search_index(&mut self) -> Result<(), ReachdbError> {
+        self.transaction(|db| {
+            for (key, _) in db.sled_scan_prefix(SledTree::NodeToken, &[])? {
+                db.sled_remove(SledTree::NodeToken, &key)?;
+            }
+            for node_id in db.get_all_node_ids()? {
+                // A node that lost its name cannot be found by it anyway
+                if let Ok(name) = db.get_node_name(node_id) {
+                    db.index_node_name(node_id, &name)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Nodes whose names best match `query`, best first
+    ///
+    /// Each query word matches a word of the name exactly, as a prefix ("diff" finds "Diffusion")
+    /// or with a typo or two depending on its length ("difusion"). Names covering more of the
+    /// query rank higher, and among those, names with fewer other words.
+    pub fn search_nodes(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, ReachdbError> {
+        let mut words = tokenize(query);
+        if words.iter().any(|word| !STOP_WORDS.contains(&word.as_str())) {
+            words.retain(|word| !STOP_WORDS.contains(&word.as_str()));
+        }
+        words.sort();
+        words.dedup();
+        if words.is_empty() || limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Best score of each query word, per node
+        let mut best: HashMap<u64, Vec<f64>> = HashMap::new();
+        let mut record = |node_id: u64, word: usize, score: f64| {
+            let node = best.entry(node_id).or_insert_with(|| vec![0.0; words.len()]);
+            node[word] = node[word].max(score);
+        };
+        let tree = self.sled_tree(SledTree::NodeToken);
+
+        // Exact and prefix matches, one range scan per query word
+        for (i, word) in words.iter().enumerate() {
+            let mut prefix = word.as_bytes().to_vec();
+            if word.chars().count() < 2 {
+                // Too short to be a prefix, only the word itself matches
+                prefix.push(0);
+            }
+            for key in tree.scan_prefix(&prefix).keys() {
+                let key = key?;
+                let (token, node_id) = split_index_key(&key)?;
+                let score = token_score(word, &String::from_utf8_lossy(token));
+                if score > 0.0 {
+                    record(node_id, i, score);
+                }
+            }
+        }
+
+        // Typos, the nodes of a token are only read when it is close to a query word
+        if words.iter().any(|word| max_typos(word) > 0) {
+            let mut from = Vec::new();
+            while let Some(entry) = tree.range(from.as_slice()..).next() {
+                let (key, _) = entry?;
+                let (token, _) = split_index_key(&key)?;
+                let text = String::from_utf8_lossy(token);
+                let close: Vec<(usize, f64)> = words.iter()
+                    .enumerate()
+                    // Tokens starting with the word were scored above
+                    .filter(|(_, word)| !token.starts_with(word.as_bytes()))
+                    .map(|(i, word)| (i, token_score(word, &text)))
+                    .filter(|(_, score)| *score > 0.0)
+                    .collect();
+                let mut token_key = token.to_vec();
+                token_key.push(0);
+                if !close.is_empty() {
+                    for key in tree.scan_prefix(&token_key).keys() {
+                        let (_, node_id) = split_index_key(&key?)?;
+                        for &(i, score) in &close {
+                            record(node_id, i, score);
+                        }
+                    }
+                }
+                // `token \1` sorts after every `token \0 node id` key
+                token_key.pop();
+                token_key.push(1);
+                from = token_key;
+            }
+        }
+
+        let mut hits = Vec::with_capacity(best.len());
+        for (node_id, scores) in best {
+            let name = self.get_node_name(node_id)?;
+            let matched = scores.iter().sum::<f64>() / words.len() as f64;
+            let name_words = tokenize(&name).len().max(1);
+            let covered = scores.iter().filter(|score| **score > 0.0).count().min(name_words);
+            let score = matched * (0.8 + 0.2 * covered as f64 / name_words as f64);
+            hits.push(SearchHit { node_id, name, score });
+        }
+        hits.sort_by(|a, b| {
+            b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal)
+                .then(a.name.len().cmp(&b.name.len()))
+                .then(a.node_id.cmp(&b.node_id))
+        });
+        hits.truncate(limit);
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::temp_db;
+    use crate::Reachdb;
+    use crate::test_utils::TypeId;
+    use super::edit_distance;
+
+    #[test]
+    fn edit_distance_counts_swaps_once() {
+        assert_eq!(edit_distance("diffusion", "difusion"), 1);
+        assert_eq!(edit_distance("transformer", "transfromer"), 1);
+        assert_eq!(edit_distance("gan", "gpt"), 2);
+    }
+
+    fn hit_names(db: &Reachdb<TypeId>, query: &str, limit: usize) -> Vec<String> {
+        db.search_nodes(query, limit).unwrap().into_iter().map(|hit| hit.name).collect()
+    }
+
+    /// Diffusion models in a chain of IS-A, and names that only look alike
+    fn diffusion_names() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("Diffusion Model", "Generative Model", "IS-A").unwrap();
+        db.add_edge("Latent Diffusion Models", "Diffusion Model", "IS-A").unwrap();
+        db.add_edge("Stable Diffusion", "Latent Diffusion Models", "IS-A").unwrap();
+        db.add_edge("Diffie-Hellman", "Key Exchange", "IS-A").unwrap();
+        db.add_edge("Transformer", "Generative Model", "RELATES-TO").unwrap();
+        (dir, db)
+    }
+
+    #[test]
+    fn search_ranks_exact_prefix_and_fuzzy_matches() {
+        let (_dir, db) = diffusion_names();
+        assert_eq!(hit_names(&db, "the diffusion thing", 1), vec!["Diffusion Model"]);
+        assert_eq!(hit_names(&db, "diffusion models", 2), vec!["Diffusion Model", "Latent Diffusion Models"]);
+        assert_eq!(hit_names(&db, "difusion", 10).len(), 3);
+        assert_eq!(hit_names(&db, "diff", 10).len(), 4);
+        assert_eq!(hit_names(&db, "transfromer", 10), vec!["Transformer"]);
+        assert!(hit_names(&db, "quantum", 10).is_empty());
+        let top = db.search_nodes("Stable Diffusion", 1).unwrap();
+        assert_eq!(top[0].score, 1.0);
+    }
+
+    #[test]
+    fn repeated_query_words_change_nothing() {
+        let (_dir, db) = diffusion_names();
+        let score = |query: &str| db.search_nodes(query, 10).unwrap().into_iter().find(|hit| hit.name == "Latent Diffusion Models").unwrap().score;
+        assert_eq!(score("diffusion model diffusion"), score("diffusion model"));
+    }
+
+    #[test]
+    fn removed_nodes_leave_the_index() {
+        let (_dir, mut db) = diffusion_names();
+        db.remove_node("Stable Diffusion").unwrap();
+        assert_eq!(hit_names(&db, "stable", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn missing_index_is_rebuilt_on_open() {
+        let (dir, mut db) = diffusion_names();
+        db.sled_tree(crate::wal::SledTree::NodeToken).clear().unwrap();
+        db.close().unwrap();
+        let db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        assert_eq!(db.search_nodes("key exchange", 5).unwrap()[0].name, "Key Exchange");
+    }
+}
@@ -0,0 +1,118 @@
+//! Handle sharing one open `Reachdb` between threads and async tasks
+//!
+//! Reads take `&self` and run side by side under the read lock, writes wait for the readers
+//! and hold the write lock for the whole call.
+
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::data_base::{Reachdb, UserDefinedRelationType};
+use crate::errors::ReachdbError;
+
+/// Cloneable, thread-safe handle to an open database, clones share the same database
+#[derive(Debug)]
+pub struct SharedReachdb<E: UserDefinedRelationType> {
+    inner: Arc<RwLock<Reachdb<E>>>,
+}
+
+impl<E: UserDefinedRelationType> Clone for SharedReachdb<E> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+fn poisoned<T>(_: T) -> ReachdbError {
+    ReachdbError::OtherError("A writer panicked while holding the database".to_string())
+}
+
+impl<E: UserDefinedRelationType> SharedReachdb<E> {
+    pub fn new(db: Reachdb<E>) -> Self {
+        Self { inner: Arc::new(RwLock::new(db)) }
+    }
+
+    /// Opens the database at `path` for writing, see `Reachdb::open`
+    pub fn open(path: &str) -> Result<Self, ReachdbError> {
+        Ok(Self::new(Reachdb::open(path, None, None)?))
+    }
+
+    /// Opens the database at `path` read-only, see `Reachdb::open_read_only`
+    pub fn open_read_only(path: &str) -> Result<Self, ReachdbError> {
+        Ok(Self::new(Reachdb::open_read_only(path)?))
+    }
+
+    /// Shared access, blocks while a writer holds the database
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, Reachdb<E>>, ReachdbError> {
+        self.inner.read().map_err(poisoned)
+    }
+
+    /// Exclusive access, blocks until the readers are done
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, Reachdb<E>>, ReachdbError> {
+        self.inner.write().map_err(poisoned)
+    }
+
+    /// Runs `f` under the read lock
+    pub fn with_read<T>(&self, f: impl FnOnce(&Reachdb<E>) -> Result<T, ReachdbError>) -> Result<T, ReachdbError> {
+        f(&*self.read()?)
+    }
+
+    /// Runs `f` in a transaction under the write lock
+    pub fn with_write<T>(&self, f: impl FnOnce(&mut Reachdb<E>) -> Result<T, ReachdbError>) -> Result<T, ReachdbError> {
+        self.write()?.transaction(f)
+    }
+
+    /// Closes the database, fails while other clones of the handle are alive
+    pub fn close(self) -> Result<(), ReachdbError> {
+        let lock = Arc::try_unwrap(self.inner).map_err(|_| {
+            ReachdbError::OtherError("The database is still shared, drop the other handles first".to_string())
+        })?;
+        lock.into_inner().map_err(poisoned)?.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::{Reachdb, SharedReachdb};
+
+    #[test]
+    fn readers_and_a_writer_share_a_handle() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        let shared = SharedReachdb::new(db);
+
+        let writer = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for i in 0..20 {
+                    shared.with_write(|db| db.add_edge(&format!("Model {}", i), "Transformer", "IS-A")).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4).map(|_| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    let found = shared.with_read(|db| db.find_edge("GPT", "Transformer", "IS-A")).unwrap();
+                    assert!(found.is_some());
+                }
+            })
+        }).collect();
+        writer.join().unwrap();
+        readers.into_iter().for_each(|reader| reader.join().unwrap());
+
+        assert!(shared.read().unwrap().get_node_id("Model 19").unwrap().is_some());
+    }
+
+    #[test]
+    fn only_the_last_handle_closes_and_releases_the_lock() {
+        let (dir, db) = temp_db(None, None);
+        let shared = SharedReachdb::new(db);
+        let clone = shared.clone();
+        assert!(shared.close().is_err());
+        clone.close().unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        Reachdb::<TypeId>::open(path, None, None).unwrap().close().unwrap();
+    }
+}
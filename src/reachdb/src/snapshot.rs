@@ -0,0 +1,727 @@
+//! Point-in-time copies of a database, as a directory or a compressed single-file archive
+//!
+//! A snapshot holds the record files as they are mapped, every tree of the two sled databases,
+//! the named graphs and the metadata, written last so an interrupted snapshot is never taken
+//! for a database. The archive is a gzip stream of the same entries:
+//!
+//! ```text
+//! "REACHSNP" version:u32
+//! 1 name file_len:u64 bytes                                  a record or metadata file
+//! 2 db:u8 graph name (key_len:u32 key value_len:u32 value)* u32::MAX     a sled tree
+//! 0                                                          end
+//! ```
+//!
+//! Names are a `u32` length followed by the bytes. Files and trees of a named graph carry its
+//! directory, like `reachdb.graphs/iteration-1/`, in front of their name and in `graph`, which
+//! is empty for the main database and missing in version 1 archives.
+
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use log::info;
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    graphs::check_graph_name,
+    lock::{LockFile, LockMode},
+};
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"REACHSNP";
+const ARCHIVE_VERSION: u32 = 2;
+
+const ENTRY_END: u8 = 0;
+const ENTRY_FILE: u8 = 1;
+const ENTRY_TREE: u8 = 2;
+const TREE_END: u32 = u32::MAX;
+/// Longest name or sled key/value an archive may hold, and longest file
+const MAX_ARCHIVE_BYTES: u64 = 1 << 30;
+const MAX_ARCHIVE_FILE: u64 = 1 << 40;
+
+/// Positions in `get_db_path` of the sled databases and of the files, the metadata last
+const SLED_PATHS: [usize; 2] = [0, 1];
+const RECORD_PATHS: [usize; 3] = [2, 3, 6];
+const METADATA_PATH: usize = 4;
+const GRAPHS_PATH: usize = 8;
+/// Positions in `get_db_path` of what a restore replaces, the metadata last; the lock file stays
+const SWAPPED_PATHS: [usize; 8] = [0, 1, 2, 3, 5, 6, GRAPHS_PATH, METADATA_PATH];
+/// Written into `<path>.old` once every live entry of a restore was set aside
+const SET_ASIDE_MARKER: &str = "reachdb.restore.moved";
+
+/// What a snapshot or a restore copied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub node_count: u64,
+    pub relationship_count: u64,
+    /// Bytes of the record and metadata files
+    pub file_bytes: u64,
+    /// Key/value pairs of all sled trees
+    pub sled_entries: u64,
+    /// Named graphs, their files and entries are counted above
+    pub graphs: u64,
+}
+
+impl fmt::Display for SnapshotInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} relationships ({} bytes of files, {} sled entries)",
+            self.node_count, self.relationship_count, self.file_bytes, self.sled_entries
+        )?;
+        if self.graphs > 0 {
+            write!(f, " and {} named graphs", self.graphs)?;
+        }
+        Ok(())
+    }
+}
+
+/// `<path>.<suffix>`, next to the database directory
+fn aside_dir(path: &str, suffix: &str) -> String {
+    format!("{}.{}", path.trim_end_matches('/'), suffix)
+}
+
+fn remove_path(path: &str) -> Result<(), ReachdbError> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Receives the entries of a snapshot
+trait SnapshotSink {
+    fn file(&mut self, name: &str, bytes: &[u8]) -> Result<(), ReachdbError>;
+    /// `graph` is the directory of a named graph, ending with `/`, or empty
+    fn tree(&mut self, graph: &str, db: u8, name: &[u8], entries: &mut dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>) -> Result<u64, ReachdbError>;
+    fn finish(self) -> Result<(), ReachdbError>;
+}
+
+/// Writes the entries into a database directory
+struct DirectorySink {
+    path: String,
+    /// Paths of `get_db_path`
+    paths: Vec<String>,
+    /// Sled databases opened so far, by graph directory and position in `SLED_PATHS`
+    sled: BTreeMap<(String, u8), sled::Db>,
+    _lock: LockFile,
+}
+
+impl DirectorySink {
+    /// Locks `path`, refused when it holds a database
+    fn create<E: UserDefinedRelationType>(path: &str) -> Result<Self, ReachdbError> {
+        std::fs::create_dir_all(path)?;
+        let paths = Reachdb::<E>::get_db_path(path);
+        let lock = LockFile::acquire(&paths[7], LockMode::ReadWrite)?;
+        if Path::new(&paths[METADATA_PATH]).exists() {
+            return Err(ReachdbError::OtherError(format!("{} already holds a database", path)));
+        }
+        Ok(Self { path: path.to_string(), paths, sled: BTreeMap::new(), _lock: lock })
+    }
+
+    /// Checks that `graph` is empty or a chain of `reachdb.graphs/<name>/`, so nothing lands outside `path`
+    fn check_graph_dir(&self, graph: &str) -> Result<(), ReachdbError> {
+        let graphs = file_name(&self.paths[GRAPHS_PATH]);
+        let parts: Vec<&str> = graph.strip_suffix('/').map(|dir| dir.split('/').collect()).unwrap_or_default();
+        let valid = (graph.is_empty() || !parts.is_empty())
+            && parts.chunks(2).all(|pair| pair.len() == 2 && pair[0] == graphs && check_graph_name(pair[1]).is_ok());
+        if !valid {
+            return Err(ReachdbError::OtherError(format!("Invalid graph directory {} in snapshot", graph)));
+        }
+        Ok(())
+    }
+}
+
+impl SnapshotSink for DirectorySink {
+    fn file(&mut self, name: &str, bytes: &[u8]) -> Result<(), ReachdbError> {
+        let (graph, file) = name.rsplit_once('/').map_or(("", name), |(dir, file)| (&name[..=dir.len()], file));
+        self.check_graph_dir(graph)?;
+        if !RECORD_PATHS.into_iter().chain([METADATA_PATH]).any(|index| file_name(&self.paths[index]) == file) {
+            return Err(ReachdbError::OtherError(format!("Unknown file {} in snapshot", name)));
+        }
+        let path = format!("{}/{}", self.path, name);
+        if let Some(parent) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    fn tree(&mut self, graph: &str, db: u8, name: &[u8], entries: &mut dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>) -> Result<u64, ReachdbError> {
+        let Some(index) = SLED_PATHS.get(db as usize) else {
+            return Err(ReachdbError::OtherError(format!("Unknown sled database {} in snapshot", db)));
+        };
+        self.check_graph_dir(graph)?;
+        let handle = match self.sled.entry((graph.to_string(), db)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = format!("{}/{}{}", self.path, graph, file_name(&self.paths[*index]));
+                entry.insert(sled::open(path)?)
+            }
+        };
+        let tree = handle.open_tree(name)?;
+        let mut count = 0;
+        for entry in entries {
+            let (key, value) = entry?;
+            tree.insert(key, value)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn finish(self) -> Result<(), ReachdbError> {
+        for db in self.sled.values() {
+            db.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the entries into a gzip archive
+struct ArchiveSink {
+    out: GzEncoder<BufWriter<File>>,
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> Result<(), ReachdbError> {
+    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+impl SnapshotSink for ArchiveSink {
+    fn file(&mut self, name: &str, bytes: &[u8]) -> Result<(), ReachdbError> {
+        self.out.write_all(&[ENTRY_FILE])?;
+        write_bytes(&mut self.out, name.as_bytes())?;
+        self.out.write_all(&(bytes.len() as u64).to_le_bytes())?;
+        self.out.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn tree(&mut self, graph: &str, db: u8, name: &[u8], entries: &mut dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>) -> Result<u64, ReachdbError> {
+        self.out.write_all(&[ENTRY_TREE, db])?;
+        write_bytes(&mut self.out, graph.as_bytes())?;
+        write_bytes(&mut self.out, name)?;
+        let mut count = 0;
+        for entry in entries {
+            let (key, value) = entry?;
+            write_bytes(&mut self.out, &key)?;
+            write_bytes(&mut self.out, &value)?;
+            count += 1;
+        }
+        self.out.write_all(&TREE_END.to_le_bytes())?;
+        Ok(count)
+    }
+
+    fn finish(mut self) -> Result<(), ReachdbError> {
+        self.out.write_all(&[ENTRY_END])?;
+        self.out.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32, ReachdbError> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Reads `len` bytes, refusing lengths above `max` that only a corrupt archive holds
+fn read_vec(input: &mut impl Read, len: u64, max: u64) -> Result<Vec<u8>, ReachdbError> {
+    if len > max {
+        return Err(ReachdbError::OtherError(format!("Corrupt archive, entry of {} bytes", len)));
+    }
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(ReachdbError::OtherError("Archive ends in the middle of an entry".to_string()));
+    }
+    Ok(bytes)
+}
+
+fn read_bytes(input: &mut impl Read) -> Result<Vec<u8>, ReachdbError> {
+    let len = read_u32(input)?;
+    read_vec(input, len as u64, MAX_ARCHIVE_BYTES)
+}
+
+/// Pairs of a tree in an archive, up to its end marker
+struct ArchiveTree<'a, R: Read> {
+    input: &'a mut R,
+    error: Option<ReachdbError>,
+}
+
+impl<R: Read> ArchiveTree<'_, R> {
+    fn next_pair(&mut self) -> Result<Option<(sled::IVec, sled::IVec)>, ReachdbError> {
+        let key_len = read_u32(self.input)?;
+        if key_len == TREE_END {
+            return Ok(None);
+        }
+        let key = read_vec(self.input, key_len as u64, MAX_ARCHIVE_BYTES)?;
+        Ok(Some((key.into(), read_bytes(self.input)?.into())))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveTree<'_, R> {
+    type Item = sled::Result<(sled::IVec, sled::IVec)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_pair() {
+            Ok(pair) => pair.map(Ok),
+            Err(e) => {
+                // sled errors cannot hold ours, it is reported once the tree is done
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Replays the entries of the archive `file` into `sink`, the metadata comes last
+fn read_archive(file: &str, metadata_name: &str, sink: &mut impl SnapshotSink) -> Result<SnapshotInfo, ReachdbError> {
+    let mut input = GzDecoder::new(BufReader::new(File::open(file)?));
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic).map_err(|_| ReachdbError::OtherError(format!("{} is not a reachdb archive", file)))?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(ReachdbError::OtherError(format!("{} is not a reachdb archive", file)));
+    }
+    let version = read_u32(&mut input)?;
+    if version == 0 || version > ARCHIVE_VERSION {
+        return Err(ReachdbError::OtherError(format!(
+            "{} has archive version {}, this build reads version {}", file, version, ARCHIVE_VERSION
+        )));
+    }
+
+    let mut info = SnapshotInfo::default();
+    let mut has_metadata = false;
+    loop {
+        let mut kind = [0; 1];
+        input.read_exact(&mut kind)?;
+        match kind[0] {
+            ENTRY_END => break,
+            ENTRY_FILE => {
+                let name = String::from_utf8_lossy(&read_bytes(&mut input)?).into_owned();
+                let mut len = [0; 8];
+                input.read_exact(&mut len)?;
+                let bytes = read_vec(&mut input, u64::from_le_bytes(len), MAX_ARCHIVE_FILE)?;
+                info.file_bytes += bytes.len() as u64;
+                if name == metadata_name {
+                    let counts: serde_json::Value = serde_json::from_slice(&bytes)?;
+                    info.node_count = counts["node_count"].as_u64().unwrap_or_default();
+                    info.relationship_count = counts["relationship_count"].as_u64().unwrap_or_default();
+                    info.graphs = counts["graphs"].as_object().map_or(0, |graphs| graphs.len() as u64);
+                    has_metadata = true;
+                }
+                sink.file(&name, &bytes)?;
+            }
+            ENTRY_TREE => {
+                let mut db = [0; 1];
+                input.read_exact(&mut db)?;
+                let graph = match version {
+                    1 => String::new(),
+                    _ => String::from_utf8_lossy(&read_bytes(&mut input)?).into_owned(),
+                };
+                let name = read_bytes(&mut input)?;
+                let mut tree = ArchiveTree { input: &mut input, error: None };
+                info.sled_entries += sink.tree(&graph, db[0], &name, &mut tree)?;
+                if let Some(e) = tree.error {
+                    return Err(e);
+                }
+            }
+            other => return Err(ReachdbError::OtherError(format!("Unknown entry {} in {}", other, file))),
+        }
+    }
+
+    if !has_metadata {
+        return Err(ReachdbError::OtherError(format!("{} has no metadata", file)));
+    }
+    Ok(info)
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Copies the database into the new directory `dest`, which can be opened like any other
+    ///
+    /// Writes need `&mut self`, so nothing changes while the copy runs; through a `SharedReachdb`
+    /// writers only wait for the time of the copy.
+    pub fn snapshot(&self, dest: &str) -> Result<SnapshotInfo, ReachdbError> {
+        let mut sink = DirectorySink::create::<E>(dest)?;
+        let info = self.write_snapshot(&mut sink, "")?;
+        sink.finish()?;
+        info!("Snapshot of {} in {}: {}", self.path, dest, info);
+        Ok(info)
+    }
+
+    /// Copies the database into the compressed single-file archive `file`, see `snapshot`
+    pub fn snapshot_archive(&self, file: &str) -> Result<SnapshotInfo, ReachdbError> {
+        let tmp = format!("{}.partial", file);
+        let mut sink = ArchiveSink { out: GzEncoder::new(BufWriter::new(File::create(&tmp)?), Compression::default()) };
+        sink.out.write_all(ARCHIVE_MAGIC)?;
+        sink.out.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        let info = self.write_snapshot(&mut sink, "")?;
+        sink.finish()?;
+        std::fs::rename(&tmp, file)?;
+        info!("Snapshot of {} in {}: {}", self.path, file, info);
+        Ok(info)
+    }
+
+    /// Replaces the database at `path` with the snapshot `source`, a directory or an archive
+    ///
+    /// The database must be closed. The snapshot is first restored into `<path>.partial` and
+    /// only moved in place of the database files once complete, so a missing or corrupt
+    /// snapshot leaves the database as it was. The live files are set aside in `<path>.old`
+    /// until the restored ones are in place, a restore interrupted before it completed is
+    /// rolled back when the database is next opened for writing or restored.
+    pub fn restore(source: &str, path: &str) -> Result<SnapshotInfo, ReachdbError> {
+        if Path::new(source).is_dir()
+            && Path::new(source).canonicalize()? == Path::new(path).canonicalize().unwrap_or_default()
+        {
+            return Err(ReachdbError::OtherError(format!("Cannot restore {} onto itself", source)));
+        }
+        std::fs::create_dir_all(path)?;
+        let paths = Self::get_db_path(path);
+        let _lock = LockFile::acquire(&paths[7], LockMode::ReadWrite)?;
+        Self::roll_back_restore(path)?;
+
+        let tmp = format!("{}.partial", path.trim_end_matches('/'));
+        if Path::new(&tmp).exists() {
+            std::fs::remove_dir_all(&tmp)?;
+        }
+        let info = match Self::restore_into(source, &tmp) {
+            Ok(info) => info,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&tmp);
+                return Err(e);
+            }
+        };
+
+        // Set the live entries aside, the metadata first, then move the restored ones in, the metadata last
+        let old = aside_dir(path, "old");
+        std::fs::create_dir(&old)?;
+        let set_aside = Self::get_db_path(&old);
+        for index in SWAPPED_PATHS.into_iter().rev() {
+            if Path::new(&paths[index]).exists() {
+                std::fs::rename(&paths[index], &set_aside[index])?;
+            }
+        }
+        File::create(format!("{}/{}", old, SET_ASIDE_MARKER))?.sync_all()?;
+        let restored = Self::get_db_path(&tmp);
+        for index in SWAPPED_PATHS {
+            if Path::new(&restored[index]).exists() {
+                std::fs::rename(&restored[index], &paths[index])?;
+            }
+        }
+        // Renamed first, so a partly deleted copy is never rolled back
+        let discarded = aside_dir(path, "discarded");
+        std::fs::rename(&old, &discarded)?;
+        std::fs::remove_dir_all(&discarded)?;
+        std::fs::remove_dir_all(&tmp)?;
+        info!("Restored {} from {}: {}", path, source, info);
+        Ok(info)
+    }
+
+    /// Puts back the entries set aside in `<path>.old` by a restore that did not complete
+    ///
+    /// Runs again from the start when interrupted itself. The caller holds the lock of `path`.
+    pub(crate) fn roll_back_restore(path: &str) -> Result<(), ReachdbError> {
+        let discarded = aside_dir(path, "discarded");
+        if Path::new(&discarded).exists() {
+            std::fs::remove_dir_all(&discarded)?;
+        }
+        let old = aside_dir(path, "old");
+        if !Path::new(&old).exists() {
+            return Ok(());
+        }
+        info!("Rolling back the interrupted restore of {}", path);
+        let paths = Self::get_db_path(path);
+        let set_aside = Self::get_db_path(&old);
+
+        // Once everything was set aside, the live entries without a counterpart were restored
+        let marker = format!("{}/{}", old, SET_ASIDE_MARKER);
+        if Path::new(&marker).exists() {
+            for index in SWAPPED_PATHS {
+                if !Path::new(&set_aside[index]).exists() {
+                    remove_path(&paths[index])?;
+                }
+            }
+            std::fs::remove_file(&marker)?;
+        }
+        for index in SWAPPED_PATHS {
+            if Path::new(&set_aside[index]).exists() {
+                remove_path(&paths[index])?;
+                std::fs::rename(&set_aside[index], &paths[index])?;
+            }
+        }
+        std::fs::remove_dir_all(&old)?;
+        Ok(())
+    }
+
+    /// Writes the snapshot `source` into the new directory `dest`
+    fn restore_into(source: &str, dest: &str) -> Result<SnapshotInfo, ReachdbError> {
+        let mut sink = DirectorySink::create::<E>(dest)?;
+        let info = if Path::new(source).is_dir() {
+            let mut snapshot = Self::open_read_only(source)?;
+            let info = snapshot.write_snapshot(&mut sink, "");
+            snapshot.close()?;
+            info?
+        } else {
+            read_archive(source, &file_name(&sink.paths[METADATA_PATH]), &mut sink)?
+        };
+        sink.finish()?;
+        Ok(info)
+    }
+
+    /// Sends the files and sled trees of the database and of its named graphs to `sink`, the metadata last
+    ///
+    /// `graph` is the directory of the database inside the snapshot, empty for the top one.
+    fn write_snapshot(&self, sink: &mut impl SnapshotSink, graph: &str) -> Result<SnapshotInfo, ReachdbError> {
+        if self.wal.is_some() {
+            return Err(ReachdbError::OtherError("Cannot take a snapshot inside a transaction".to_string()));
+        }
+        if !self.read_only {
+            self.flush()?;
+        }
+        let paths = Self::get_db_path(&self.path);
+        let mmap = self.mmap.as_ref().expect("Mmap not initialized");
+        let mut info = SnapshotInfo {
+            node_count: self.node_count,
+            relationship_count: self.relationship_count,
+            ..Default::default()
+        };
+
+        for (index, bytes) in RECORD_PATHS.into_iter().zip([
+            Some(mmap.get_node_as_ref()),
+            Some(mmap.get_relation_as_ref()),
+            mmap.get_embedding_as_ref().ok(),
+        ]) {
+            if let Some(bytes) = bytes {
+                sink.file(&format!("{}{}", graph, file_name(&paths[index])), bytes)?;
+                info.file_bytes += bytes.len() as u64;
+            }
+        }
+
+        let sled = self.sled.as_ref().expect("Sled databases not opened");
+        for (db, handle) in SLED_PATHS.into_iter().zip([&sled.node_id, &sled.property]) {
+            for name in handle.tree_names() {
+                let tree = handle.open_tree(&name)?;
+                info.sled_entries += sink.tree(graph, db as u8, &name, &mut tree.iter())?;
+            }
+        }
+
+        // Opened read-only, so a graph that is open elsewhere fails the snapshot instead of being copied halfway
+        for name in self.graphs.keys() {
+            let mut named = Self::open_read_only(&self.graph_path(name))?;
+            let copied = named.write_snapshot(sink, &format!("{}{}/{}/", graph, file_name(&paths[GRAPHS_PATH]), name));
+            named.close()?;
+            let copied = copied?;
+            info.file_bytes += copied.file_bytes;
+            info.sled_entries += copied.sled_entries;
+            info.graphs += 1 + copied.graphs;
+        }
+
+        let metadata = serde_json::to_string(&self)?;
+        sink.file(&format!("{}{}", graph, file_name(&paths[METADATA_PATH])), metadata.as_bytes())?;
+        info.file_bytes += metadata.len() as u64;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::Path;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::test_utils::{temp_db, TypeId};
+    use crate::Reachdb;
+    use super::{ARCHIVE_MAGIC, ARCHIVE_VERSION, ENTRY_FILE, SET_ASIDE_MARKER, SWAPPED_PATHS};
+
+    /// A database of "GPT" and "Transformer", its directory and a directory for its backups
+    fn gpt_with_backups() -> (tempfile::TempDir, Reachdb<TypeId>, tempfile::TempDir) {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+        db.add_node_alias("Transformers architecture", "Transformer").unwrap();
+        (dir, db, tempfile::tempdir().unwrap())
+    }
+
+    /// Checks a snapshot of `gpt_with_backups` restored over a database where "BERT" was added since
+    fn assert_restored(path: &str) {
+        let mut db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert!(db.get_node_id("BERT").unwrap().is_none());
+        assert!(db.find_edge("GPT", "Transformer", "IS-A").unwrap().is_some());
+        assert_eq!(db.search_nodes("transformer", 1).unwrap()[0].name, "Transformer");
+        assert_eq!(db.get_aliased_node_id("Transformers architecture").unwrap(), db.get_node_id("Transformer").unwrap());
+        db.add_edge("BERT", "GPT", "INFLUENCES").unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn snapshots_report_their_counts_and_keep_existing_copies() {
+        let (_dir, db, backups) = gpt_with_backups();
+        let snapshot_dir = backups.path().join("checkpoint");
+        let snapshot_dir = snapshot_dir.to_str().unwrap();
+        let archive = backups.path().join("checkpoint.reachdb.gz");
+
+        let info = db.snapshot(snapshot_dir).unwrap();
+        assert_eq!((info.node_count, info.relationship_count), (2, 1));
+        assert_eq!(db.snapshot_archive(archive.to_str().unwrap()).unwrap(), info);
+        assert!(db.snapshot(snapshot_dir).is_err());
+    }
+
+    #[test]
+    fn snapshot_directories_restore_the_database_as_it_was() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        let snapshot_dir = backups.path().join("checkpoint");
+        let snapshot_dir = snapshot_dir.to_str().unwrap();
+        db.snapshot(snapshot_dir).unwrap();
+        db.add_edge("BERT", "GPT", "INFLUENCES").unwrap();
+        db.close().unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        assert_eq!(Reachdb::<TypeId>::restore(snapshot_dir, path).unwrap().node_count, 2);
+        assert_restored(path);
+    }
+
+    #[test]
+    fn archives_restore_the_database_as_it_was() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        let archive = backups.path().join("checkpoint.reachdb.gz");
+        let archive = archive.to_str().unwrap();
+        db.snapshot_archive(archive).unwrap();
+        db.add_edge("BERT", "GPT", "INFLUENCES").unwrap();
+        db.close().unwrap();
+
+        let path = dir.path().to_str().unwrap();
+        assert_eq!(Reachdb::<TypeId>::restore(archive, path).unwrap().node_count, 2);
+        assert_restored(path);
+    }
+
+    #[test]
+    fn open_databases_are_not_replaced() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        let archive = backups.path().join("checkpoint.reachdb.gz");
+        let archive = archive.to_str().unwrap();
+        db.snapshot_archive(archive).unwrap();
+        assert!(Reachdb::<TypeId>::restore(archive, dir.path().to_str().unwrap()).is_err());
+        db.close().unwrap();
+    }
+
+    /// Restores the snapshot at `archive` into `path` by hand up to a crash, after setting aside
+    /// the first `set_aside` live entries and moving in the first `moved_in` restored ones
+    fn interrupted_restore(archive: &str, path: &str, set_aside: usize, moved_in: usize) {
+        let tmp = format!("{}.partial", path);
+        Reachdb::<TypeId>::restore_into(archive, &tmp).unwrap();
+        let old = super::aside_dir(path, "old");
+        std::fs::create_dir(&old).unwrap();
+        let (paths, aside, restored) = (Reachdb::<TypeId>::get_db_path(path), Reachdb::<TypeId>::get_db_path(&old), Reachdb::<TypeId>::get_db_path(&tmp));
+        for index in SWAPPED_PATHS.into_iter().rev().take(set_aside) {
+            if Path::new(&paths[index]).exists() {
+                std::fs::rename(&paths[index], &aside[index]).unwrap();
+            }
+        }
+        if set_aside == SWAPPED_PATHS.len() {
+            File::create(format!("{}/{}", old, SET_ASIDE_MARKER)).unwrap();
+        }
+        for index in SWAPPED_PATHS.into_iter().take(moved_in) {
+            if Path::new(&restored[index]).exists() {
+                std::fs::rename(&restored[index], &paths[index]).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn interrupted_restores_are_rolled_back_on_open() {
+        for (set_aside, moved_in) in [(3, 0), (SWAPPED_PATHS.len(), 0), (SWAPPED_PATHS.len(), 5)] {
+            let (dir, mut db, backups) = gpt_with_backups();
+            let archive = backups.path().join("checkpoint.reachdb.gz");
+            let archive = archive.to_str().unwrap();
+            db.snapshot_archive(archive).unwrap();
+            db.add_edge("BERT", "GPT", "INFLUENCES").unwrap();
+            db.close().unwrap();
+
+            let path = dir.path().to_str().unwrap();
+            interrupted_restore(archive, path, set_aside, moved_in);
+            let mut db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+            assert!(db.verify().unwrap().is_ok());
+            assert!(db.find_edge("BERT", "GPT", "INFLUENCES").unwrap().is_some());
+            db.close().unwrap();
+            assert!(!Path::new(&super::aside_dir(path, "old")).exists());
+
+            // The next restore starts from the database as it was
+            Reachdb::<TypeId>::restore(archive, path).unwrap();
+            assert_restored(path);
+        }
+    }
+
+    /// Writes an archive of the given entries under the right header
+    fn write_archive(path: &Path, entries: &[u8]) {
+        let mut out = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        out.write_all(ARCHIVE_MAGIC).unwrap();
+        out.write_all(&ARCHIVE_VERSION.to_le_bytes()).unwrap();
+        out.write_all(entries).unwrap();
+        out.finish().unwrap();
+    }
+
+    fn file_entry(name: &str, len: u64) -> Vec<u8> {
+        let mut entry = vec![ENTRY_FILE];
+        entry.extend((name.len() as u32).to_le_bytes());
+        entry.extend(name.as_bytes());
+        entry.extend(len.to_le_bytes());
+        entry
+    }
+
+    /// Restoring `bad` fails and leaves the closed database at `dir` as it was
+    fn assert_rejected(dir: &tempfile::TempDir, bad: &Path) {
+        let path = dir.path().to_str().unwrap();
+        assert!(Reachdb::<TypeId>::restore(bad.to_str().unwrap(), path).is_err());
+        let mut db = Reachdb::<TypeId>::open(path, None, None).unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert!(db.find_edge("GPT", "Transformer", "IS-A").unwrap().is_some());
+        db.close().unwrap();
+        assert!(!Path::new(&format!("{}.partial", path)).exists());
+    }
+
+    #[test]
+    fn truncated_archives_leave_the_database_intact() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        let archive = backups.path().join("checkpoint.reachdb.gz");
+        db.snapshot_archive(archive.to_str().unwrap()).unwrap();
+        db.close().unwrap();
+
+        let bad = backups.path().join("bad.reachdb.gz");
+        let full = std::fs::read(&archive).unwrap();
+        std::fs::write(&bad, &full[..full.len() / 2]).unwrap();
+        assert_rejected(&dir, &bad);
+    }
+
+    #[test]
+    fn names_escaping_the_directory_are_rejected() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        db.close().unwrap();
+        let bad = backups.path().join("bad.reachdb.gz");
+        let mut escaping = file_entry("../escaped", 2);
+        escaping.extend(b"{}");
+        write_archive(&bad, &escaping);
+        assert_rejected(&dir, &bad);
+        assert!(!dir.path().parent().unwrap().join("escaped").exists());
+    }
+
+    #[test]
+    fn absurd_lengths_and_missing_archives_are_rejected() {
+        let (dir, mut db, backups) = gpt_with_backups();
+        db.close().unwrap();
+        let bad = backups.path().join("bad.reachdb.gz");
+        write_archive(&bad, &file_entry("reachdb.node.db", u64::MAX));
+        assert_rejected(&dir, &bad);
+        assert_rejected(&dir, Path::new("missing.reachdb.gz"));
+    }
+}
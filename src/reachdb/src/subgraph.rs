@@ -0,0 +1,320 @@
+//! Bounded neighbourhoods of a few concepts, copied out of the database
+//!
+//! A `Subgraph` owns its names and properties, so it can be handed to a prompt, serialized
+//! to JSON or written into a database of its own after the source database is closed.
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    algorithms::Direction,
+    data_base::{Reachdb, UserDefinedRelationType},
+    diff::EdgeTriple,
+    errors::ReachdbError,
+    records::property::PropertyValue,
+};
+
+/// A node of a subgraph, `id` is its id in the source database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubgraphNode {
+    pub id: u64,
+    pub name: String,
+    /// Hops from the nearest seed
+    pub depth: usize,
+    pub properties: BTreeMap<String, PropertyValue>,
+}
+
+/// A relation between two nodes of a subgraph, with the ids of the source database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubgraphEdge {
+    pub id: u64,
+    pub source_id: u64,
+    pub target_id: u64,
+    pub relation: String,
+    pub properties: BTreeMap<String, PropertyValue>,
+}
+
+/// Nodes around the seeds and every followed relation between them, created by `Reachdb::subgraph`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Subgraph {
+    /// Seeds first, then by depth
+    pub nodes: Vec<SubgraphNode>,
+    /// Sorted by id
+    pub edges: Vec<SubgraphEdge>,
+    /// `max_nodes` stopped the expansion before `hops` was reached
+    pub truncated: bool,
+}
+
+impl Subgraph {
+    pub fn node(&self, name: &str) -> Option<&SubgraphNode> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    /// The edges with the names of their nodes, `#<id>` for a node the subgraph does not hold
+    pub fn triples(&self) -> Vec<EdgeTriple> {
+        let names: HashMap<u64, &str> = self.nodes.iter().map(|node| (node.id, node.name.as_str())).collect();
+        let name = |id: u64| names.get(&id).map_or_else(|| format!("#{}", id), |name| name.to_string());
+        self.edges.iter()
+            .map(|edge| EdgeTriple {
+                source: name(edge.source_id),
+                relation: edge.relation.clone(),
+                target: name(edge.target_id),
+            })
+            .collect()
+    }
+
+    /// Writes the subgraph into a new database at `path` and returns it open
+    ///
+    /// Ids are assigned anew. Relation types the database does not know are registered.
+    /// `path` must not exist yet or be an empty directory, and is left that way on failure.
+    pub fn to_reachdb<E: UserDefinedRelationType>(&self, path: &str) -> Result<Reachdb<E>, ReachdbError> {
+        let ids: HashSet<u64> = self.nodes.iter().map(|node| node.id).collect();
+        if let Some(edge) = self.edges.iter().find(|edge| !ids.contains(&edge.source_id) || !ids.contains(&edge.target_id)) {
+            return Err(ReachdbError::OtherError(format!("Edge {} points outside the subgraph", edge.id)));
+        }
+        let target = Path::new(path);
+        let existed = target.exists();
+        if existed && target.read_dir()?.next().is_some() {
+            return Err(ReachdbError::OtherError(format!("{} is not an empty directory", path)));
+        }
+        self.write_reachdb(path).inspect_err(|_| {
+            let _ = std::fs::remove_dir_all(path);
+            if existed {
+                let _ = std::fs::create_dir(path);
+            }
+        })
+    }
+
+    fn write_reachdb<E: UserDefinedRelationType>(&self, path: &str) -> Result<Reachdb<E>, ReachdbError> {
+        let mut db = Reachdb::<E>::open(path, None, None)?;
+        db.transaction(|db| {
+            let mut ids = HashMap::new();
+            for node in &self.nodes {
+                let id = db.get_or_add_node_id(&node.name)?;
+                for (key, value) in &node.properties {
+                    db.set_node_property(id, key, value.clone())?;
+                }
+                ids.insert(node.id, id);
+            }
+            for edge in &self.edges {
+                let type_id = match db.relation_type_id(&edge.relation) {
+                    Some(id) => id,
+                    None => db.register_relation(&edge.relation, None)?,
+                };
+                let (source, target) = (&ids[&edge.source_id], &ids[&edge.target_id]);
+                if db.if_edge_exists(source, target, &type_id)?.is_some() {
+                    continue;
+                }
+                let id = db.add_relation(source, target, &type_id)?;
+                for (key, value) in &edge.properties {
+                    db.set_edge_property(id, key, value.clone())?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(db)
+    }
+}
+
+/// One `"source" -[RELATION]-> "target"` line per edge, then the nodes without edges
+impl fmt::Display for Subgraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connected: HashSet<u64> = self.edges.iter().flat_map(|edge| [edge.source_id, edge.target_id]).collect();
+        for triple in self.triples() {
+            writeln!(f, "{}", triple)?;
+        }
+        for node in self.nodes.iter().filter(|node| !connected.contains(&node.id)) {
+            writeln!(f, "\"{}\"", node.name)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Nodes within `hops` relations of the seeds, and the relations between them
+    ///
+    /// Relations are followed in `direction` and, with a `relation_filter`, only when their type
+    /// is listed. The expansion is breadth-first, so when `max_nodes` cuts it short the nodes
+    /// closest to the seeds are kept. Every relation of the filter between two kept nodes is
+    /// included, also those between nodes at the same depth.
+    pub fn subgraph(
+        &self,
+        seeds: &[&str],
+        hops: usize,
+        direction: Direction,
+        relation_filter: Option<&[&str]>,
+        max_nodes: usize,
+    ) -> Result<Subgraph, ReachdbError> {
+        let type_ids = match relation_filter {
+            Some(relations) => Some(relations.iter()
+                .map(|relation| self.relation_type_id(relation)
+                    .ok_or_else(|| ReachdbError::OtherError(format!("Unknown relation type {}", relation))))
+                .collect::<Result<HashSet<u8>, _>>()?),
+            None => None,
+        };
+        let followed = |type_id: u8| type_ids.as_ref().is_none_or(|ids| ids.contains(&type_id));
+
+        let mut depths: HashMap<u64, usize> = HashMap::new();
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+        let mut truncated = false;
+        for seed in seeds {
+            let id = self.get_node_id(seed)?
+                .ok_or_else(|| ReachdbError::OtherError(format!("Node \"{}\" not found", seed)))?;
+            if depths.contains_key(&id) {
+                continue;
+            }
+            if order.len() == max_nodes {
+                truncated = true;
+                break;
+            }
+            depths.insert(id, 0);
+            order.push(id);
+            queue.push_back(id);
+        }
+
+        'expand: while let Some(node_id) = queue.pop_front() {
+            let depth = depths[&node_id];
+            if depth == hops {
+                continue;
+            }
+            for (rel_id, next) in self.neighbors(node_id, direction)? {
+                if depths.contains_key(&next) || !followed(self.get_relation(rel_id)?.type_id) {
+                    continue;
+                }
+                if order.len() == max_nodes {
+                    truncated = true;
+                    break 'expand;
+                }
+                depths.insert(next, depth + 1);
+                order.push(next);
+                queue.push_back(next);
+            }
+        }
+
+        let mut subgraph = Subgraph { truncated, ..Default::default() };
+        for node_id in order {
+            subgraph.nodes.push(SubgraphNode {
+                id: node_id,
+                name: self.get_node_name(node_id)?,
+                depth: depths[&node_id],
+                properties: self.get_node_properties(node_id)?,
+            });
+            for rel_id in self.get_outgoing_node_relations(node_id)? {
+                let rel = self.get_relation(rel_id)?;
+                if depths.contains_key(&rel.target_id) && followed(rel.type_id) {
+                    subgraph.edges.push(SubgraphEdge {
+                        id: rel_id,
+                        source_id: rel.source_id,
+                        target_id: rel.target_id,
+                        relation: self.get_relation_type_str(rel.type_id),
+                        properties: self.get_edge_properties(rel_id)?,
+                    });
+                }
+            }
+        }
+        subgraph.edges.sort_by_key(|edge| edge.id);
+        Ok(subgraph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithms::Direction;
+    use crate::test_utils::{transformer_taxonomy, TypeId};
+    use crate::{PropertyValue, Reachdb};
+    use super::Subgraph;
+
+    /// The transformer taxonomy up to "Model", GPT's IS-A relation has a source URL
+    fn taxonomy_up_to_model() -> (tempfile::TempDir, Reachdb<TypeId>) {
+        let (dir, mut db) = transformer_taxonomy();
+        db.add_edge("Neural Network", "Model", "IS-A").unwrap();
+        db.add_edge("GPT", "BERT", "RELATES-TO").unwrap();
+        let gpt = db.find_edge("GPT", "Transformer", "IS-A").unwrap().unwrap();
+        db.set_edge_property(gpt, "source_url", PropertyValue::from("https://a.org")).unwrap();
+        (dir, db)
+    }
+
+    fn names(subgraph: &Subgraph) -> Vec<String> {
+        subgraph.nodes.iter().map(|node| node.name.clone()).collect()
+    }
+
+    #[test]
+    fn extracts_bounded_neighbourhoods() {
+        let (_dir, db) = taxonomy_up_to_model();
+        let up = db.subgraph(&["GPT"], 2, Direction::Outgoing, Some(&["IS-A"]), 10).unwrap();
+        assert_eq!(names(&up), vec!["GPT", "Transformer", "Neural Network"]);
+        assert_eq!(up.node("Neural Network").unwrap().depth, 2);
+        assert_eq!(up.edges.len(), 2);
+        assert!(!up.truncated);
+        assert!(db.subgraph(&["Unknown"], 1, Direction::Both, None, 10).is_err());
+    }
+
+    #[test]
+    fn relations_between_kept_nodes_come_along() {
+        let (_dir, db) = taxonomy_up_to_model();
+        // Whichever way they were reached
+        let around = db.subgraph(&["Transformer"], 1, Direction::Both, None, 10).unwrap();
+        assert_eq!(around.nodes.len(), 5);
+        assert_eq!(around.edges.len(), 5);
+        assert!(around.to_string().contains("\"GPT\" -[RELATES-TO]-> \"BERT\""));
+    }
+
+    #[test]
+    fn node_cap_truncates_the_subgraph() {
+        let (_dir, db) = taxonomy_up_to_model();
+        let capped = db.subgraph(&["Transformer"], 3, Direction::Both, None, 2).unwrap();
+        assert_eq!((capped.nodes.len(), capped.truncated), (2, true));
+    }
+
+    #[test]
+    fn subgraphs_survive_json_and_become_databases() {
+        let (_dir, db) = taxonomy_up_to_model();
+        let up = db.subgraph(&["GPT"], 2, Direction::Outgoing, Some(&["IS-A"]), 10).unwrap();
+        let json = serde_json::to_string(&up).unwrap();
+        let copy: Subgraph = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy, up);
+
+        let target = tempfile::tempdir().unwrap();
+        let path = target.path().join("ego");
+        let mut ego = copy.to_reachdb::<TypeId>(path.to_str().unwrap()).unwrap();
+        let edge = ego.find_edge("GPT", "Transformer", "IS-A").unwrap().unwrap();
+        assert_eq!(ego.get_edge_properties(edge).unwrap()["source_url"], PropertyValue::from("https://a.org"));
+        assert_eq!(ego.get_all_relation_ids().unwrap().len(), 2);
+        ego.close().unwrap();
+        assert!(copy.to_reachdb::<TypeId>(path.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn edges_to_missing_nodes_print_but_do_not_convert() {
+        let (_dir, db) = taxonomy_up_to_model();
+        let mut broken = db.subgraph(&["GPT"], 2, Direction::Outgoing, Some(&["IS-A"]), 10).unwrap();
+        broken.nodes.truncate(1);
+        assert!(broken.to_string().contains("\"GPT\" -[IS-A]-> \"#"));
+        let target = tempfile::tempdir().unwrap();
+        let path = target.path().join("broken");
+        assert!(broken.to_reachdb::<TypeId>(path.to_str().unwrap()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn existing_databases_are_not_targets() {
+        let (_dir, db) = taxonomy_up_to_model();
+        let up = db.subgraph(&["GPT"], 2, Direction::Outgoing, Some(&["IS-A"]), 10).unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let path = target.path().to_str().unwrap();
+        Reachdb::<TypeId>::open(path, None, None).unwrap().close().unwrap();
+        assert!(up.to_reachdb::<TypeId>(path).is_err());
+
+        // An empty directory is, and stays empty when the copy fails
+        let empty = tempfile::tempdir().unwrap();
+        let mut failing = up.clone();
+        failing.edges[0].relation = " ".to_string();
+        assert!(failing.to_reachdb::<TypeId>(empty.path().to_str().unwrap()).is_err());
+        assert_eq!(empty.path().read_dir().unwrap().count(), 0);
+        up.to_reachdb::<TypeId>(empty.path().to_str().unwrap()).unwrap().close().unwrap();
+    }
+}
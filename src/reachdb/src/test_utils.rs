@@ -0,0 +1,56 @@
+//! Helpers shared by the unit tests of this crate
+
+use crate::{BuiltinRelationType, Reachdb, UserDefinedRelationType};
+
+/// Relation types of the tests: IS-A, RELATES-TO and INFLUENCES
+pub type TypeId = BuiltinRelationType;
+
+/// Opens a fresh database inside a temporary directory.
+///
+/// The directory is removed when the returned guard is dropped, so keep it alive for the whole test.
+pub fn temp_db(node_mmap_size: Option<usize>, relation_mmap_size: Option<usize>) -> (tempfile::TempDir, Reachdb<TypeId>) {
+    let dir = tempfile::tempdir().expect("Failed to create temp dir");
+    let db = Reachdb::<TypeId>::open(
+        dir.path().to_str().unwrap(),
+        node_mmap_size,
+        relation_mmap_size
+    ).expect("Failed to open reachdb");
+    (dir, db)
+}
+
+/// Id of the node `name`, which must exist
+pub fn node_id<E: UserDefinedRelationType>(db: &Reachdb<E>, name: &str) -> u64 {
+    db.get_node_id(name).unwrap().unwrap()
+}
+
+/// Names of the nodes, sorted
+pub fn node_names<E: UserDefinedRelationType>(db: &Reachdb<E>, ids: Vec<u64>) -> Vec<String> {
+    let mut names: Vec<String> = ids.into_iter().map(|id| db.get_node_name(id).unwrap()).collect();
+    names.sort();
+    names
+}
+
+/// "BERT" and "GPT" are Transformers, which are Neural Networks, and "Attention" influences "Transformer"
+pub fn transformer_taxonomy() -> (tempfile::TempDir, Reachdb<TypeId>) {
+    let (dir, mut db) = temp_db(None, None);
+    db.add_edge("BERT", "Transformer", "IS-A").unwrap();
+    db.add_edge("GPT", "Transformer", "IS-A").unwrap();
+    db.add_edge("Transformer", "Neural Network", "IS-A").unwrap();
+    db.add_edge("Attention", "Transformer", "INFLUENCES").unwrap();
+    (dir, db)
+}
+
+/// Two routes from "Score Matching" to "Generative Model", and "GAN" hanging off the end
+///
+/// Returns the relations of the routes, the `INFLUENCES`/`IS-A` one first.
+pub fn two_routes_to_generative_model() -> (tempfile::TempDir, Reachdb<TypeId>, Vec<u64>) {
+    let (dir, mut db) = temp_db(None, None);
+    let relations = vec![
+        db.add_edge("Score Matching", "Diffusion", "INFLUENCES").unwrap().unwrap(),
+        db.add_edge("Diffusion", "Generative Model", "IS-A").unwrap().unwrap(),
+        db.add_edge("Score Matching", "Energy Model", "RELATES-TO").unwrap().unwrap(),
+        db.add_edge("Energy Model", "Generative Model", "IS-A").unwrap().unwrap(),
+    ];
+    db.add_edge("GAN", "Generative Model", "IS-A").unwrap();
+    (dir, db, relations)
+}
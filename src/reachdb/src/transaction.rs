@@ -0,0 +1,270 @@
+//! Atomic transactions on top of the write-ahead log
+//!
+//! Every change of a record or a sled key goes through the helpers in this file, which keep the
+//! new value in the log while a transaction is running. Reads that must see the changes of the
+//! running transaction go through the helpers here as well.
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use log::error;
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    records::{node::NodeRecord, out_of_bounds, relationship::RelationshipRecord, Record},
+    wal::{RecordFile, SledTree, WalEntry, WriteAheadLog},
+};
+
+/// Keys and values of a sled tree, in key order
+pub(crate) type SledEntries = Vec<(Vec<u8>, Vec<u8>)>;
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Runs `f` as one atomic transaction.
+    ///
+    /// Either all the changes made by `f` are durable once this returns `Ok`, or none of them are:
+    /// they are rolled back when `f` returns an error or panics, and on the next `open` when the
+    /// process dies before the commit. Nested calls join the outer transaction.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, ReachdbError>
+    where
+        F: FnOnce(&mut Self) -> Result<T, ReachdbError>,
+    {
+        if self.wal.is_some() {
+            return f(self);
+        }
+        self.check_writable()?;
+
+        let wal_path = &Self::get_db_path(&self.path)[5];
+        self.wal = Some(WriteAheadLog::begin(wal_path, serde_json::to_string(&self)?));
+
+        // A panic must not leave the log behind, later calls would join the dead transaction
+        match panic::catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(Ok(value)) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Ok(Err(e)) => {
+                self.rollback()?;
+                Err(e)
+            }
+            Err(payload) => {
+                if let Err(e) = self.rollback() {
+                    error!("Rollback after a panic failed: {}", e);
+                }
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    fn commit(&mut self) -> Result<(), ReachdbError> {
+        let wal = self.wal.take().expect("Transaction not running");
+
+        // The transaction is durable once the commit marker is synced, nothing was written before
+        let committed = serde_json::to_string(&self).map_err(ReachdbError::from).and_then(|metadata| wal.commit(metadata));
+        if let Err(e) = committed {
+            self.wal = Some(wal);
+            if let Err(e) = self.rollback() {
+                error!("Rollback after a failed commit failed: {}", e);
+            }
+            return Err(e);
+        }
+
+        for (file, id, bytes) in wal.records() {
+            self.write_record_bytes(file, id, bytes)?;
+        }
+        for (tree, key, value) in wal.keys() {
+            self.put_sled_value(tree, key, value.map(<[u8]>::to_vec))?;
+        }
+        self.flush()?;
+        self.save_metadata()?;
+        wal.finish()
+    }
+
+    fn rollback(&mut self) -> Result<(), ReachdbError> {
+        let wal = self.wal.take().expect("Transaction not running");
+        // Names read inside the transaction may have been cached
+        for (tree, key, _) in wal.keys() {
+            if tree == SledTree::Property {
+                self.property_cache.invalidate(bincode::deserialize::<u64>(key)?);
+            }
+        }
+        self.restore_metadata(wal.metadata())?;
+        // A commit that failed part way may have left a log, it must not be replayed
+        wal.finish()
+    }
+
+    /// Replays the log left by a transaction that committed, a log without a commit marker is dropped
+    pub(crate) fn recover(&mut self) -> Result<(), ReachdbError> {
+        let wal_path = &Self::get_db_path(&self.path)[5];
+        if !Path::new(wal_path).exists() {
+            return Ok(());
+        }
+        let entries = WriteAheadLog::read_entries(wal_path)?;
+        if entries.iter().any(|entry| matches!(entry, WalEntry::Commit { .. })) {
+            for entry in entries {
+                match entry {
+                    WalEntry::RecordRedo { file, id, bytes } => self.write_record_bytes(file, id, &bytes)?,
+                    WalEntry::SledRedo { tree, key, value } => self.put_sled_value(tree, &key, value)?,
+                    WalEntry::Commit { metadata } => self.restore_metadata(&metadata)?,
+                }
+            }
+            self.flush()?;
+            self.save_metadata()?;
+        }
+        std::fs::remove_file(wal_path)?;
+        Ok(())
+    }
+
+    /// Restores the counters and free lists saved in the log
+    fn restore_metadata(&mut self, metadata: &str) -> Result<(), ReachdbError> {
+        let mut saved: Self = serde_json::from_str(metadata)?;
+
+        // The files never shrink, keep the current mappings
+        saved.node_mmap_size = self.node_mmap_size;
+        saved.relation_mmap_size = self.relation_mmap_size;
+        saved.embedding_mmap_size = self.embedding_mmap_size;
+        saved.mmap = self.mmap.take();
+        saved.sled = self.sled.take();
+        saved.path = self.path.clone();
+        saved.lock = self.lock.take();
+        saved.read_only = self.read_only;
+        *self = saved;
+        Ok(())
+    }
+
+    /// Flushes the memory maps and the sled databases
+    pub(crate) fn flush(&self) -> Result<(), ReachdbError> {
+        if let Some(mmap) = self.mmap.as_ref() {
+            mmap.flush()?;
+        }
+        // Flushing a tree flushes its whole database
+        self.sled_tree(SledTree::NodeId).flush()?;
+        self.sled_tree(SledTree::Property).flush()?;
+        Ok(())
+    }
+
+    pub(crate) fn sled_tree(&self, tree: SledTree) -> &sled::Tree {
+        let sled = self.sled.as_ref().expect("Sled databases not opened");
+        match tree {
+            SledTree::NodeId => &sled.node_id,
+            SledTree::NodeAlias => &sled.node_alias,
+            SledTree::Property => &sled.property,
+            SledTree::PropertyKv => &sled.property_kv,
+            SledTree::NodeToken => &sled.node_token,
+            SledTree::EdgeProvenance => &sled.edge_provenance,
+        }
+    }
+
+    pub(crate) fn sled_insert(&mut self, tree: SledTree, key: &[u8], value: Vec<u8>) -> Result<(), ReachdbError> {
+        self.put_sled(tree, key, Some(value))
+    }
+
+    pub(crate) fn sled_remove(&mut self, tree: SledTree, key: &[u8]) -> Result<(), ReachdbError> {
+        self.put_sled(tree, key, None)
+    }
+
+    fn put_sled(&mut self, tree: SledTree, key: &[u8], value: Option<Vec<u8>>) -> Result<(), ReachdbError> {
+        match self.wal.as_mut() {
+            Some(wal) => {
+                wal.set_key(tree, key, value);
+                if tree == SledTree::Property {
+                    self.property_cache.invalidate(bincode::deserialize::<u64>(key)?);
+                }
+                Ok(())
+            }
+            None => self.put_sled_value(tree, key, value),
+        }
+    }
+
+    /// Value of a sled key, as changed by the running transaction
+    pub(crate) fn sled_get(&self, tree: SledTree, key: &[u8]) -> Result<Option<Vec<u8>>, ReachdbError> {
+        if let Some(value) = self.wal.as_ref().and_then(|wal| wal.key(tree, key)) {
+            return Ok(value.map(<[u8]>::to_vec));
+        }
+        Ok(self.sled_tree(tree).get(key)?.map(|value| value.to_vec()))
+    }
+
+    pub(crate) fn sled_contains_key(&self, tree: SledTree, key: &[u8]) -> Result<bool, ReachdbError> {
+        Ok(self.sled_get(tree, key)?.is_some())
+    }
+
+    /// Entries of a sled tree whose keys start with `prefix`, as changed by the running transaction
+    pub(crate) fn sled_scan_prefix(&self, tree: SledTree, prefix: &[u8]) -> Result<SledEntries, ReachdbError> {
+        let mut entries = BTreeMap::new();
+        for entry in self.sled_tree(tree).scan_prefix(prefix) {
+            let (key, value) = entry?;
+            entries.insert(key.to_vec(), value.to_vec());
+        }
+        if let Some(wal) = self.wal.as_ref() {
+            for (key, value) in wal.keys_with_prefix(tree, prefix) {
+                match value {
+                    Some(value) => entries.insert(key.to_vec(), value.to_vec()),
+                    None => entries.remove(key),
+                };
+            }
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    pub(crate) fn write_node(&mut self, node_id: u64, node: &NodeRecord) -> Result<(), ReachdbError> {
+        self.put_record(RecordFile::Node, node_id, node.to_bytes())
+    }
+
+    pub(crate) fn write_relation(&mut self, relation_id: u64, relation: &RelationshipRecord) -> Result<(), ReachdbError> {
+        self.put_record(RecordFile::Relation, relation_id, relation.to_bytes())
+    }
+
+    /// Writes the whole embedding slot of `node_id`, flag and values
+    pub(crate) fn write_embedding(&mut self, node_id: u64, slot: &[u8]) -> Result<(), ReachdbError> {
+        self.put_record(RecordFile::Embedding, node_id, slot.to_vec())
+    }
+
+    fn put_record(&mut self, file: RecordFile, id: u64, bytes: Vec<u8>) -> Result<(), ReachdbError> {
+        match self.wal.as_mut() {
+            Some(wal) => {
+                wal.set_record(file, id, bytes);
+                Ok(())
+            }
+            None => self.write_record_bytes(file, id, &bytes),
+        }
+    }
+
+    /// New bytes of a record changed by the running transaction
+    pub(crate) fn pending_record(&self, file: RecordFile, id: u64) -> Option<&[u8]> {
+        self.wal.as_ref()?.record(file, id)
+    }
+
+    fn write_record_bytes(&mut self, file: RecordFile, id: u64, bytes: &[u8]) -> Result<(), ReachdbError> {
+        // The logged bytes give the slot size of an embedding, the metadata may not be restored yet
+        match file {
+            RecordFile::Node => self.ensure_node_capacity(id)?,
+            RecordFile::Relation => self.ensure_relation_capacity(id)?,
+            RecordFile::Embedding => self.ensure_embedding_capacity(id, bytes.len())?,
+        }
+        let mmap = self.mmap.as_mut().expect("Mmap not initialized");
+        let mmap = match file {
+            RecordFile::Node => mmap.get_node_as_mut()?,
+            RecordFile::Relation => mmap.get_relation_as_mut()?,
+            RecordFile::Embedding => mmap.get_embedding_as_mut()?,
+        };
+        let len = mmap.len();
+        mmap.get_mut(file.range(id, bytes.len()))
+            .ok_or_else(|| out_of_bounds(id, len))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn put_sled_value(&self, tree: SledTree, key: &[u8], value: Option<Vec<u8>>) -> Result<(), ReachdbError> {
+        // Names can change through a removal or a rollback, so the cached one is dropped
+        if tree == SledTree::Property {
+            self.property_cache.invalidate(bincode::deserialize::<u64>(key)?);
+        }
+        let sled_tree = self.sled_tree(tree);
+        match value {
+            Some(value) => sled_tree.insert(key, value)?,
+            None => sled_tree.remove(key)?,
+        };
+        Ok(())
+    }
+}
@@ -1,6 +1,43 @@
 use super::errors::ReachdbError;
-use memmap2::{MmapMut, MmapOptions};
+use memmap2::{Mmap, MmapMut, MmapOptions};
 use std::fs::OpenOptions;
+use std::ops::Deref;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A memory-mapped record file, writable or mapped read-only
+#[derive(Debug)]
+pub enum MappedFile {
+    ReadWrite(MmapMut),
+    ReadOnly(Mmap),
+}
+
+impl MappedFile {
+    pub fn flush(&self) -> Result<(), ReachdbError> {
+        if let Self::ReadWrite(mmap) = self {
+            mmap.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The writable map, an error for a file mapped read-only
+    pub fn as_mut(&mut self) -> Result<&mut MmapMut, ReachdbError> {
+        match self {
+            Self::ReadWrite(mmap) => Ok(mmap),
+            Self::ReadOnly(_) => Err(ReachdbError::OtherError("Database opened read-only".to_string())),
+        }
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::ReadWrite(mmap) => mmap,
+            Self::ReadOnly(mmap) => mmap,
+        }
+    }
+}
 
 /// Creates and memory-maps a file at `file_path` with the specified `size`.
 ///
@@ -13,4 +50,31 @@ pub fn create_mmap(file_path: &str, size: usize) -> Result<MmapMut, ReachdbError
         .open(file_path)?;
     file.set_len(size as u64)?;
     Ok(unsafe { MmapOptions::new().map_mut(&file)? })
-}
\ No newline at end of file
+}
+
+/// Seconds since the UNIX epoch, 0 when the clock is set before it
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Maps the existing file at `file_path` read-only, without changing its size.
+///
+/// **Use-case:** Read the records of a database opened with `Reachdb::open_read_only`.
+pub fn open_mmap_read_only(file_path: &str) -> Result<MappedFile, ReachdbError> {
+    let file = OpenOptions::new().read(true).open(file_path)?;
+    Ok(MappedFile::ReadOnly(unsafe { MmapOptions::new().map(&file)? }))
+}
+
+/// Returns the size a memory map has to grow to in order to hold `required` bytes.
+///
+/// **Use-case:** The size is doubled until it fits, so repeated inserts only remap the files a logarithmic number of times.
+pub fn grown_mmap_size(current: usize, required: usize) -> usize {
+    let mut size = current.max(1);
+    while size < required {
+        size *= 2;
+    }
+    size
+}
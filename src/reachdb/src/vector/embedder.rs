@@ -0,0 +1,65 @@
+use crate::errors::ReachdbError;
+
+/// Turns text into embeddings, implemented for each embedding provider
+pub trait Embedder {
+    /// Length of the embeddings
+    fn dimension(&self) -> usize;
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, ReachdbError>;
+
+    /// Embeds several texts, providers with a batch endpoint should override it
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>, ReachdbError> {
+        texts.iter().map(|text| self.embed(text)).collect()
+    }
+}
+
+/// Deterministic embedder hashing the words and letter trigrams of a text, for offline use and tests
+///
+/// Texts sharing words or spellings get similar embeddings, but nothing about their meaning is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashingEmbedder {
+    dimension: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimension: usize) -> Self {
+        Self { dimension: dimension.max(1) }
+    }
+
+    /// Adds `weight` to the bucket of `feature`, with a sign from the hash so collisions cancel out
+    fn add_feature(&self, embedding: &mut [f32], feature: &str, weight: f32) {
+        let hash = fnv1a(feature.as_bytes());
+        let bucket = (hash % self.dimension as u64) as usize;
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        embedding[bucket] += sign * weight;
+    }
+}
+
+/// 64-bit FNV-1a, stable across platforms and releases unlike the std hasher
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+impl Embedder for HashingEmbedder {
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>, ReachdbError> {
+        let mut embedding = vec![0.0; self.dimension];
+        let text = text.to_lowercase();
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+            self.add_feature(&mut embedding, word, 1.0);
+            let padded: Vec<char> = format!("#{}#", word).chars().collect();
+            for trigram in padded.windows(3) {
+                self.add_feature(&mut embedding, &trigram.iter().collect::<String>(), 0.5);
+            }
+        }
+
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|x| *x /= norm);
+        }
+        Ok(embedding)
+    }
+}
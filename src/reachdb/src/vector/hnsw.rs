@@ -0,0 +1,251 @@
+//! Hierarchical navigable small world graph, for approximate nearest-neighbour search
+//!
+//! Each vector gets a random top layer. Searches descend greedily from the top layer to layer 0,
+//! where a beam of `ef` candidates is explored. Vectors are normalized on insert, so the cosine
+//! similarity is a dot product.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::errors::ReachdbError;
+use super::keep_most_similar;
+
+/// Settings of an `HnswIndex`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswConfig {
+    /// Neighbors kept per vector and layer, twice as many on layer 0
+    pub m: usize,
+    /// Beam width while inserting, higher builds a better graph more slowly
+    pub ef_construction: usize,
+    /// Beam width while searching, raised to `k` when smaller
+    pub ef_search: usize,
+    /// Seed of the layer draws, so an index built twice is the same
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        Self { m: 16, ef_construction: 100, ef_search: 50, seed: 42 }
+    }
+}
+
+/// Similarity of a candidate, ordered by similarity then by position
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// In-memory approximate nearest-neighbour index over node embeddings
+#[derive(Debug, Clone)]
+pub struct HnswIndex {
+    config: HnswConfig,
+    dimension: usize,
+    node_ids: Vec<u64>,
+    vectors: Vec<Vec<f32>>,
+    /// Neighbors of each vector, per layer from 0 up to its top layer
+    links: Vec<Vec<Vec<usize>>>,
+    entry_point: Option<usize>,
+    rng: StdRng,
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+impl HnswIndex {
+    /// Empty index for vectors of `dimension` floats, 0 takes the length of the first vector
+    pub fn new(dimension: usize, config: HnswConfig) -> Self {
+        Self {
+            config: HnswConfig { m: config.m.max(2), ..config },
+            dimension,
+            node_ids: Vec::new(),
+            vectors: Vec::new(),
+            links: Vec::new(),
+            entry_point: None,
+            rng: StdRng::seed_from_u64(config.seed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.node_ids.is_empty()
+    }
+
+    fn check_dimension(&self, vector: &[f32]) -> Result<(), ReachdbError> {
+        if self.dimension != 0 && vector.len() != self.dimension {
+            return Err(ReachdbError::OtherError(format!(
+                "Vector of {} dimensions, expected {}", vector.len(), self.dimension
+            )));
+        }
+        Ok(())
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 { 2 * self.config.m } else { self.config.m }
+    }
+
+    /// Adds the embedding of a node
+    pub fn insert(&mut self, node_id: u64, vector: &[f32]) -> Result<(), ReachdbError> {
+        self.check_dimension(vector)?;
+        self.dimension = vector.len();
+
+        // Layers thin out by a factor of m, like the levels of a skip list
+        let level_factor = 1.0 / (self.config.m as f64).ln();
+        let level = (-(1.0 - self.rng.r#gen::<f64>()).ln() * level_factor) as usize;
+
+        let position = self.node_ids.len();
+        self.node_ids.push(node_id);
+        self.vectors.push(normalized(vector));
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(position);
+            return Ok(());
+        };
+        let top_layer = self.links[entry_point].len() - 1;
+        let query = self.vectors[position].clone();
+
+        let mut entry_points = vec![entry_point];
+        for layer in (level + 1..=top_layer).rev() {
+            entry_points = vec![self.search_layer(&query, &entry_points, 1, layer)[0].1];
+        }
+        for layer in (0..=level.min(top_layer)).rev() {
+            let found = self.search_layer(&query, &entry_points, self.config.ef_construction, layer);
+            let neighbors = self.select_neighbors(&found, self.max_links(layer));
+            for &neighbor in &neighbors {
+                self.links[neighbor][layer].push(position);
+                self.prune(neighbor, layer);
+            }
+            self.links[position][layer] = neighbors;
+            entry_points = found.into_iter().map(|scored| scored.1).collect();
+        }
+
+        if level > top_layer {
+            self.entry_point = Some(position);
+        }
+        Ok(())
+    }
+
+    /// Keeps the best neighbors of `position` on `layer` when it has too many
+    fn prune(&mut self, position: usize, layer: usize) {
+        let max_links = self.max_links(layer);
+        if self.links[position][layer].len() <= max_links {
+            return;
+        }
+        let vector = &self.vectors[position];
+        let mut scored: Vec<Scored> = self.links[position][layer].iter()
+            .map(|&neighbor| Scored(dot(vector, &self.vectors[neighbor]), neighbor))
+            .collect();
+        scored.sort_unstable_by(|a, b| b.cmp(a));
+        self.links[position][layer] = self.select_neighbors(&scored, max_links);
+    }
+
+    /// Picks up to `max_links` of the candidates, most similar first
+    ///
+    /// A candidate closer to an already picked neighbor than to the base vector is skipped at
+    /// first, so links also reach other clusters. Skipped candidates fill the remaining places.
+    fn select_neighbors(&self, candidates: &[Scored], max_links: usize) -> Vec<usize> {
+        let mut selected: Vec<usize> = Vec::with_capacity(max_links);
+        let mut skipped = Vec::new();
+        for &Scored(similarity, candidate) in candidates {
+            if selected.len() == max_links {
+                break;
+            }
+            let dominated = selected.iter()
+                .any(|&picked| dot(&self.vectors[candidate], &self.vectors[picked]) > similarity);
+            if dominated {
+                skipped.push(candidate);
+            } else {
+                selected.push(candidate);
+            }
+        }
+        let missing = max_links - selected.len();
+        selected.extend(skipped.into_iter().take(missing));
+        selected
+    }
+
+    /// Beam search of `layer`, returns up to `ef` positions, most similar first
+    fn search_layer(&self, query: &[f32], entry_points: &[usize], ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        // Min-heap of the best results, its top is the worst one kept
+        let mut results: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+        for &position in entry_points {
+            let scored = Scored(dot(query, &self.vectors[position]), position);
+            candidates.push(scored);
+            results.push(Reverse(scored));
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().expect("entry points are kept").0;
+            if candidate.0 < worst.0 && results.len() >= ef {
+                break;
+            }
+            let Some(neighbors) = self.links[candidate.1].get(layer) else {
+                continue;
+            };
+            for &neighbor in neighbors {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = Scored(dot(query, &self.vectors[neighbor]), neighbor);
+                let worst = results.peek().expect("entry points are kept").0;
+                if results.len() < ef || scored.0 > worst.0 {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut found: Vec<Scored> = results.into_iter().map(|Reverse(scored)| scored).collect();
+        found.sort_unstable_by(|a, b| b.cmp(a));
+        found
+    }
+
+    /// The `k` node ids whose embeddings are approximately the most similar to `query`
+    pub fn search(&self, query: &[f32], k: usize) -> Result<Vec<(u64, f32)>, ReachdbError> {
+        self.check_dimension(query)?;
+        let Some(entry_point) = self.entry_point else {
+            return Ok(Vec::new());
+        };
+        let query = normalized(query);
+        let mut entry_points = vec![entry_point];
+        for layer in (1..self.links[entry_point].len()).rev() {
+            entry_points = vec![self.search_layer(&query, &entry_points, 1, layer)[0].1];
+        }
+        let found = self.search_layer(&query, &entry_points, self.config.ef_search.max(k), 0);
+        let mut neighbors: Vec<(u64, f32)> = found.into_iter()
+            .map(|Scored(similarity, position)| (self.node_ids[position], similarity))
+            .collect();
+        keep_most_similar(&mut neighbors, k);
+        Ok(neighbors)
+    }
+}
@@ -0,0 +1,314 @@
+//! Node embeddings and nearest-neighbour search
+//!
+//! Every node has a slot in `reachdb.embedding.db`, after the header like the records:
+//! a little-endian `u32` flag set to 1 when the node has an embedding, then `embedding_dim`
+//! little-endian `f32`. Slots are written through the write-ahead log like the records.
+
+mod embedder;
+mod hnsw;
+
+pub use embedder::{Embedder, HashingEmbedder};
+pub use hnsw::{HnswConfig, HnswIndex};
+
+use std::cmp::Ordering;
+
+use crate::{data_base::{Reachdb, UserDefinedRelationType}, errors::ReachdbError, records::HEADER_SIZE, wal::RecordFile};
+
+/// Identifies the embedding file in its header
+pub(crate) const EMBEDDING_MAGIC: [u8; 8] = *b"REACHEMB";
+
+/// Bytes of the slot holding an embedding of `dim` floats
+pub(crate) fn embedding_slot_size(dim: usize) -> usize {
+    4 + 4 * dim
+}
+
+/// Offset of the slot of `node_id` in the embedding file
+pub(crate) fn embedding_offset(node_id: u64, slot_size: usize) -> usize {
+    HEADER_SIZE + node_id as usize * slot_size
+}
+
+/// Cosine similarity, 0 when one of the vectors is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a.sqrt() * norm_b.sqrt())
+}
+
+/// Sorts `(node id, similarity)` pairs most similar first and keeps `k` of them
+pub(crate) fn keep_most_similar(neighbors: &mut Vec<(u64, f32)>, k: usize) {
+    neighbors.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+    neighbors.truncate(k);
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Length of the node embeddings, `None` until it is set
+    pub fn embedding_dimension(&self) -> Option<usize> {
+        (self.embedding_dim > 0).then_some(self.embedding_dim)
+    }
+
+    /// Sets the length of the node embeddings
+    ///
+    /// It can only change while no node has an embedding.
+    pub fn set_embedding_dimension(&mut self, dim: usize) -> Result<(), ReachdbError> {
+        if dim == 0 {
+            return Err(ReachdbError::OtherError("Embeddings need at least one dimension".to_string()));
+        }
+        if self.embedding_dim == dim {
+            return Ok(());
+        }
+        if !self.embedded_node_ids()?.is_empty() {
+            return Err(ReachdbError::OtherError(format!(
+                "Nodes already have embeddings of {} dimensions, remove them first", self.embedding_dim
+            )));
+        }
+        self.transaction(|db| {
+            db.embedding_dim = dim;
+            Ok(())
+        })
+    }
+
+    /// Stores the embedding of a node, replacing the previous one
+    ///
+    /// The first embedding sets the dimension if it is not set yet.
+    pub fn set_embedding(&mut self, node_id: u64, embedding: &[f32]) -> Result<(), ReachdbError> {
+        if node_id >= self.node_count || self.get_node(node_id)?.is_deleted() {
+            return Err(ReachdbError::OtherError(format!("Node {} not found", node_id)));
+        }
+        self.transaction(|db| {
+            if db.embedding_dim == 0 {
+                db.set_embedding_dimension(embedding.len())?;
+            }
+            if embedding.len() != db.embedding_dim {
+                return Err(ReachdbError::OtherError(format!(
+                    "Embedding of {} dimensions, expected {}", embedding.len(), db.embedding_dim
+                )));
+            }
+            let mut slot = Vec::with_capacity(embedding_slot_size(embedding.len()));
+            slot.extend_from_slice(&1u32.to_le_bytes());
+            for value in embedding {
+                slot.extend_from_slice(&value.to_le_bytes());
+            }
+            db.write_embedding(node_id, &slot)
+        })
+    }
+
+    /// Embedding of a node, `None` if it has none
+    pub fn get_embedding(&self, node_id: u64) -> Result<Option<Vec<f32>>, ReachdbError> {
+        if self.embedding_dim == 0 {
+            return Ok(None);
+        }
+        let slot_size = embedding_slot_size(self.embedding_dim);
+        let slot = match self.pending_record(RecordFile::Embedding, node_id) {
+            Some(slot) => slot,
+            None => {
+                let Ok(embedding_mmap) = self.mmap.as_ref().expect("Mmap not initialized").get_embedding_as_ref() else {
+                    return Ok(None);
+                };
+                let offset = embedding_offset(node_id, slot_size);
+                let Some(slot) = embedding_mmap.get(offset..offset + slot_size) else {
+                    return Ok(None);
+                };
+                slot
+            }
+        };
+        if slot.len() != slot_size || slot[..4] != 1u32.to_le_bytes() {
+            return Ok(None);
+        }
+        Ok(Some(slot[4..].chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect()))
+    }
+
+    /// Removes the embedding of a node, returns `false` if it had none
+    pub fn remove_embedding(&mut self, node_id: u64) -> Result<bool, ReachdbError> {
+        if self.get_embedding(node_id)?.is_none() {
+            return Ok(false);
+        }
+        self.transaction(|db| db.clear_embedding(node_id))?;
+        Ok(true)
+    }
+
+    /// Zeroes the slot of a node, so slots of another dimension never read as set
+    pub(crate) fn clear_embedding(&mut self, node_id: u64) -> Result<(), ReachdbError> {
+        if self.get_embedding(node_id)?.is_some() {
+            self.write_embedding(node_id, &vec![0; embedding_slot_size(self.embedding_dim)])?;
+        }
+        Ok(())
+    }
+
+    /// Ids of the nodes that have an embedding
+    pub fn embedded_node_ids(&self) -> Result<Vec<u64>, ReachdbError> {
+        let mut ids = Vec::new();
+        for node_id in self.get_all_node_ids()? {
+            if self.get_embedding(node_id)?.is_some() {
+                ids.push(node_id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Embeds the names of the nodes, all of them or only those without an embedding
+    ///
+    /// Returns the number of nodes embedded. The names go to the embedder in one batch.
+    pub fn embed_nodes<M: Embedder + ?Sized>(&mut self, embedder: &M, overwrite: bool) -> Result<usize, ReachdbError> {
+        let mut node_ids = Vec::new();
+        let mut names = Vec::new();
+        for node_id in self.get_all_node_ids()? {
+            if overwrite || self.get_embedding(node_id)?.is_none() {
+                names.push(self.get_node_name(node_id)?);
+                node_ids.push(node_id);
+            }
+        }
+        if node_ids.is_empty() {
+            return Ok(0);
+        }
+        let texts: Vec<&str> = names.iter().map(String::as_str).collect();
+        let embeddings = embedder.embed_batch(&texts)?;
+        if embeddings.len() != node_ids.len() {
+            return Err(ReachdbError::OtherError(format!(
+                "The embedder returned {} embeddings for {} names", embeddings.len(), node_ids.len()
+            )));
+        }
+        self.transaction(|db| {
+            for (node_id, embedding) in node_ids.iter().zip(&embeddings) {
+                db.set_embedding(*node_id, embedding)?;
+            }
+            Ok(node_ids.len())
+        })
+    }
+
+    /// The `k` nodes whose embeddings are the most similar to `query`, by cosine similarity
+    ///
+    /// Exact search over every embedding, see `hnsw_index` for an approximate one.
+    pub fn nearest_nodes(&self, query: &[f32], k: usize) -> Result<Vec<(u64, f32)>, ReachdbError> {
+        if self.embedding_dim > 0 && query.len() != self.embedding_dim {
+            return Err(ReachdbError::OtherError(format!(
+                "Query of {} dimensions, expected {}", query.len(), self.embedding_dim
+            )));
+        }
+        let mut neighbors = Vec::new();
+        for node_id in self.get_all_node_ids()? {
+            if let Some(embedding) = self.get_embedding(node_id)? {
+                neighbors.push((node_id, cosine_similarity(query, &embedding)));
+            }
+        }
+        keep_most_similar(&mut neighbors, k);
+        Ok(neighbors)
+    }
+
+    /// The `k` nodes the most similar to the node `node_id`, itself left out, e.g. to find duplicates
+    pub fn similar_nodes(&self, node_id: u64, k: usize) -> Result<Vec<(u64, f32)>, ReachdbError> {
+        let embedding = self.get_embedding(node_id)?
+            .ok_or_else(|| ReachdbError::OtherError(format!("Node {} has no embedding", node_id)))?;
+        let mut neighbors = self.nearest_nodes(&embedding, k + 1)?;
+        neighbors.retain(|(id, _)| *id != node_id);
+        neighbors.truncate(k);
+        Ok(neighbors)
+    }
+
+    /// Builds an approximate nearest-neighbour index over the current embeddings
+    ///
+    /// The index lives in memory and does not follow later changes of the database.
+    pub fn hnsw_index(&self, config: HnswConfig) -> Result<HnswIndex, ReachdbError> {
+        let mut index = HnswIndex::new(self.embedding_dim, config);
+        for node_id in self.get_all_node_ids()? {
+            if let Some(embedding) = self.get_embedding(node_id)? {
+                index.insert(node_id, &embedding)?;
+            }
+        }
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::{node_id, temp_db, TypeId};
+    use crate::Reachdb;
+    use super::{Embedder, HashingEmbedder, HnswConfig};
+
+    /// Embeds "Diffusion Model", its plural and a few more nodes, and returns the embedder
+    fn embedded_diffusion_models() -> (tempfile::TempDir, Reachdb<TypeId>, HashingEmbedder) {
+        let (dir, mut db) = temp_db(None, None);
+        db.add_edge("Diffusion Model", "Generative Model", "IS-A").unwrap();
+        db.add_edge("Diffusion Models", "Generative Model", "IS-A").unwrap();
+        db.add_edge("Transformer", "Neural Network", "IS-A").unwrap();
+        let embedder = HashingEmbedder::new(64);
+        assert_eq!(db.embed_nodes(&embedder, false).unwrap(), 5);
+        (dir, db, embedder)
+    }
+
+    #[test]
+    fn embeddings_keep_one_dimension() {
+        let (_dir, mut db, _) = embedded_diffusion_models();
+        let diffusion = node_id(&db, "Diffusion Model");
+        assert_eq!(db.embedding_dimension(), Some(64));
+        assert!(db.set_embedding_dimension(32).is_err());
+        assert!(db.set_embedding(diffusion, &[1.0; 3]).is_err());
+    }
+
+    #[test]
+    fn nearest_nodes_share_the_most_words() {
+        let (_dir, db, embedder) = embedded_diffusion_models();
+        // The plural is the closest node to the singular
+        let diffusion = node_id(&db, "Diffusion Model");
+        let plural = node_id(&db, "Diffusion Models");
+        assert_eq!(db.similar_nodes(diffusion, 1).unwrap()[0].0, plural);
+        let query = embedder.embed("transformers").unwrap();
+        assert_eq!(db.nearest_nodes(&query, 1).unwrap()[0].0, node_id(&db, "Transformer"));
+    }
+
+    #[test]
+    fn failed_transactions_restore_embeddings() {
+        let (_dir, mut db, _) = embedded_diffusion_models();
+        let diffusion = node_id(&db, "Diffusion Model");
+        let before = db.get_embedding(diffusion).unwrap();
+        let failed: Result<(), _> = db.transaction(|db| {
+            db.set_embedding(diffusion, &[0.5; 64])?;
+            Err(crate::ReachdbError::OtherError("abort".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(db.get_embedding(diffusion).unwrap(), before);
+    }
+
+    #[test]
+    fn embeddings_persist_without_removed_nodes() {
+        let (dir, mut db, _) = embedded_diffusion_models();
+        let diffusion = node_id(&db, "Diffusion Model");
+        let plural = node_id(&db, "Diffusion Models");
+        let before = db.get_embedding(diffusion).unwrap();
+        db.remove_node("Diffusion Models").unwrap();
+        assert_eq!(db.get_embedding(plural).unwrap(), None);
+        db.close().unwrap();
+
+        let db = Reachdb::<TypeId>::open(dir.path().to_str().unwrap(), None, None).unwrap();
+        assert_eq!(db.get_embedding(diffusion).unwrap(), before);
+        assert_eq!(db.embedded_node_ids().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn hnsw_finds_the_exact_neighbors() {
+        let (_dir, mut db) = temp_db(None, None);
+        let embedder = HashingEmbedder::new(32);
+        for i in 0..200 {
+            db.add_edge(&format!("concept {}", i), &format!("topic {}", i % 7), "IS-A").unwrap();
+        }
+        db.embed_nodes(&embedder, false).unwrap();
+        let index = db.hnsw_index(HnswConfig::default()).unwrap();
+        assert_eq!(index.len(), 207);
+
+        let mut found = 0;
+        for name in ["concept 17", "topic 3", "concept 150", "concept 99"] {
+            let query = embedder.embed(name).unwrap();
+            let exact: Vec<u64> = db.nearest_nodes(&query, 5).unwrap().into_iter().map(|(id, _)| id).collect();
+            let approximate: Vec<u64> = index.search(&query, 5).unwrap().into_iter().map(|(id, _)| id).collect();
+            assert_eq!(approximate[0], exact[0]);
+            found += approximate.iter().filter(|id| exact.contains(id)).count();
+        }
+        assert!(found >= 18, "recall too low: {}/20", found);
+    }
+}
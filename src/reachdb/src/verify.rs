@@ -0,0 +1,484 @@
+//! Integrity checks of the record files against the sled mappings and the metadata
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+
+use crate::{
+    data_base::{Reachdb, UserDefinedRelationType},
+    errors::ReachdbError,
+    records::{node::NodeRecord, relationship::RelationshipRecord, Record, HEADER_SIZE, NULL_OFFSET},
+    wal::SledTree,
+};
+
+/// A single inconsistency found by `Reachdb::verify`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// Non empty records are stored past the count in the metadata
+    RecordsPastCount { file: &'static str, count: u64, last_id: u64 },
+    /// The count in the metadata runs past the last stored record, as after a truncated file
+    CountPastRecords { file: &'static str, count: u64, stored: u64 },
+    /// Property ids in use are not below `property_count`
+    PropertyCountBehind { count: u64, max_id: u64 },
+    /// The record in slot `node_id` carries another id
+    NodeIdMismatch { node_id: u64, stored_id: u64 },
+    /// The relation points at a node that does not exist or is removed
+    DanglingRelation { relation_id: u64, node_id: u64 },
+    /// Walking the chain of the node hit a bad link at `relation_id`
+    BrokenChain { node_id: u64, relation_id: u64 },
+    /// The chain of the node does not hold exactly the relations touching it
+    ChainMismatch { node_id: u64, in_chain: usize, expected: usize },
+    /// The name of the node is missing from `reachdb.property`
+    MissingName { node_id: u64 },
+    /// `reachdb.nodeid` does not map the name of the node back to it
+    UnmappedName { node_id: u64, name: String },
+    /// `reachdb.nodeid` maps a name to a node that is removed or named differently
+    StaleName { name: String, node_id: u64 },
+    /// `reachdb.property` holds a name under a property id no live node carries
+    OrphanName { property_id: u64, name: String },
+    /// A free list entry is out of range, duplicated or not a removed record
+    BadFreeSlot { file: &'static str, id: u64 },
+    /// A removed record missing from its free list
+    LeakedSlot { file: &'static str, id: u64 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RecordsPastCount { file, count, last_id } => write!(f, "{} records up to id {} are stored past the count {}", file, last_id, count),
+            Self::CountPastRecords { file, count, stored } => write!(f, "{} count {} runs past the {} stored records", file, count, stored),
+            Self::PropertyCountBehind { count, max_id } => write!(f, "property id {} is in use but the property count is {}", max_id, count),
+            Self::NodeIdMismatch { node_id, stored_id } => write!(f, "node slot {} holds id {}", node_id, stored_id),
+            Self::DanglingRelation { relation_id, node_id } => write!(f, "relation {} points at missing node {}", relation_id, node_id),
+            Self::BrokenChain { node_id, relation_id } => write!(f, "chain of node {} is broken at relation {}", node_id, relation_id),
+            Self::ChainMismatch { node_id, in_chain, expected } => write!(f, "chain of node {} holds {} relations, expected {}", node_id, in_chain, expected),
+            Self::MissingName { node_id } => write!(f, "node {} has no name", node_id),
+            Self::UnmappedName { node_id, name } => write!(f, "name \"{}\" of node {} is not mapped to it", name, node_id),
+            Self::StaleName { name, node_id } => write!(f, "name \"{}\" is mapped to node {} which does not carry it", name, node_id),
+            Self::OrphanName { property_id, name } => write!(f, "name \"{}\" of property {} belongs to no node", name, property_id),
+            Self::BadFreeSlot { file, id } => write!(f, "{} free list holds invalid slot {}", file, id),
+            Self::LeakedSlot { file, id } => write!(f, "removed {} slot {} is not in the free list", file, id),
+        }
+    }
+}
+
+/// Outcome of `Reachdb::verify` and `Reachdb::repair`
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub nodes_checked: u64,
+    pub relations_checked: u64,
+    pub issues: Vec<Issue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Checked {} nodes and {} relations", self.nodes_checked, self.relations_checked)?;
+        if self.is_ok() {
+            return write!(f, "No problems found");
+        }
+        writeln!(f, "{} problems found:", self.issues.len())?;
+        for issue in &self.issues {
+            writeln!(f, "  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// State gathered by one pass over the records
+#[derive(Default)]
+struct Scan {
+    report: VerifyReport,
+    /// Names of the live nodes, `None` when missing
+    names: BTreeMap<u64, Option<String>>,
+    /// Live relations touching each live node, by ascending id
+    incident: BTreeMap<u64, Vec<u64>>,
+    dangling: BTreeSet<u64>,
+    mismatched_ids: Vec<u64>,
+    deleted_nodes: Vec<u64>,
+    deleted_relations: Vec<u64>,
+    chains_broken: bool,
+}
+
+impl<E: UserDefinedRelationType> Reachdb<E> {
+    /// Checks the records, their chains, the sled mappings and the metadata counts
+    pub fn verify(&self) -> Result<VerifyReport, ReachdbError> {
+        Ok(self.scan()?.report)
+    }
+
+    /// Verifies the database and repairs what it can, returning the problems found before the repair.
+    ///
+    /// Relations pointing at missing nodes are removed and every chain is rebuilt from the relation
+    /// table. Nodes whose name is lost stay as they are.
+    pub fn repair(&mut self) -> Result<VerifyReport, ReachdbError> {
+        let found = self.verify()?;
+        if found.is_ok() {
+            return Ok(found);
+        }
+        self.transaction(|db| {
+            db.repair_counts()?;
+            let scan = db.scan()?;
+
+            for &node_id in &scan.mismatched_ids {
+                let mut node = db.get_node(node_id)?;
+                node.id = node_id;
+                db.write_node(node_id, &node)?;
+            }
+            for &relation_id in &scan.dangling {
+                let relation = db.get_relation(relation_id)?;
+                db.clear_properties(relation.first_property_id)?;
+                db.clear_edge_provenance(relation_id)?;
+                db.write_relation(relation_id, &RelationshipRecord::tombstone())?;
+            }
+            if scan.chains_broken || !scan.dangling.is_empty() {
+                for (node_id, relations) in &scan.incident {
+                    db.rebuild_chain(*node_id, relations)?;
+                }
+            }
+            for issue in &scan.report.issues {
+                match issue {
+                    Issue::StaleName { name, .. } => db.sled_remove(SledTree::NodeId, name.as_bytes())?,
+                    Issue::UnmappedName { node_id, name } => {
+                        db.sled_insert(SledTree::NodeId, name.as_bytes(), bincode::serialize(node_id)?)?
+                    }
+                    Issue::OrphanName { property_id, .. } => db.sled_remove(SledTree::Property, &bincode::serialize(property_id)?)?,
+                    _ => {}
+                }
+            }
+            db.rebuild_search_index()?;
+
+            // Free lists are rebuilt from the removed records, the lowest slot is reused first
+            let mut free_relations = scan.deleted_relations.clone();
+            free_relations.extend(scan.dangling.iter().copied());
+            free_relations.sort_unstable_by(|a, b| b.cmp(a));
+            db.free_relationship_ids = free_relations;
+            let mut free_nodes = scan.deleted_nodes.clone();
+            free_nodes.sort_unstable_by(|a, b| b.cmp(a));
+            db.free_node_ids = free_nodes;
+            Ok(())
+        })?;
+        Ok(found)
+    }
+
+    /// Raises the counts over records stored past them, empty slots in between become removed records.
+    /// Counts running past the stored records are lowered to them.
+    fn repair_counts(&mut self) -> Result<(), ReachdbError> {
+        self.node_count = self.stored_nodes();
+        self.relationship_count = self.stored_relations();
+        if let Some(last_id) = self.last_stored_node()? {
+            for node_id in self.node_count..=last_id {
+                if self.slot_is_empty(&self.mmap.as_ref().expect("Mmap not initialized").node_mmap, NodeRecord::id2offset(node_id), NodeRecord::record_size()) {
+                    self.write_node(node_id, &NodeRecord::tombstone())?;
+                }
+            }
+            self.node_count = last_id + 1;
+        }
+        if let Some(last_id) = self.last_stored_relation()? {
+            for relation_id in self.relationship_count..=last_id {
+                if self.slot_is_empty(&self.mmap.as_ref().expect("Mmap not initialized").relation_mmap, RelationshipRecord::id2offset(relation_id), RelationshipRecord::record_size()) {
+                    self.write_relation(relation_id, &RelationshipRecord::tombstone())?;
+                }
+            }
+            self.relationship_count = last_id + 1;
+        }
+        if let Some(max_id) = self.max_property_id()? {
+            self.property_count = self.property_count.max(max_id + 1);
+        }
+        Ok(())
+    }
+
+    /// Links the relations of a node in id order, the newest one becomes the head
+    fn rebuild_chain(&mut self, node_id: u64, relations: &[u64]) -> Result<(), ReachdbError> {
+        for (i, &relation_id) in relations.iter().enumerate() {
+            let mut relation = self.get_relation(relation_id)?;
+            let prev_id = if i == 0 { NULL_OFFSET } else { relations[i - 1] };
+            let next_id = relations.get(i + 1).copied().unwrap_or(NULL_OFFSET);
+            relation.set_prev_id_for(node_id, prev_id);
+            relation.set_next_id_for(node_id, next_id);
+            self.write_relation(relation_id, &relation)?;
+        }
+        let mut node = self.get_node(node_id)?;
+        node.update(Some(relations.last().copied().unwrap_or(NULL_OFFSET)), None);
+        self.write_node(node_id, &node)
+    }
+
+    fn scan(&self) -> Result<Scan, ReachdbError> {
+        let mut scan = Scan::default();
+        let issues = &mut scan.report.issues;
+
+        // Metadata counts against the file contents
+        if let Some(last_id) = self.last_stored_node()? {
+            issues.push(Issue::RecordsPastCount { file: "node", count: self.node_count, last_id });
+        }
+        if let Some(last_id) = self.last_stored_relation()? {
+            issues.push(Issue::RecordsPastCount { file: "relation", count: self.relationship_count, last_id });
+        }
+        // Opening pads the files to their size in the metadata, so lost records read as empty slots
+        let (node_count, relationship_count) = (self.stored_nodes(), self.stored_relations());
+        if node_count < self.node_count {
+            issues.push(Issue::CountPastRecords { file: "node", count: self.node_count, stored: node_count });
+        }
+        if relationship_count < self.relationship_count {
+            issues.push(Issue::CountPastRecords { file: "relation", count: self.relationship_count, stored: relationship_count });
+        }
+        if let Some(max_id) = self.max_property_id()?
+            && max_id >= self.property_count
+        {
+            issues.push(Issue::PropertyCountBehind { count: self.property_count, max_id });
+        }
+
+        // Nodes and their names
+        let mut property_ids = HashSet::new();
+        for node_id in 0..node_count {
+            let node = self.get_node(node_id)?;
+            if node.is_deleted() {
+                scan.deleted_nodes.push(node_id);
+                continue;
+            }
+            if node.id != node_id {
+                issues.push(Issue::NodeIdMismatch { node_id, stored_id: node.id });
+                scan.mismatched_ids.push(node_id);
+            }
+            property_ids.insert(node.first_property_id);
+            let name = match self.sled_get(SledTree::Property, &bincode::serialize(&node.first_property_id)?)? {
+                Some(name) => Some(bincode::deserialize::<String>(&name)?),
+                None => {
+                    issues.push(Issue::MissingName { node_id });
+                    None
+                }
+            };
+            scan.names.insert(node_id, name);
+            scan.incident.insert(node_id, Vec::new());
+        }
+        scan.report.nodes_checked = node_count;
+
+        // Relations and their endpoints
+        for relation_id in 0..relationship_count {
+            let relation = self.get_relation(relation_id)?;
+            if relation.is_deleted() {
+                scan.deleted_relations.push(relation_id);
+                continue;
+            }
+            for node_id in [relation.source_id, relation.target_id] {
+                if !scan.incident.contains_key(&node_id) {
+                    issues.push(Issue::DanglingRelation { relation_id, node_id });
+                    scan.dangling.insert(relation_id);
+                }
+            }
+            if scan.dangling.contains(&relation_id) {
+                continue;
+            }
+            scan.incident.get_mut(&relation.source_id).unwrap().push(relation_id);
+            if relation.target_id != relation.source_id {
+                scan.incident.get_mut(&relation.target_id).unwrap().push(relation_id);
+            }
+        }
+        scan.report.relations_checked = relationship_count;
+
+        // Chains, walked from the head (newest) to the oldest relation
+        for (&node_id, relations) in &scan.incident {
+            let node = self.get_node(node_id)?;
+            let mut in_chain = HashSet::new();
+            let mut expected_next = NULL_OFFSET;
+            let mut current = node.first_relationship_id;
+            while current != NULL_OFFSET {
+                let valid = current < relationship_count && !in_chain.contains(&current) && {
+                    let relation = self.get_relation(current)?;
+                    let touches = relation.source_id == node_id || relation.target_id == node_id;
+                    touches && !relation.is_deleted() && relation.next_id_for(node_id) == expected_next
+                };
+                if !valid {
+                    issues.push(Issue::BrokenChain { node_id, relation_id: current });
+                    scan.chains_broken = true;
+                    break;
+                }
+                in_chain.insert(current);
+                expected_next = current;
+                current = self.get_relation(current)?.prev_id_for(node_id);
+            }
+            if in_chain.len() != relations.len() || relations.iter().any(|id| !in_chain.contains(id)) {
+                issues.push(Issue::ChainMismatch { node_id, in_chain: in_chain.len(), expected: relations.len() });
+                scan.chains_broken = true;
+            }
+        }
+
+        // Name mappings in both directions
+        let mut mapped_ids = HashSet::new();
+        for (name, id) in self.sled_scan_prefix(SledTree::NodeId, &[])? {
+            let name = String::from_utf8(name)?;
+            let node_id = bincode::deserialize::<u64>(&id)?;
+            if scan.names.get(&node_id) == Some(&Some(name.clone())) {
+                mapped_ids.insert(node_id);
+            } else {
+                issues.push(Issue::StaleName { name, node_id });
+            }
+        }
+        for (&node_id, name) in &scan.names {
+            if let Some(name) = name
+                && !mapped_ids.contains(&node_id)
+            {
+                issues.push(Issue::UnmappedName { node_id, name: name.clone() });
+            }
+        }
+        for (key, name) in self.sled_scan_prefix(SledTree::Property, &[])? {
+            let property_id = bincode::deserialize::<u64>(&key)?;
+            if !property_ids.contains(&property_id) {
+                issues.push(Issue::OrphanName { property_id, name: bincode::deserialize::<String>(&name)? });
+            }
+        }
+
+        // Free lists
+        Self::check_free_list(issues, "node", &self.free_node_ids, &scan.deleted_nodes);
+        Self::check_free_list(issues, "relation", &self.free_relationship_ids, &scan.deleted_relations);
+
+        Ok(scan)
+    }
+
+    fn check_free_list(issues: &mut Vec<Issue>, file: &'static str, free_ids: &[u64], deleted: &[u64]) {
+        let deleted: HashSet<u64> = deleted.iter().copied().collect();
+        let mut seen = HashSet::new();
+        for &id in free_ids {
+            if !deleted.contains(&id) || !seen.insert(id) {
+                issues.push(Issue::BadFreeSlot { file, id });
+            }
+        }
+        for &id in &deleted {
+            if !seen.contains(&id) {
+                issues.push(Issue::LeakedSlot { file, id });
+            }
+        }
+    }
+
+    fn slot_is_empty(&self, mmap: &[u8], offset: usize, size: usize) -> bool {
+        mmap.get(offset..offset + size).is_none_or(|bytes| bytes.iter().all(|b| *b == 0))
+    }
+
+    /// Number of node slots below `node_count` up to the last non empty one
+    fn stored_nodes(&self) -> u64 {
+        let mmap = &self.mmap.as_ref().expect("Mmap not initialized").node_mmap;
+        (0..self.node_count)
+            .rev()
+            .find(|id| !self.slot_is_empty(mmap, NodeRecord::id2offset(*id), NodeRecord::record_size()))
+            .map_or(0, |id| id + 1)
+    }
+
+    /// Number of relation slots below `relationship_count` up to the last non empty one
+    fn stored_relations(&self) -> u64 {
+        let mmap = &self.mmap.as_ref().expect("Mmap not initialized").relation_mmap;
+        (0..self.relationship_count)
+            .rev()
+            .find(|id| !self.slot_is_empty(mmap, RelationshipRecord::id2offset(*id), RelationshipRecord::record_size()))
+            .map_or(0, |id| id + 1)
+    }
+
+    /// Id of the last non empty node slot at or past `node_count`
+    fn last_stored_node(&self) -> Result<Option<u64>, ReachdbError> {
+        let mmap = &self.mmap.as_ref().expect("Mmap not initialized").node_mmap;
+        let slots = (mmap.len().saturating_sub(HEADER_SIZE) / NodeRecord::record_size()) as u64;
+        Ok((self.node_count..slots)
+            .rev()
+            .find(|id| !self.slot_is_empty(mmap, NodeRecord::id2offset(*id), NodeRecord::record_size())))
+    }
+
+    /// Id of the last non empty relation slot at or past `relationship_count`
+    fn last_stored_relation(&self) -> Result<Option<u64>, ReachdbError> {
+        let mmap = &self.mmap.as_ref().expect("Mmap not initialized").relation_mmap;
+        let slots = (mmap.len().saturating_sub(HEADER_SIZE) / RelationshipRecord::record_size()) as u64;
+        Ok((self.relationship_count..slots)
+            .rev()
+            .find(|id| !self.slot_is_empty(mmap, RelationshipRecord::id2offset(*id), RelationshipRecord::record_size())))
+    }
+
+    /// Largest property id used by a name or a relation
+    fn max_property_id(&self) -> Result<Option<u64>, ReachdbError> {
+        // Keys are little-endian, so the last key is not the largest id
+        let mut max_id = None;
+        for (key, _) in self.sled_scan_prefix(SledTree::Property, &[])? {
+            max_id = max_id.max(Some(bincode::deserialize::<u64>(&key)?));
+        }
+        for relation_id in 0..self.relationship_count {
+            let relation = self.get_relation(relation_id)?;
+            if !relation.is_deleted() && relation.first_property_id != NULL_OFFSET {
+                max_id = max_id.max(Some(relation.first_property_id));
+            }
+        }
+        Ok(max_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::records::{Record, NULL_OFFSET};
+    use crate::test_utils::temp_db;
+    use crate::wal::SledTree;
+    use super::Issue;
+
+    #[test]
+    fn repair_rebuilds_broken_chains_and_mappings() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        db.add_edge("B", "C", "RELATES-TO").unwrap();
+        db.add_edge("A", "C", "INFLUENCES").unwrap();
+        assert!(db.verify().unwrap().is_ok());
+
+        // Cut the chain of "A", lose the mapping of "C" and leak a slot, as an interrupted run could
+        let mut relation = db.get_relation(2).unwrap();
+        relation.set_prev_id_for(0, NULL_OFFSET);
+        relation.write(db.mmap.as_mut().unwrap().get_relation_as_mut().unwrap(), 2).unwrap();
+        db.sled_remove(SledTree::NodeId, b"C").unwrap();
+        db.free_node_ids.push(1);
+
+        let report = db.verify().unwrap();
+        assert!(report.issues.contains(&Issue::ChainMismatch { node_id: 0, in_chain: 1, expected: 2 }));
+        assert!(report.issues.contains(&Issue::UnmappedName { node_id: 2, name: "C".to_string() }));
+        assert!(report.issues.contains(&Issue::BadFreeSlot { file: "node", id: 1 }));
+
+        let found = db.repair().unwrap();
+        assert_eq!(found.issues, report.issues);
+        assert!(db.verify().unwrap().is_ok());
+        let mut relations = db.get_all_node_relations(0).unwrap();
+        relations.sort();
+        assert_eq!(relations, vec![0, 2]);
+        assert_eq!(db.get_node_id("C").unwrap(), Some(2));
+        assert!(db.free_node_ids.is_empty());
+    }
+
+    #[test]
+    fn repair_removes_names_of_no_node() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        db.sled_insert(SledTree::Property, &bincode::serialize(&7u64).unwrap(), bincode::serialize("ghost").unwrap()).unwrap();
+        db.property_count = 8;
+
+        let report = db.verify().unwrap();
+        assert_eq!(report.issues, vec![Issue::OrphanName { property_id: 7, name: "ghost".to_string() }]);
+        db.repair().unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert!(db.get_property(7).is_err());
+        assert_eq!(db.get_node_name(1).unwrap(), "B");
+    }
+
+    #[test]
+    fn repair_lowers_counts_past_the_stored_records() {
+        let (_dir, mut db) = temp_db(None, None);
+        db.add_edge("A", "B", "IS-A").unwrap();
+        // Records lost with the end of a truncated file read back as zeroed slots
+        db.node_count += 2;
+        db.relationship_count += 1;
+
+        let report = db.verify().unwrap();
+        assert!(report.issues.contains(&Issue::CountPastRecords { file: "node", count: 4, stored: 2 }));
+        assert!(report.issues.contains(&Issue::CountPastRecords { file: "relation", count: 2, stored: 1 }));
+        assert_eq!(report.nodes_checked, 2);
+
+        db.repair().unwrap();
+        assert!(db.verify().unwrap().is_ok());
+        assert_eq!((db.node_count, db.relationship_count), (2, 1));
+        db.add_edge("B", "C", "IS-A").unwrap();
+        assert_eq!(db.get_node_id("C").unwrap(), Some(2));
+    }
+}
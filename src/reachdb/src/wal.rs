@@ -0,0 +1,203 @@
+//! Write-ahead log backing `Reachdb::transaction`
+//!
+//! Records and sled keys are not changed in place while a transaction runs: their new values are
+//! kept in the log, and reads inside the transaction see them first. On commit the new values are
+//! appended to the log file as redo entries followed by a commit marker, the log is synced once,
+//! and only then are the values written to the record files and sled trees.
+//!
+//! On `open` a leftover log is replayed when it has a commit marker and dropped when it has not,
+//! since nothing was written in place before the commit.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Range;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ReachdbError;
+use crate::records::{node::NodeRecord, relationship::RelationshipRecord, Record};
+use crate::vector::embedding_offset;
+
+/// Memory mapped file a record lives in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum RecordFile {
+    Node,
+    Relation,
+    /// Embedding slot of a node, `embedding_size` bytes long
+    Embedding,
+}
+
+impl RecordFile {
+    /// Byte range of the record `id` inside its file
+    pub(crate) fn range(&self, id: u64, embedding_size: usize) -> Range<usize> {
+        match self {
+            Self::Node => {
+                let offset = NodeRecord::id2offset(id);
+                offset..offset + NodeRecord::record_size()
+            }
+            Self::Relation => {
+                let offset = RelationshipRecord::id2offset(id);
+                offset..offset + RelationshipRecord::record_size()
+            }
+            Self::Embedding => {
+                let offset = embedding_offset(id, embedding_size);
+                offset..offset + embedding_size
+            }
+        }
+    }
+}
+
+/// Sled tree a key lives in
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum SledTree {
+    /// Node name -> node id, default tree of `reachdb.nodeid`
+    NodeId,
+    /// Alias or normalized name -> node id, named tree of `reachdb.nodeid`
+    NodeAlias,
+    /// Property id -> node name, default tree of `reachdb.property`
+    Property,
+    /// Key/value properties, named tree of `reachdb.property`
+    PropertyKv,
+    /// Token of a node name and node id, named tree of `reachdb.nodeid`
+    NodeToken,
+    /// Relation id -> provenance of the relation, named tree of `reachdb.property`
+    EdgeProvenance,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) enum WalEntry {
+    RecordRedo { file: RecordFile, id: u64, bytes: Vec<u8> },
+    SledRedo { tree: SledTree, key: Vec<u8>, value: Option<Vec<u8>> },
+    /// Metadata after the transaction, everything logged before it is durable
+    Commit { metadata: String },
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Syncs of the log made by this thread
+    pub(crate) static SYNCS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Log of the running transaction
+#[derive(Debug)]
+pub(crate) struct WriteAheadLog {
+    path: String,
+    /// Metadata before the transaction started, restored by a rollback
+    metadata: String,
+    records: BTreeMap<(RecordFile, u64), Vec<u8>>,
+    /// `None` for a removed key
+    keys: BTreeMap<(SledTree, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl WriteAheadLog {
+    /// Starts a log, nothing is written to `path` before the commit
+    pub(crate) fn begin(path: &str, metadata: String) -> Self {
+        Self {
+            path: path.to_string(),
+            metadata,
+            records: BTreeMap::new(),
+            keys: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn metadata(&self) -> &str {
+        &self.metadata
+    }
+
+    /// New bytes of a record changed by the transaction
+    pub(crate) fn record(&self, file: RecordFile, id: u64) -> Option<&[u8]> {
+        self.records.get(&(file, id)).map(Vec::as_slice)
+    }
+
+    pub(crate) fn set_record(&mut self, file: RecordFile, id: u64, bytes: Vec<u8>) {
+        self.records.insert((file, id), bytes);
+    }
+
+    /// New value of a sled key changed by the transaction, `Some(None)` when it was removed
+    pub(crate) fn key(&self, tree: SledTree, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.keys.get(&(tree, key.to_vec())).map(|value| value.as_deref())
+    }
+
+    pub(crate) fn set_key(&mut self, tree: SledTree, key: &[u8], value: Option<Vec<u8>>) {
+        self.keys.insert((tree, key.to_vec()), value);
+    }
+
+    /// Keys of `tree` starting with `prefix` changed by the transaction, in key order
+    pub(crate) fn keys_with_prefix<'a>(&'a self, tree: SledTree, prefix: &'a [u8]) -> impl Iterator<Item = (&'a [u8], Option<&'a [u8]>)> + 'a {
+        self.keys.range((tree, prefix.to_vec())..)
+            .take_while(move |((key_tree, key), _)| *key_tree == tree && key.starts_with(prefix))
+            .map(|((_, key), value)| (key.as_slice(), value.as_deref()))
+    }
+
+    /// Records changed by the transaction with their new bytes
+    pub(crate) fn records(&self) -> impl Iterator<Item = (RecordFile, u64, &[u8])> {
+        self.records.iter().map(|(&(file, id), bytes)| (file, id, bytes.as_slice()))
+    }
+
+    /// Sled keys changed by the transaction with their new values
+    pub(crate) fn keys(&self) -> impl Iterator<Item = (SledTree, &[u8], Option<&[u8]>)> {
+        self.keys.iter().map(|((tree, key), value)| (*tree, key.as_slice(), value.as_deref()))
+    }
+
+    /// Writes the redo entries and the commit marker, then syncs the log once
+    pub(crate) fn commit(&self, metadata: String) -> Result<(), ReachdbError> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        for (&(file_kind, id), bytes) in &self.records {
+            Self::append(&mut file, &WalEntry::RecordRedo { file: file_kind, id, bytes: bytes.clone() })?;
+        }
+        for ((tree, key), value) in &self.keys {
+            Self::append(&mut file, &WalEntry::SledRedo { tree: *tree, key: key.clone(), value: value.clone() })?;
+        }
+        Self::append(&mut file, &WalEntry::Commit { metadata })?;
+        file.sync_data()?;
+        #[cfg(test)]
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
+        Ok(())
+    }
+
+    /// Removes the log file once its changes are applied, if the transaction wrote one
+    pub(crate) fn finish(self) -> Result<(), ReachdbError> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Each entry is framed as `len: u32 | crc32: u32 | bincode payload`
+    fn append(file: &mut File, entry: &WalEntry) -> Result<(), ReachdbError> {
+        let payload = bincode::serialize(entry)?;
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        file.write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Reads all the complete entries of a log, a torn or corrupt tail is ignored
+    pub(crate) fn read_entries(path: &str) -> Result<Vec<WalEntry>, ReachdbError> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + 8 <= bytes.len() {
+            let len = u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            let crc = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            let payload = match bytes.get(offset + 8..offset + 8 + len) {
+                Some(payload) if crc32fast::hash(payload) == crc => payload,
+                _ => break,
+            };
+            match bincode::deserialize(payload) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => break,
+            }
+            offset += 8 + len;
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,86 @@
+use super::{
+    Parser,
+    Reachdb,
+    RelationType,
+    RsearchError,
+};
+use reachdb::{ExportFormat, ImportFormat, DEFAULT_IMPORT_BATCH_SIZE};
+
+/// Work with the knowledge graphs stored by rsearch
+#[derive(Parser, Debug)]
+pub struct Kg {
+    #[command(subcommand)]
+    pub command: KgCommands,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum KgCommands {
+    /// Export a knowledge graph to GraphML, DOT, JSON-LD or CSV
+    Export {
+        /// Output format: graphml, dot, jsonld or csv
+        #[arg(short, long)]
+        format: ExportFormat,
+
+        /// Output file, for csv `<output>_nodes.csv` and `<output>_edges.csv` are written
+        #[arg(short, long)]
+        output: String,
+
+        /// Directory of the knowledge graph
+        #[arg(long, default_value = "data/iter_test")]
+        db: String,
+    },
+
+    /// Import triples from a knowledge_graph.json, JSONL or CSV file
+    Import {
+        /// File with the triples
+        #[arg(short, long)]
+        input: String,
+
+        /// Input format: json, jsonl or csv, guessed from the extension by default
+        #[arg(short, long)]
+        format: Option<ImportFormat>,
+
+        /// Number of triples committed together
+        #[arg(long, default_value_t = DEFAULT_IMPORT_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Directory of the knowledge graph, created if needed
+        #[arg(long, default_value = "data/iter_test")]
+        db: String,
+    },
+}
+
+impl Kg {
+    pub fn run(&self) -> Result<(), RsearchError> {
+        match &self.command {
+            KgCommands::Export { format, output, db } => {
+                // Opening a missing path would create an empty graph
+                if !std::path::Path::new(db).exists() {
+                    return Err(RsearchError::IoError(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("No knowledge graph at {}", db),
+                    )));
+                }
+                let mut db = Reachdb::<RelationType>::open(db, None, None)?;
+                db.export(*format, output)?;
+                db.close()?;
+                println!("Exported the knowledge graph as {} to {}", format, output);
+                Ok(())
+            }
+            KgCommands::Import { input, format, batch_size, db } => {
+                let format = match format.or_else(|| ImportFormat::from_path(input)) {
+                    Some(format) => format,
+                    None => return Err(RsearchError::IoError(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Cannot guess the format of {}, pass --format", input),
+                    ))),
+                };
+                let mut db = Reachdb::<RelationType>::open(db, None, None)?;
+                let summary = db.import(format, input, *batch_size)?;
+                db.close()?;
+                println!("{}", summary);
+                Ok(())
+            }
+        }
+    }
+}
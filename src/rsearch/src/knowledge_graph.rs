@@ -8,9 +8,12 @@ use super::{
     RsearchError, Regex, Value, HashMap, 
     info, trace,
     append_to_json, get_markdown,
-    Reachdb, UserDefinedRelationType
+    Reachdb, UserDefinedRelationType, PropertyValue, DEFAULT_DAMPING, Direction, WalkConfig
 };
 
+/// Recent edges looked at per next query, their sources are ranked to pick the seeds
+const SEED_POOL_FACTOR: u64 = 4;
+
 // TODO: Make a struct where we already initialze the api-config during intialization
 async fn get_relevent_urls(query: &str, ftype: &str) -> Result<Vec<String>, RsearchError> {
     // Get the APIs
@@ -188,10 +191,21 @@ async fn generate_webkg<T: UserDefinedRelationType>(
                     captures[2].to_string(),
                     captures[3].to_string(),
                 ));
-                db.add_edge(&captures[1], &captures[3], &captures[2])?;
             }
         }
     }
+
+    // Add the whole batch atomically, a failure leaves none of its edges behind
+    db.transaction(|db| {
+        for (src, rel, dst) in &edges {
+            // Keep track of where the triple was extracted from
+            if let Some(relation_id) = db.add_edge(src, dst, rel)? {
+                db.set_edge_property(relation_id, "source_url", PropertyValue::from(url))?;
+                db.set_edge_property(relation_id, "query", PropertyValue::from(query))?;
+            }
+        }
+        Ok(())
+    })?;
     // println!("{edges:?}");
     let response: Value = {
         let mut map = serde_json::Map::new();
@@ -227,21 +241,36 @@ async fn get_next_query_from_kg<T: UserDefinedRelationType>(
     num_queries: i8,
 ) -> Result<Vec<String>, RsearchError> {
     // Extract the Recent Extracted Concepts & Relationships that is the recent Edges
-    trace!("Getting the Recent {} Edges", num_queries);
-    let edges = db.get_recent_edges(num_queries as u64)?;
+    trace!("Getting the Recent {} Edges", num_queries as u64 * SEED_POOL_FACTOR);
+    let edges = db.get_recent_edges(num_queries as u64 * SEED_POOL_FACTOR)?;
     let mut next_queries = Vec::new();
 
-    for rel in edges {
-        let src = rel.source_id;
-        let path = db.random_walk(src, num_depth as usize)?;
+    // Among the recent sources, seed the walks from the most central concepts
+    let ranks = db.pagerank(DEFAULT_DAMPING, 50, 1e-6)?;
+    let mut seeds: Vec<u64> = edges.iter().map(|rel| rel.source_id).collect();
+    seeds.sort_unstable();
+    seeds.dedup();
+    // A seed the ranks miss, removed in between, sorts last instead of aborting the run
+    let rank = |id: &u64| ranks.get(id).copied().unwrap_or(0.0);
+    seeds.sort_by(|a, b| rank(b).total_cmp(&rank(a)));
+    seeds.truncate(num_queries as usize);
+
+    // Undirected walks that lean away from where they came from and favour influences
+    let mut walk = WalkConfig::default().direction(Direction::Both).q(0.5);
+    if db.relation_type_id("INFLUENCES").is_some() {
+        walk = walk.type_weight("INFLUENCES", 2.0);
+    }
+
+    for src in seeds {
+        let path = db.biased_walk(src, num_depth as usize, &walk)?;
 
         // Use these relations to perform a random walk on KG for R steps
         let mut concepts = Vec::new();
         for rel_id in path {
             let rel = db.get_relation(rel_id)?;
-            let src = db.get_property(rel.source_id)?;
-            let dst = db.get_property(rel.target_id)?;
-            concepts.push((src, T::get_type_str(rel.type_id).unwrap(), dst));
+            let src = db.get_node_name(rel.source_id)?;
+            let dst = db.get_node_name(rel.target_id)?;
+            concepts.push((src, db.get_relation_type_str(rel.type_id), dst));
         }
         // Use Concepts to to generate next query
         let next_query = get_next_query_from_concept(query, &concepts).await?;
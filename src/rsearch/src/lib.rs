@@ -3,6 +3,10 @@
 mod knowledge_graph;
 mod utils;
 pub use utils::*;
+/// Relation types of the knowledge graphs built by rsearch
+pub use reachdb::BuiltinRelationType as RelationType;
+mod commands;
+pub use commands::{Kg, KgCommands};
 mod errors;
 pub use errors::RsearchError;
 
@@ -23,7 +27,8 @@ use reachapi::{
     ApiConfig, ApiKeys, RawOuts,
     ReachApiError, google_search, gemini_query
 };
-use reachdb::{Reachdb, ReachdbError, UserDefinedRelationType};
+use reachdb::{PropertyValue, Reachdb, ReachdbError, UserDefinedRelationType};
+use reachdb::algorithms::{Direction, WalkConfig, DEFAULT_DAMPING};
 
 //############################ COMMON FUNCTIONS/TRAITS/ENUMS (MAY/MAY-NOT BE EMITTED) ############################//
 